@@ -1,11 +1,23 @@
 #![allow(clippy::uninlined_format_args)]
 
-use rs_script::{execute, get_args};
+use rs_script::{execute, get_args, BuildRunError};
 use std::error::Error;
 
 pub fn main() -> Result<(), Box<dyn Error>> {
     let args = get_args();
-    execute(args)?;
+    if let Err(err) = execute(args) {
+        // Pass on the outcome of a failed script, e.g. for CI
+        if let Some(script_err) = err.downcast_ref::<BuildRunError>() {
+            if let Some(exit_code) = script_err.exit_code() {
+                // The script will have reported its own failure
+                if !matches!(script_err, BuildRunError::ScriptExit(_)) {
+                    eprintln!("{script_err}");
+                }
+                std::process::exit(exit_code);
+            }
+        }
+        return Err(err);
+    }
 
     Ok(())
 }
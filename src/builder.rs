@@ -1,22 +1,31 @@
+use crate::cache;
 use crate::code_utils::{
     self, create_next_repl_file, create_temp_source_file, extract_ast, extract_manifest,
-    process_expr, read_file_contents, rustfmt, strip_curly_braces, wrap_snippet, write_source,
+    loop_snippet, process_expr, read_file_contents, rustfmt, strip_curly_braces, wrap_snippet,
+    write_if_changed,
 };
 use crate::colors::{nu_resolve_style, MessageLevel};
+use crate::completions;
+use crate::config::CONFIG;
+use crate::deps_cache;
 use crate::errors::BuildRunError;
+use crate::events::{self, elapsed_ms, emit, Event, OutputEvents};
 use crate::log;
 use crate::logging::Verbosity;
 use crate::manifest;
+use crate::project;
 use crate::repl::run_repl;
+use crate::sandbox::{self, StderrRelay};
 #[cfg(debug_assertions)]
 use crate::shared::debug_timings;
 use crate::shared::{display_timings, Ast, BuildState};
 use crate::stdin::CrosstermEventReader;
 use crate::stdin::{edit_stdin, read_stdin};
+use crate::watch::watch;
 #[cfg(debug_assertions)]
 use crate::VERSION;
 use crate::{
-    cmd_args::{get_proc_flags, validate_args, Cli, ProcFlags},
+    cmd_args::{get_proc_flags, validate_args, Cli, Commands, DepsCommand, ProcFlags},
     ScriptState,
 };
 use crate::{
@@ -24,27 +33,73 @@ use crate::{
     RS_SUFFIX, TEMP_SCRIPT_NAME, TMPDIR,
 };
 
-use cargo_toml::Manifest;
+use cargo_toml::{Dependency, Manifest};
 #[cfg(debug_assertions)]
 use env_logger::{Builder, Env, WriteStyle};
 use lazy_static::lazy_static;
 #[cfg(debug_assertions)]
 use log::{log_enabled, Level::Debug};
 use regex::Regex;
+#[cfg(unix)]
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    iterator::{exfiltrator::WithOrigin, SignalsInfo},
+    low_level::emulate_default_handler,
+};
+#[cfg(unix)]
+use std::sync::{
+    atomic::{AtomicI32, Ordering},
+    Once,
+};
 use std::{
+    collections::BTreeMap,
     error::Error,
-    fs::{self, OpenOptions},
-    io::Write,
+    fs,
+    io::{BufRead, BufReader, Write},
     path::{Path, PathBuf},
-    process::Command,
-    time::Instant,
+    process::{Child, Command, ExitStatus},
+    thread,
+    time::{Duration, Instant},
 };
 
+/// How often to check whether a running script has finished, timed out or been signalled.
+const RUN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// How long a script asked to stop with SIGTERM has to clean up before it is killed.
+#[cfg(unix)]
+const STOP_GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+/// The process id of the script being waited on, or 0 while no script is running.
+#[cfg(unix)]
+static SCRIPT_PID: AtomicI32 = AtomicI32::new(0);
+/// The last SIGINT or SIGTERM received while the current script was running, or 0 if none.
+#[cfg(unix)]
+static RECEIVED_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
 #[allow(clippy::too_many_lines)]
 pub fn execute(mut args: Cli) -> Result<(), Box<dyn Error>> {
     let start = Instant::now();
     #[cfg(debug_assertions)]
     configure_log();
+    CONFIG.apply_to_cli(&mut args);
+    match args.command {
+        Some(Commands::Clean { ref script, all }) => {
+            return Ok(cache::clean(script.as_deref(), all)?);
+        }
+        Some(Commands::List) => return Ok(cache::list()?),
+        Some(Commands::Cache { ref command }) => return Ok(cache::run_command(command)?),
+        Some(Commands::Deps {
+            command: DepsCommand::Cache { ref command },
+        }) => return Ok(deps_cache::run_command(command)?),
+        Some(Commands::Completions { ref shell }) => return Ok(completions::completions(shell)?),
+        Some(Commands::Import {
+            ref project,
+            ref to,
+        }) => return Ok(project::import(project, to.as_deref(), args.force)?),
+        Some(Commands::Convert { ref script, to }) => {
+            return Ok(project::convert(Path::new(script), to)?)
+        }
+        _ => args.apply_command()?,
+    }
     let proc_flags = get_proc_flags(&args)?;
     #[cfg(debug_assertions)]
     if log_enabled!(Debug) {
@@ -74,7 +129,8 @@ pub fn execute(mut args: Cli) -> Result<(), Box<dyn Error>> {
     let is_expr = proc_flags.contains(ProcFlags::EXPR);
     let is_stdin = proc_flags.contains(ProcFlags::STDIN);
     let is_edit = proc_flags.contains(ProcFlags::EDIT);
-    let is_dynamic = is_expr | is_stdin | is_edit;
+    let is_loop = proc_flags.contains(ProcFlags::LOOP);
+    let is_dynamic = is_expr | is_loop | is_stdin | is_edit;
     if is_dynamic {
         create_temp_source_file();
     }
@@ -137,6 +193,9 @@ pub fn execute(mut args: Cli) -> Result<(), Box<dyn Error>> {
     if is_repl {
         debug_log!("build_state.source_path={:?}", build_state.source_path);
     }
+    if let Some(Commands::Export { ref to, .. }) = args.command {
+        return project::export(&args, &proc_flags, &mut build_state, to);
+    }
     if is_repl {
         run_repl(&mut args, &proc_flags, &mut build_state, start)
     } else if is_dynamic {
@@ -147,6 +206,18 @@ pub fn execute(mut args: Cli) -> Result<(), Box<dyn Error>> {
                 )));
             };
             rs_source
+        } else if is_loop {
+            let Some(ref loop_expr) = args.loop_expr else {
+                return Err(Box::new(BuildRunError::Command(
+                    "Missing expression for --loop option".to_string(),
+                )));
+            };
+            loop_snippet(
+                loop_expr,
+                args.print,
+                args.begin.as_deref(),
+                args.end.as_deref(),
+            )
         } else if is_edit {
             debug_log!("About to call edit_stdin()");
             let event_reader = CrosstermEventReader;
@@ -185,6 +256,8 @@ pub fn execute(mut args: Cli) -> Result<(), Box<dyn Error>> {
                 "Error parsing code".to_string(),
             )))
         }
+    } else if proc_flags.contains(ProcFlags::WATCH) {
+        watch(&mut args, &proc_flags, &mut build_state)
     } else {
         gen_build_run(
             &mut args,
@@ -227,113 +300,7 @@ pub fn gen_build_run(
     let options = &options;
 
     if build_state.must_gen {
-        let source_path: &Path = &build_state.source_path;
-        let start_parsing_rs = Instant::now();
-        let mut rs_source = read_file_contents(source_path)?;
-
-        // Strip off any shebang: it may have got us here but we don't need it
-        // in the gen_build_run process.
-        rs_source = if rs_source.starts_with("#!") {
-            let split_once = rs_source.split_once('\n');
-            let (shebang, rust_code) = split_once.expect("Failed to strip shebang");
-            debug_log!("Successfully stripped shebang {shebang}");
-            rust_code.to_string()
-        } else {
-            rs_source
-        };
-        let rs_manifest: Manifest = {
-            // debug_timings(&start_parsing_rs, "Parsed source");
-            extract_manifest(&rs_source, start_parsing_rs)
-        }?;
-        // debug_log!("rs_manifest={rs_manifest:#?}");
-        // debug_log!("rs_source={rs_source}");
-        if build_state.rs_manifest.is_none() {
-            build_state.rs_manifest = Some(rs_manifest);
-        }
-        // let mut rs_source = read_file_contents(&build_state.source_path)?;
-        let syntax_tree: Option<Ast> = if syntax_tree.is_none() {
-            code_utils::to_ast(&rs_source)
-        } else {
-            syntax_tree
-        };
-
-        debug_log!("syntax_tree={syntax_tree:#?}");
-
-        if build_state.rs_manifest.is_some() {
-            build_state.cargo_manifest = Some(manifest::merge_manifest(
-                build_state,
-                &rs_source,
-                &syntax_tree,
-            )?);
-        }
-
-        lazy_static! {
-            static ref RE: Regex = Regex::new(r"(?m)^\s*(async\s+)?fn\s+main\s*\(\s*\)").unwrap();
-        }
-        let main_methods = match syntax_tree {
-            Some(ref ast) => code_utils::count_main_methods(ast),
-            None => RE.find_iter(&rs_source).count(),
-        };
-        let has_main = match main_methods {
-            0 => false,
-            1 => true,
-            _ => {
-                if options.multimain {
-                    true
-                } else {
-                    writeln!(
-                    &mut std::io::stderr(),
-                    "{main_methods} main methods found, only one allowed by default. Specify --multimain option to allow more"
-                )
-                .unwrap();
-                    std::process::exit(1);
-                }
-            }
-        };
-
-        // println!("build_state={build_state:#?}");
-        rs_source = if has_main {
-            // Strip off any enclosing braces we may have added
-            if rs_source.starts_with('{') {
-                strip_curly_braces(&rs_source).unwrap_or(rs_source)
-            } else {
-                rs_source
-            }
-        } else {
-            // let start_quote = Instant::now();
-            let rust_code = if let Some(ref syntax_tree_ref) = syntax_tree {
-                let returns_unit = match syntax_tree_ref {
-                    Ast::Expr(expr) => code_utils::is_last_stmt_unit(expr),
-                    Ast::File(file) => {
-                        let expr = code_utils::extract_expr_from_file(file)
-                            .expect("Error extracting syn::Expr from syn::File");
-                        code_utils::returns_unit(&expr)
-                    }
-                };
-                if returns_unit {
-                    debug_log!("Option B: returns unit type");
-                    quote::quote!(
-                        #syntax_tree_ref
-                    )
-                    .to_string()
-                } else {
-                    debug_log!("Option A: returns a substantive type");
-                    quote::quote!(
-                        println!("{:#?}", #syntax_tree_ref);
-                    )
-                    .to_string()
-                }
-            } else {
-                // demo/fizz_buzz.rs broke this: not an expression but still a valid snippet.
-                // format!(r#"println!("Expression returned {{}}", {rs_source});"#)
-                // debug_log!("dbg!(rs_source)={}", dbg!(rs_source.clone()));
-                // dbg!(rs_source)
-                rs_source
-            };
-            // display_timings(&start_quote, "Completed quote", proc_flags);
-            wrap_snippet(&rust_code.to_string())
-        };
-        generate(build_state, &rs_source, proc_flags)?;
+        gen_step(options, proc_flags, build_state, syntax_tree)?;
     } else {
         log!(
             Verbosity::Normal,
@@ -344,9 +311,21 @@ pub fn gen_build_run(
         );
         // build_state.cargo_manifest = Some(default_manifest(build_state)?);
         build_state.cargo_manifest = None; // Don't need it in memory, build will find it on disk
+        emit(&Event::Generate {
+            target_dir_path: build_state.target_dir_path.clone(),
+            cargo_toml_path: build_state.cargo_toml_path.clone(),
+            skipped: true,
+            elapsed_ms: 0,
+        });
     }
-    if build_state.must_build {
+    if proc_flags.contains(ProcFlags::TEST) {
+        // cargo test does its own building
+        test(proc_flags, &options.args, build_state)?;
+    } else if build_state.must_build {
         build(proc_flags, build_state)?;
+        if !proc_flags.intersects(ProcFlags::CHECK | ProcFlags::CLIPPY) {
+            build_state.save_fingerprint(&options.build_options())?;
+        }
     } else {
         log!(
             Verbosity::Normal,
@@ -355,6 +334,14 @@ pub fn gen_build_run(
                 // .bold()
                 .paint("Skipping unnecessary cargo build step. Use --force (-f) to override.")
         );
+        emit(&Event::Build {
+            command: "build".to_string(),
+            target_path: build_state.target_path.clone(),
+            skipped: true,
+            success: true,
+            diagnostics: vec![],
+            elapsed_ms: 0,
+        });
     }
     if proc_flags.contains(ProcFlags::RUN) {
         run(proc_flags, &options.args, build_state)?;
@@ -367,6 +354,193 @@ pub fn gen_build_run(
     Ok(())
 }
 
+/// Write any dependencies missing from the toml block of the script back into it, leaving
+/// the rest of the script as it was.
+/// # Errors
+///
+/// Will return `Err` if the script can't be read or written, or its toml block is invalid.
+fn update_toml(
+    source_path: &Path,
+    dependencies: &BTreeMap<String, Dependency>,
+) -> Result<(), BuildRunError> {
+    let rs_full_source = read_file_contents(source_path)?;
+    let (updated, added) = code_utils::update_toml_block(&rs_full_source, dependencies)?;
+    if added.is_empty() {
+        log!(
+            Verbosity::Normal,
+            "The toml block of {} already lists all its dependencies",
+            source_path.display()
+        );
+        return Ok(());
+    }
+    fs::write(source_path, updated)?;
+    log!(
+        Verbosity::Normal,
+        "Added {} to the toml block of {}",
+        added.join(", "),
+        source_path.display()
+    );
+    Ok(())
+}
+
+/// The generation step of `gen_build_run`: parse the script, infer and merge its
+/// dependencies into the manifest, wrap it in a `main` method if necessary, and write
+/// out the source and `Cargo.toml` of the generated project.
+/// # Errors
+///
+/// Will return `Err` if the script can't be read or parsed, or the project can't be written.
+pub fn gen_step(
+    options: &Cli,
+    proc_flags: &ProcFlags,
+    build_state: &mut BuildState,
+    syntax_tree: Option<Ast>,
+) -> Result<(), Box<dyn Error>> {
+    let source_path: &Path = &build_state.source_path;
+    let start_parsing_rs = Instant::now();
+    let mut rs_source = read_file_contents(source_path)?;
+
+    // Strip off any shebang: it may have got us here but we don't need it
+    // in the gen_build_run process. `#![` starts an inner attribute, not a shebang.
+    rs_source = if rs_source.starts_with("#!") && !rs_source.starts_with("#![") {
+        let split_once = rs_source.split_once('\n');
+        let (shebang, rust_code) = split_once.expect("Failed to strip shebang");
+        debug_log!("Successfully stripped shebang {shebang}");
+        rust_code.to_string()
+    } else {
+        rs_source
+    };
+    // Cargo-script front matter is not valid Rust on stable
+    rs_source = code_utils::comment_out_frontmatter(&rs_source);
+    let rs_manifest: Manifest = {
+        // debug_timings(&start_parsing_rs, "Parsed source");
+        extract_manifest(&rs_source, start_parsing_rs)
+    }?;
+    // debug_log!("rs_manifest={rs_manifest:#?}");
+    // debug_log!("rs_source={rs_source}");
+    if build_state.rs_manifest.is_none() {
+        build_state.rs_manifest = Some(rs_manifest);
+    }
+    // let mut rs_source = read_file_contents(&build_state.source_path)?;
+    let mut syntax_tree: Option<Ast> = if syntax_tree.is_none() {
+        code_utils::to_ast(&rs_source)
+    } else {
+        syntax_tree
+    };
+
+    debug_log!("syntax_tree={syntax_tree:#?}");
+    emit(&Event::Parse {
+        source_path: build_state.source_path.clone(),
+        parsed: syntax_tree.is_some(),
+        elapsed_ms: elapsed_ms(&start_parsing_rs),
+    });
+
+    // Options on the command line take precedence over those in the toml block
+    let script_options = code_utils::extract_build_options(&rs_source)?;
+    build_state.build_options = build_state
+        .build_options
+        .clone()
+        .with_defaults(script_options);
+    if code_utils::uses_unstable_features(syntax_tree.as_ref(), &rs_source) {
+        build_state.build_options = build_state.build_options.clone().for_unstable_features()?;
+    }
+
+    if build_state.rs_manifest.is_some() {
+        build_state.cargo_manifest = Some(manifest::merge_manifest(
+            build_state,
+            &rs_source,
+            &syntax_tree,
+        )?);
+    }
+
+    if proc_flags.contains(ProcFlags::UPDATE_TOML) {
+        if let Some(ref cargo_manifest) = build_state.cargo_manifest {
+            update_toml(&build_state.source_path, &cargo_manifest.dependencies)?;
+        }
+    }
+
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"(?m)^\s*(async\s+)?fn\s+main\s*\(\s*\)").unwrap();
+    }
+    let main_methods = match syntax_tree {
+        Some(ref ast) => code_utils::count_main_methods(ast),
+        None => RE.find_iter(&rs_source).count(),
+    };
+    let has_main = match main_methods {
+        0 => false,
+        1 => true,
+        _ => {
+            if options.multimain {
+                true
+            } else {
+                writeln!(
+                &mut std::io::stderr(),
+                "{main_methods} main methods found, only one allowed by default. Specify --multimain option to allow more"
+            )
+            .unwrap();
+                std::process::exit(1);
+            }
+        }
+    };
+
+    // println!("build_state={build_state:#?}");
+    rs_source = if has_main {
+        // Strip off any enclosing braces we may have added
+        if rs_source.starts_with('{') {
+            strip_curly_braces(&rs_source).unwrap_or(rs_source)
+        } else {
+            rs_source
+        }
+    } else {
+        // let start_quote = Instant::now();
+        let hoisted_items = match syntax_tree {
+            Some(ref mut ast) if proc_flags.contains(ProcFlags::TEST) => {
+                code_utils::hoist_items(ast)
+            }
+            _ => vec![],
+        };
+        let rust_code = if let Some(ref syntax_tree_ref) = syntax_tree {
+            let returns_unit = match syntax_tree_ref {
+                Ast::Expr(expr) => code_utils::is_last_stmt_unit(expr),
+                Ast::File(file) => {
+                    let expr = code_utils::extract_expr_from_file(file)
+                        .expect("Error extracting syn::Expr from syn::File");
+                    code_utils::returns_unit(&expr)
+                }
+            };
+            if returns_unit {
+                debug_log!("Option B: returns unit type");
+                // Unlike a block or loop, a macro call needs a semicolon to be a statement
+                if matches!(syntax_tree_ref, Ast::Expr(syn::Expr::Macro(_))) {
+                    quote::quote!(
+                        #syntax_tree_ref;
+                    )
+                } else {
+                    quote::quote!(
+                        #syntax_tree_ref
+                    )
+                }
+                .to_string()
+            } else {
+                debug_log!("Option A: returns a substantive type");
+                quote::quote!(
+                    println!("{:#?}", #syntax_tree_ref);
+                )
+                .to_string()
+            }
+        } else {
+            // demo/fizz_buzz.rs broke this: not an expression but still a valid snippet.
+            // format!(r#"println!("Expression returned {{}}", {rs_source});"#)
+            // debug_log!("dbg!(rs_source)={}", dbg!(rs_source.clone()));
+            // dbg!(rs_source)
+            rs_source
+        };
+        // display_timings(&start_quote, "Completed quote", proc_flags);
+        wrap_snippet(&rust_code.to_string()) + &quote::quote!(#(#hoisted_items)*).to_string()
+    };
+    generate(build_state, &rs_source, proc_flags)?;
+    Ok(())
+}
+
 /// # Errors
 ///
 /// Will return `Err` if there is an error creating the directory path, writing to the
@@ -399,16 +573,9 @@ pub fn generate(
         "GGGGGGGG Creating source file: {target_rs_path:?}"
     );
 
-    write_source(&target_rs_path, rs_source)?;
-    rustfmt(build_state)?;
+    // Leave unchanged files alone, so as not to invalidate cargo's own fingerprints
+    write_if_changed(&target_rs_path, &rustfmt(build_state, rs_source)?)?;
 
-    // debug_log!("cargo_toml_path will be {:?}", &build_state.cargo_toml_path);
-    if !Path::try_exists(&build_state.cargo_toml_path)? {
-        OpenOptions::new()
-            .write(true)
-            .create_new(true)
-            .open(&build_state.cargo_toml_path)?;
-    }
     // debug_log!("cargo_toml: {cargo_toml:?}");
 
     let manifest = &build_state
@@ -422,17 +589,25 @@ pub fn generate(
         code_utils::disentangle(cargo_manifest_str)
     );
 
-    let mut toml_file = fs::File::create(&build_state.cargo_toml_path)?;
-    toml_file.write_all(cargo_manifest_str.as_bytes())?;
+    write_if_changed(&build_state.cargo_toml_path, cargo_manifest_str)?;
     // debug_log!("cargo_toml_path={:?}", &build_state.cargo_toml_path);
     // debug_log!("##### Cargo.toml generation succeeded!");
 
+    cache::record_source_path(build_state)?;
+
     display_timings(&start_gen, "Completed generation", proc_flags);
+    emit(&Event::Generate {
+        target_dir_path: build_state.target_dir_path.clone(),
+        cargo_toml_path: build_state.cargo_toml_path.clone(),
+        skipped: false,
+        elapsed_ms: elapsed_ms(&start_gen),
+    });
 
     Ok(())
 }
 
-/// Build the Rust program using Cargo (with manifest path)
+/// Build the Rust program using Cargo (with manifest path), or just check or lint it
+/// in check or clippy mode.
 /// # Panics
 ///
 /// Will panic if the cargo build process fails to spawn.
@@ -440,34 +615,39 @@ pub fn build(proc_flags: &ProcFlags, build_state: &BuildState) -> Result<(), Bui
     let start_build = Instant::now();
     // let verbose = proc_flags.contains(ProcFlags::VERBOSE);
     let quiet = proc_flags.contains(ProcFlags::QUIET);
+    let (cargo_command, action) = if proc_flags.contains(ProcFlags::CHECK) {
+        ("check", "Checking")
+    } else if proc_flags.contains(ProcFlags::CLIPPY) {
+        ("clippy", "Linting")
+    } else {
+        ("build", "Building")
+    };
 
     debug_log!("BBBBBBBB In build");
 
-    let Ok(cargo_toml_path_str) = code_utils::path_to_str(&build_state.cargo_toml_path) else {
-        return Err(BuildRunError::OsString(
-            build_state.cargo_toml_path.clone().into_os_string(),
-        ));
-    };
+    let json = events::json_enabled();
     let mut build_command = Command::new("cargo");
     // Rustc writes to std
-    let mut args = vec!["build", "--manifest-path", &cargo_toml_path_str];
-    // if verbose {
-    //     args.push("--verbose");
-    // };
-    if quiet {
-        args.push("--quiet");
+    let mut args = cargo_args(cargo_command, build_state, quiet)?;
+    if json {
+        // Collect the diagnostics for the build event rather than rendering them
+        args.push("--message-format=json".to_string());
     }
-
     build_command.args(&args); // .current_dir(build_dir);
 
     // Show sign of life in case build takes a while
     log!(
         Verbosity::Normal,
-        "Building {} ...",
+        "{action} {} ...",
         nu_resolve_style(MessageLevel::Emphasis).paint(&build_state.source_name)
     );
 
-    if quiet {
+    if json {
+        build_command
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::inherit());
+    } else if quiet && cargo_command == "build" {
+        // The diagnostics are the whole point of checking or linting
         // Pipe output: TODO: debug
         build_command
             .stdout(std::process::Stdio::piped())
@@ -482,24 +662,214 @@ pub fn build(proc_flags: &ProcFlags, build_state: &BuildState) -> Result<(), Bui
     // Execute the command and handle the result
     let output = build_command
         .spawn()
-        .expect("failed to spawn cargo build process");
+        .unwrap_or_else(|_| panic!("failed to spawn cargo {cargo_command} process"));
 
     // Wait for the process to finish
     let exit_status = output
         .wait_with_output()
-        .expect("failed to wait on cargo build");
+        .unwrap_or_else(|_| panic!("failed to wait on cargo {cargo_command}"));
+
+    emit(&Event::Build {
+        command: cargo_command.to_string(),
+        target_path: build_state.target_path.clone(),
+        skipped: false,
+        success: exit_status.status.success(),
+        diagnostics: events::compiler_messages(&String::from_utf8_lossy(&exit_status.stdout)),
+        elapsed_ms: elapsed_ms(&start_build),
+    });
 
     if exit_status.status.success() {
-        debug_log!("Build succeeded");
-    } else {
+        debug_log!("cargo {cargo_command} succeeded");
+        if cargo_command == "build" {
+            if let Some(built) = build_state.shared_target_binary() {
+                install_binary(&built, &build_state.target_path)?;
+            }
+        }
+    } else if cargo_command == "build" {
         return Err(BuildRunError::Command(String::from("Build failed")));
+    } else {
+        return Err(BuildRunError::Command(format!(
+            "cargo {cargo_command} failed"
+        )));
     };
 
-    display_timings(&start_build, "Completed build", proc_flags);
+    display_timings(
+        &start_build,
+        &format!("Completed {cargo_command}"),
+        proc_flags,
+    );
 
     Ok(())
 }
 
+/// Copy an executable built in the shared target directory to the script's own `target_path`.
+/// The copy is renamed into place, so that a concurrent run never sees a partial executable.
+/// # Errors
+///
+/// Will return `Err` if the executable can't be copied.
+pub fn install_binary(built: &Path, target_path: &Path) -> Result<(), BuildRunError> {
+    if let Some(parent) = target_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut temp_name = target_path.as_os_str().to_os_string();
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    let temp_path = PathBuf::from(temp_name);
+    fs::copy(built, &temp_path)?;
+    if let Err(err) = fs::rename(&temp_path, target_path) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(err.into());
+    }
+    debug_log!("Copied {built:?} to {target_path:?}");
+    Ok(())
+}
+
+/// The arguments to run a cargo command such as `build` or `test` against the generated
+/// manifest, with the profile, toolchain and features chosen for the build.
+fn cargo_args(
+    cargo_command: &str,
+    build_state: &BuildState,
+    quiet: bool,
+) -> Result<Vec<String>, BuildRunError> {
+    let Ok(cargo_toml_path_str) = code_utils::path_to_str(&build_state.cargo_toml_path) else {
+        return Err(BuildRunError::OsString(
+            build_state.cargo_toml_path.clone().into_os_string(),
+        ));
+    };
+    let build_options = &build_state.build_options;
+    let mut args = vec![];
+    // A rustup toolchain override must come first
+    if let Some(ref toolchain) = build_options.toolchain {
+        args.push(format!("+{toolchain}"));
+    }
+    args.extend([
+        cargo_command.to_string(),
+        "--manifest-path".to_string(),
+        cargo_toml_path_str,
+    ]);
+    // if verbose {
+    //     args.push("--verbose");
+    // };
+    if let Some(ref shared_target_dir) = build_state.shared_target_dir {
+        // Cargo locks the target directory, so concurrent builds wait their turn
+        args.push("--target-dir".to_string());
+        args.push(shared_target_dir.to_string_lossy().to_string());
+    }
+    if let Some(ref profile) = build_state.cargo_profile {
+        args.push("--profile".to_string());
+        args.push(profile.clone());
+    }
+    if !build_options.features.is_empty() {
+        args.push("--features".to_string());
+        args.push(build_options.features.join(","));
+    }
+    if build_options.no_default_features {
+        args.push("--no-default-features".to_string());
+    }
+    if build_state.offline {
+        args.push("--offline".to_string());
+    }
+    if quiet {
+        args.push("--quiet".to_string());
+    }
+    Ok(args)
+}
+
+/// Run the `#[test]` functions of the script with cargo test, passing on any test name
+/// filter and test harness options such as `--nocapture`, and report each test's outcome.
+/// # Errors
+///
+/// Will return `Err` if cargo test can't be run or any test fails.
+pub fn test(
+    proc_flags: &ProcFlags,
+    args: &[String],
+    build_state: &BuildState,
+) -> Result<(), BuildRunError> {
+    let start_test = Instant::now();
+    let quiet = proc_flags.contains(ProcFlags::QUIET);
+
+    debug_log!("TTTTTTTT In test");
+
+    let json = events::json_enabled();
+    let mut test_command = Command::new("cargo");
+    test_command.args(cargo_args("test", build_state, quiet)?);
+    if json {
+        // Collect the diagnostics for the build event rather than rendering them
+        test_command.arg("--message-format=json");
+    }
+    if !args.is_empty() {
+        test_command.arg("--").args(args);
+    }
+    debug_log!("Test command is {test_command:?}");
+
+    log!(
+        Verbosity::Normal,
+        "Testing {} ...",
+        nu_resolve_style(MessageLevel::Emphasis).paint(&build_state.source_name)
+    );
+
+    // Compiler messages go to stderr, or in JSON mode to stdout, and test harness output to stdout
+    let mut child = test_command
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::inherit())
+        .spawn()?;
+    let mut diagnostics = vec![];
+    if let Some(stdout) = child.stdout.take() {
+        for line in BufReader::new(stdout).lines() {
+            let line = line?;
+            if json {
+                // Keep the event stream all JSON
+                match events::cargo_message(&line) {
+                    Some(message) => diagnostics.extend(events::compiler_message(message)),
+                    None => emit(&Event::TestOutput { line }),
+                }
+            } else {
+                report_test_line(&line);
+            }
+        }
+    }
+    let exit_status = child.wait()?;
+    emit(&Event::Build {
+        command: "test".to_string(),
+        target_path: build_state.target_path.clone(),
+        skipped: false,
+        success: exit_status.success(),
+        diagnostics,
+        elapsed_ms: elapsed_ms(&start_test),
+    });
+
+    display_timings(&start_test, "Completed test", proc_flags);
+
+    if exit_status.success() {
+        Ok(())
+    } else {
+        Err(BuildRunError::Command(String::from("Test failed")))
+    }
+}
+
+/// Display a line of test harness output, styling the outcome of each test and the summary.
+fn report_test_line(line: &str) {
+    lazy_static! {
+        static ref TEST_OUTCOME_RE: Regex =
+            Regex::new(r"^test (.+) \.\.\. (ok|FAILED|ignored.*)$").unwrap();
+    }
+    let level = if let Some(caps) = TEST_OUTCOME_RE.captures(line) {
+        match &caps[2] {
+            "ok" => MessageLevel::Emphasis,
+            "FAILED" => MessageLevel::Error,
+            _ => MessageLevel::Ghost,
+        }
+    } else if line.starts_with("test result: ok") {
+        MessageLevel::Emphasis
+    } else if line.starts_with("test result: FAILED") || line == "failures:" {
+        MessageLevel::Error
+    } else {
+        // Anything else, such as captured output and panic messages, goes through as is
+        log!(Verbosity::Quiet, "{line}");
+        return;
+    };
+    log!(Verbosity::Quiet, "{}", nu_resolve_style(level).paint(line));
+}
+
 /// Run the built program
 /// # Errors
 ///
@@ -532,7 +902,23 @@ pub fn run(
         nu_ansi_term::Color::Yellow.paint(dash_line.clone())
     );
 
-    let _exit_status = run_command.spawn()?.wait()?;
+    if build_state.sandbox.is_some() {
+        // Relayed to tell whether an abort was down to the memory limit
+        run_command.stderr(std::process::Stdio::piped());
+    }
+    OutputEvents::pipe(&mut run_command);
+    let mut child = sandbox::spawn(&mut run_command, build_state.sandbox.as_ref())?;
+    let stderr_relay = sandbox::StderrRelay::start(&mut child);
+    let output_events = OutputEvents::start(&mut child);
+    let exit_status =
+        wait_for_script(&mut child, build_state.timeout, || false).and_then(|exit_status| {
+            exit_status.ok_or_else(|| BuildRunError::NoneOption("Script was stopped".to_string()))
+        });
+    // Let the relays finish before closing the script's output
+    let stderr_tail = stderr_relay.map(StderrRelay::tail).unwrap_or_default();
+    if let Some(output_events) = output_events {
+        output_events.finish();
+    }
 
     // println!("{}", nu_ansi_term::Color::Yellow.paint(dash_line.clone()));
     log!(
@@ -544,6 +930,167 @@ pub fn run(
     // debug_log!("Exit status={exit_status:#?}");
 
     display_timings(&start_run, "Completed run", proc_flags);
+    let status = exit_status.as_ref().ok();
+    emit(&Event::Run {
+        target_path,
+        success: status.is_some_and(ExitStatus::success),
+        exit_status: status.and_then(ExitStatus::code),
+        signal: status.and_then(exit_signal),
+        timed_out: matches!(exit_status, Err(BuildRunError::Timeout(_))),
+        elapsed_ms: elapsed_ms(&start_run),
+    });
 
-    Ok(())
+    let exit_status = exit_status?;
+    if exit_status.success() {
+        Ok(())
+    } else if let Some(signal) = exit_signal(&exit_status) {
+        let violation = build_state
+            .sandbox
+            .as_ref()
+            .and_then(|sandbox| sandbox.violation(signal, &stderr_tail));
+        Err(violation.map_or(
+            BuildRunError::ScriptSignal(signal),
+            BuildRunError::SandboxViolation,
+        ))
+    } else {
+        Err(BuildRunError::ScriptExit(exit_status.code().unwrap_or(1)))
+    }
+}
+
+/// Wait for the script to finish, killing it if it runs for longer than the timeout, and
+/// stopping it with `stop_script` as soon as `stop` returns true, in which case the result
+/// is `None`. SIGINT and SIGTERM sent to rs-script while it waits are handled by
+/// `forward_signals`.
+/// # Errors
+///
+/// Will return `Err` if the script times out or can't be waited on.
+pub fn wait_for_script(
+    child: &mut Child,
+    timeout: Option<Duration>,
+    mut stop: impl FnMut() -> bool,
+) -> Result<Option<ExitStatus>, BuildRunError> {
+    let start = Instant::now();
+    #[cfg(unix)]
+    let _running = {
+        forward_signals()?;
+        let pid = libc::pid_t::try_from(child.id()).map_err(|_| {
+            BuildRunError::Command(format!("Process id {} is out of range", child.id()))
+        })?;
+        RECEIVED_SIGNAL.store(0, Ordering::SeqCst);
+        SCRIPT_PID.store(pid, Ordering::SeqCst);
+        scopeguard::guard((), |()| SCRIPT_PID.store(0, Ordering::SeqCst))
+    };
+    loop {
+        if let Some(exit_status) = child.try_wait()? {
+            return Ok(Some(exit_status));
+        }
+        if stop() {
+            stop_script(child)?;
+            return Ok(None);
+        }
+        if let Some(timeout) = timeout {
+            if start.elapsed() >= timeout {
+                debug_log!("Killing process {} after {timeout:?}", child.id());
+                // It may have finished in the meantime
+                let _ = child.kill();
+                child.wait()?;
+                return Err(BuildRunError::Timeout(timeout));
+            }
+        }
+        thread::sleep(RUN_POLL_INTERVAL);
+    }
+}
+
+/// Stop the script, giving it the chance to clean up: on Unix it is sent SIGTERM, and only
+/// killed if it hasn't finished within the grace period.
+/// # Errors
+///
+/// Will return `Err` if the script can't be waited on.
+fn stop_script(child: &mut Child) -> Result<ExitStatus, BuildRunError> {
+    #[cfg(unix)]
+    {
+        if let Ok(pid) = libc::pid_t::try_from(child.id()) {
+            debug_log!("Terminating process {pid}");
+            // SAFETY: kill has no memory safety requirements
+            unsafe { libc::kill(pid, SIGTERM) };
+            let start = Instant::now();
+            while start.elapsed() < STOP_GRACE_PERIOD {
+                if let Some(exit_status) = child.try_wait()? {
+                    return Ok(exit_status);
+                }
+                thread::sleep(RUN_POLL_INTERVAL);
+            }
+        }
+    }
+    debug_log!("Killing process {}", child.id());
+    // It may have finished in the meantime
+    let _ = child.kill();
+    Ok(child.wait()?)
+}
+
+/// Handle SIGINT and SIGTERM for the rest of the process, since signal-hook never restores
+/// the default handling once a handler is registered. While a script is being waited on,
+/// those sent by another process are passed on to the script, which would otherwise be
+/// orphaned. Those from the terminal, such as Ctrl-C, already reach the script as a member
+/// of the same process group, so are not sent again. With no script running, the signal
+/// has its default effect of terminating rs-script.
+/// # Errors
+///
+/// Will return `Err` if the handler can't be registered.
+#[cfg(unix)]
+fn forward_signals() -> Result<(), BuildRunError> {
+    static REGISTER: Once = Once::new();
+    let mut result = Ok(());
+    REGISTER.call_once(|| {
+        result = SignalsInfo::<WithOrigin>::new([SIGINT, SIGTERM])
+            .map(|mut signals| {
+                thread::spawn(move || {
+                    for origin in signals.forever() {
+                        let pid = SCRIPT_PID.load(Ordering::SeqCst);
+                        if pid == 0 {
+                            let _ = emulate_default_handler(origin.signal);
+                            continue;
+                        }
+                        RECEIVED_SIGNAL.store(origin.signal, Ordering::SeqCst);
+                        if origin.process.is_some() {
+                            debug_log!("Forwarding signal {} to the script", origin.signal);
+                            // SAFETY: kill has no memory safety requirements
+                            unsafe { libc::kill(pid, origin.signal) };
+                        }
+                    }
+                });
+            })
+            .map_err(BuildRunError::from);
+    });
+    result
+}
+
+/// The SIGINT or SIGTERM, if any, that rs-script received while the last script waited on
+/// was running.
+#[must_use]
+pub fn received_signal() -> Option<i32> {
+    #[cfg(unix)]
+    return Some(RECEIVED_SIGNAL.load(Ordering::SeqCst)).filter(|&signal| signal != 0);
+    #[cfg(not(unix))]
+    None
+}
+
+/// Give any signal that rs-script received while the last script was running its default
+/// effect of terminating rs-script, once the script is out of the way.
+pub fn reraise_received_signal() {
+    #[cfg(unix)]
+    {
+        if let Some(signal) = received_signal() {
+            let _ = emulate_default_handler(signal);
+        }
+    }
+}
+
+/// The signal that terminated the script, if any.
+#[allow(clippy::unnecessary_wraps, unused_variables)]
+fn exit_signal(exit_status: &ExitStatus) -> Option<i32> {
+    #[cfg(unix)]
+    return std::os::unix::process::ExitStatusExt::signal(exit_status);
+    #[cfg(not(unix))]
+    None
 }
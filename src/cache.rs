@@ -0,0 +1,745 @@
+use crate::cmd_args::CacheCommand;
+use crate::code_utils::write_if_changed;
+use crate::colors::{nu_resolve_style, MessageLevel};
+use crate::debug_log;
+use crate::errors::BuildRunError;
+use crate::log;
+use crate::logging::Verbosity;
+use crate::shared::{canonical_script_path, script_target_dir, shared_bin_name, BuildState};
+use crate::{
+    DYNAMIC_SUBDIR, PACKAGE_NAME, REPL_SUBDIR, RS_SUFFIX, SHARED_TARGET_SUBDIR, TEMP_SCRIPT_NAME,
+    TMPDIR,
+};
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use strum::Display;
+
+/// File in a generated project that records the path of the script it was generated from.
+pub const SOURCE_PATH_FILE: &str = ".source_path";
+/// Human-readable index of the generated projects of named scripts and their scripts.
+pub const INDEX_FILE: &str = "index.toml";
+
+/// The kind of input that a generated project was generated from, or the shared target
+/// directory used with `--shared-target`.
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq)]
+#[strum(serialize_all = "lowercase")]
+pub enum CacheKind {
+    Script,
+    Repl,
+    Expression,
+    #[strum(serialize = "shared")]
+    SharedTarget,
+}
+
+/// A generated project in the cache, with what is known about its source and build.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CacheEntry {
+    pub name: String,
+    pub kind: CacheKind,
+    pub dir: PathBuf,
+    /// The script the project was generated from, if recorded.
+    pub source_path: Option<PathBuf>,
+    /// The executables built for each profile, with their sizes and modification times.
+    pub binaries: Vec<(PathBuf, u64, SystemTime)>,
+    /// Total size of the project directory, including the cargo target directory.
+    pub dir_size: u64,
+    pub modified: SystemTime,
+    /// The project's files in the shared target directory, with their sizes: its executables,
+    /// their dependency info and incremental compilation data.
+    pub shared_artifacts: Vec<(PathBuf, u64)>,
+}
+
+impl CacheEntry {
+    /// Gather the details of the generated project in the given directory.
+    /// # Errors
+    ///
+    /// Will return `Err` if the directory can't be read.
+    pub fn read(dir: &Path, kind: CacheKind) -> io::Result<Self> {
+        let name = dir
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let source_path = fs::read_to_string(dir.join(SOURCE_PATH_FILE))
+            .ok()
+            .map(|path| PathBuf::from(path.trim_end()));
+        let binary_name = binary_name(&name, kind, source_path.as_deref());
+        let mut binaries = vec![];
+        for project_dir in [dir.to_path_buf(), dir.join(TEMP_SCRIPT_NAME)] {
+            for profile_dir in subdirs(&project_dir.join("target"))? {
+                let binary = profile_dir.join(&binary_name);
+                if let Ok(metadata) = fs::metadata(&binary) {
+                    if metadata.is_file() {
+                        binaries.push((binary, metadata.len(), metadata.modified()?));
+                    }
+                }
+            }
+        }
+        Ok(CacheEntry {
+            name,
+            kind,
+            dir: dir.to_path_buf(),
+            source_path,
+            binaries,
+            dir_size: dir_size(dir)?,
+            modified: fs::metadata(dir)?.modified()?,
+            shared_artifacts: vec![],
+        })
+    }
+
+    /// Gather the details of the shared target directory. It counts as used whenever an
+    /// executable is built in it.
+    /// # Errors
+    ///
+    /// Will return `Err` if the directory can't be read.
+    pub fn read_shared_target(dir: &Path) -> io::Result<Self> {
+        let mut modified = fs::metadata(dir)?.modified()?;
+        for profile_dir in subdirs(dir)? {
+            modified = modified.max(fs::metadata(profile_dir)?.modified()?);
+        }
+        Ok(CacheEntry {
+            name: SHARED_TARGET_SUBDIR.to_string(),
+            kind: CacheKind::SharedTarget,
+            dir: dir.to_path_buf(),
+            source_path: None,
+            binaries: vec![],
+            dir_size: dir_size(dir)?,
+            modified,
+            shared_artifacts: vec![],
+        })
+    }
+
+    /// Name of the project's binary target when built in the shared target directory.
+    #[must_use]
+    pub fn shared_bin_name(&self) -> String {
+        // A REPL session's project is in a subdirectory of the session directory
+        let target_dir_path = match self.kind {
+            CacheKind::Repl => self.dir.join(TEMP_SCRIPT_NAME),
+            _ => self.dir.clone(),
+        };
+        shared_bin_name(
+            &binary_name(&self.name, self.kind, self.source_path.as_deref()),
+            &target_dir_path,
+        )
+    }
+
+    /// Total size of the project's files in the shared target directory.
+    #[must_use]
+    pub fn shared_size(&self) -> u64 {
+        self.shared_artifacts.iter().map(|(_, size)| size).sum()
+    }
+
+    /// When the script was last built, if it has been.
+    #[must_use]
+    pub fn last_build(&self) -> Option<SystemTime> {
+        self.binaries.iter().map(|(_, _, modified)| *modified).max()
+    }
+
+    /// When the project was last built or failing that generated, for pruning by age.
+    #[must_use]
+    pub fn last_used(&self) -> SystemTime {
+        self.last_build()
+            .map_or(self.modified, |built| built.max(self.modified))
+    }
+
+    /// Size of the most recently built executable, if any.
+    #[must_use]
+    pub fn binary_size(&self) -> Option<u64> {
+        self.binaries
+            .iter()
+            .max_by_key(|(_, _, modified)| *modified)
+            .map(|(_, size, _)| *size)
+    }
+
+    /// Whether the project was generated from a script that no longer exists.
+    #[must_use]
+    pub fn is_orphan(&self) -> bool {
+        self.source_path.as_ref().is_some_and(|path| !path.exists())
+    }
+}
+
+/// Name of the executable built for a project: scripts' projects are named after the script
+/// and its path, and expressions are all built as the same temporary script.
+fn binary_name(name: &str, kind: CacheKind, source_path: Option<&Path>) -> String {
+    match (kind, source_path) {
+        (CacheKind::Script, Some(source_path)) => source_path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+        (CacheKind::Expression, _) => TEMP_SCRIPT_NAME.trim_end_matches(RS_SUFFIX).to_string(),
+        _ => name.to_string(),
+    }
+}
+
+/// What to prune from the cache with `cache gc`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GcCriteria {
+    /// Prune projects not built or generated for longer than this.
+    pub older_than: Option<Duration>,
+    /// Prune the least recently used projects until the cache is no bigger than this in bytes.
+    pub max_size: Option<u64>,
+    /// Prune projects whose script no longer exists.
+    pub orphans: bool,
+}
+
+/// Record the path of the script in its generated project, so that the cache commands
+/// can show it and spot orphaned projects.
+/// # Errors
+///
+/// Will return `Err` if the file can't be written.
+pub fn record_source_path(build_state: &BuildState) -> Result<(), BuildRunError> {
+    let source_path = build_state.source_path.to_string_lossy();
+    let recorded = write_if_changed(
+        &build_state.target_dir_path.join(SOURCE_PATH_FILE),
+        &source_path,
+    )?;
+    if recorded
+        && build_state
+            .target_dir_path
+            .starts_with(TMPDIR.join(PACKAGE_NAME))
+    {
+        write_index()?;
+    }
+    Ok(())
+}
+
+/// Rewrite the index of the generated projects of named scripts, which maps each project
+/// directory to the path of its script, for the benefit of anyone browsing the cache.
+/// # Errors
+///
+/// Will return `Err` if the cache directory can't be read or the index can't be written.
+pub fn write_index() -> Result<(), BuildRunError> {
+    let scripts_dir = TMPDIR.join(PACKAGE_NAME);
+    if !scripts_dir.is_dir() {
+        return Ok(());
+    }
+    let mut index =
+        String::from("# Generated projects of scripts run by rs-script, by directory\n");
+    for dir in cached_script_dirs()? {
+        let name = dir.file_name().unwrap_or_default().to_string_lossy();
+        if let Ok(source_path) = fs::read_to_string(dir.join(SOURCE_PATH_FILE)) {
+            index.push_str(&toml::to_string(&BTreeMap::from([(name, source_path)]))?);
+        }
+    }
+    write_if_changed(&scripts_dir.join(INDEX_FILE), &index)?;
+    Ok(())
+}
+
+/// Move the generated project of a script from its directory in the layout used before
+/// directories were keyed on the script's path, `<stem>` rather than `<stem>-<hash>`,
+/// unless it is known to belong to another script of the same name.
+/// # Errors
+///
+/// Will return `Err` if the project can't be moved.
+pub fn migrate_legacy_dir(source_path: &Path, target_dir: &Path) -> Result<(), BuildRunError> {
+    let Some(stem) = source_path.file_stem() else {
+        return Ok(());
+    };
+    let legacy_dir = TMPDIR.join(PACKAGE_NAME).join(stem);
+    if target_dir.exists() || !legacy_dir.is_dir() {
+        return Ok(());
+    }
+    let recorded = fs::read_to_string(legacy_dir.join(SOURCE_PATH_FILE)).ok();
+    if recorded.is_some_and(|recorded| Path::new(&recorded) != source_path) {
+        return Ok(());
+    }
+    fs::rename(&legacy_dir, target_dir)?;
+    log!(
+        Verbosity::Verbose,
+        "Moved generated project {} to {}",
+        legacy_dir.display(),
+        target_dir.display()
+    );
+    Ok(())
+}
+
+/// Carry out a `cache` subcommand.
+/// # Errors
+///
+/// Will return `Err` if the subcommand fails.
+pub fn run_command(command: &CacheCommand) -> Result<(), BuildRunError> {
+    match command {
+        CacheCommand::List => list(),
+        CacheCommand::Show { script } => show(script),
+        CacheCommand::Clean { script, all } => clean(script.as_deref(), *all),
+        CacheCommand::Gc {
+            older_than,
+            max_size,
+            orphans,
+            dry_run,
+        } => gc(
+            &GcCriteria {
+                older_than: *older_than,
+                max_size: *max_size,
+                orphans: *orphans,
+            },
+            *dry_run,
+        ),
+    }
+}
+
+/// Delete the generated project of the named script, or if `all` is specified, the generated
+/// projects of all scripts, REPL sessions and expressions and the shared target directory.
+/// # Errors
+///
+/// Will return `Err` if the script name is invalid or a directory can't be removed.
+pub fn clean(script: Option<&str>, all: bool) -> Result<(), BuildRunError> {
+    let dirs = if all {
+        vec![
+            TMPDIR.join(PACKAGE_NAME),
+            TMPDIR.join(REPL_SUBDIR),
+            TMPDIR.join(DYNAMIC_SUBDIR),
+            TMPDIR.join(SHARED_TARGET_SUBDIR),
+        ]
+    } else {
+        let script =
+            script.ok_or_else(|| BuildRunError::NoneOption("Missing script name".to_string()))?;
+        let dir = script_project_dir(script)?;
+        if dir.is_dir() {
+            let mut entries = [CacheEntry::read(&dir, CacheKind::Script)?];
+            find_shared_artifacts(&TMPDIR.join(SHARED_TARGET_SUBDIR), &mut entries)?;
+            remove_shared_artifacts(&entries[0], false)?;
+        }
+        vec![dir]
+    };
+
+    for dir in dirs {
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+            log!(Verbosity::Normal, "Deleted {}", dir.display());
+        } else {
+            log!(Verbosity::Normal, "Nothing to delete at {}", dir.display());
+        }
+    }
+    write_index()
+}
+
+/// List the generated projects of the scripts, REPL sessions and expressions run so far,
+/// with their sources, last build times and sizes.
+/// # Errors
+///
+/// Will return `Err` if the cache directories can't be read.
+pub fn list() -> Result<(), BuildRunError> {
+    let entries = cache_entries()?;
+    if entries.is_empty() {
+        log!(
+            Verbosity::Quiet,
+            "No generated projects found in {}",
+            TMPDIR.display()
+        );
+        return Ok(());
+    }
+    let now = SystemTime::now();
+    for entry in &entries {
+        let source = match (&entry.source_path, entry.is_orphan()) {
+            (Some(path), true) => format!("{} (missing)", path.display()),
+            (Some(path), false) => path.display().to_string(),
+            (None, _) => entry.dir.display().to_string(),
+        };
+        let last_build = if entry.kind == CacheKind::SharedTarget {
+            Some(entry.modified)
+        } else {
+            entry.last_build()
+        };
+        log!(
+            Verbosity::Quiet,
+            "{} {:<10} {:>10} {:>10} {:>10}  {source}",
+            nu_resolve_style(MessageLevel::Emphasis).paint(format!("{:<24}", entry.name)),
+            entry.kind.to_string(),
+            last_build.map_or_else(|| "not built".to_string(), |time| format_age(now, time)),
+            entry.binary_size().map_or_else(String::new, format_size),
+            format_size(entry.dir_size)
+        );
+    }
+    // The projects' files in the shared target directory are counted in its size
+    let projects = entries
+        .iter()
+        .filter(|entry| entry.kind != CacheKind::SharedTarget)
+        .count();
+    let total = entries.iter().map(|entry| entry.dir_size).sum();
+    log!(
+        Verbosity::Normal,
+        "{projects} generated project(s) using {} in {}",
+        format_size(total),
+        TMPDIR.display()
+    );
+    Ok(())
+}
+
+/// Show the details of the generated project of the named script.
+/// # Errors
+///
+/// Will return `Err` if the script name is invalid or the script has no generated project.
+pub fn show(script: &str) -> Result<(), BuildRunError> {
+    let dir = script_project_dir(script)?;
+    if !dir.is_dir() {
+        return Err(BuildRunError::Command(format!(
+            "No generated project for {script} in {}",
+            dir.display()
+        )));
+    }
+    let mut entries = [CacheEntry::read(&dir, CacheKind::Script)?];
+    find_shared_artifacts(&TMPDIR.join(SHARED_TARGET_SUBDIR), &mut entries)?;
+    let [entry] = entries;
+    let now = SystemTime::now();
+    let source = entry.source_path.as_ref().map_or_else(
+        || "unknown".to_string(),
+        |path| {
+            let missing = if entry.is_orphan() { " (missing)" } else { "" };
+            format!("{}{missing}", path.display())
+        },
+    );
+    log!(Verbosity::Quiet, "Script:        {source}");
+    log!(Verbosity::Quiet, "Project:       {}", entry.dir.display());
+    log!(
+        Verbosity::Quiet,
+        "Project size:  {}",
+        format_size(entry.dir_size)
+    );
+    if !entry.shared_artifacts.is_empty() {
+        log!(
+            Verbosity::Quiet,
+            "Shared target: {}",
+            format_size(entry.shared_size())
+        );
+    }
+    if entry.binaries.is_empty() {
+        log!(Verbosity::Quiet, "Executables:   none built");
+    }
+    for (binary, size, modified) in &entry.binaries {
+        log!(
+            Verbosity::Quiet,
+            "Executable:    {} ({}, built {})",
+            binary.display(),
+            format_size(*size),
+            format_age(now, *modified)
+        );
+    }
+    Ok(())
+}
+
+/// Delete the generated projects that meet any of the criteria, least recently used first.
+/// # Errors
+///
+/// Will return `Err` if the cache directories can't be read or a project can't be deleted.
+pub fn gc(criteria: &GcCriteria, dry_run: bool) -> Result<(), BuildRunError> {
+    let entries = cache_entries()?;
+    let victims = gc_victims(&entries, criteria, SystemTime::now());
+    let shared_target_pruned = victims
+        .iter()
+        .any(|entry| entry.kind == CacheKind::SharedTarget);
+    let mut freed = 0;
+    for entry in &victims {
+        if dry_run {
+            log!(Verbosity::Normal, "Would delete {}", entry.dir.display());
+        } else {
+            fs::remove_dir_all(&entry.dir)?;
+            log!(Verbosity::Normal, "Deleted {}", entry.dir.display());
+        }
+        freed += entry.dir_size;
+        // Its files in the shared target directory go too, unless that goes as a whole
+        if !shared_target_pruned {
+            remove_shared_artifacts(entry, dry_run)?;
+            freed += entry.shared_size();
+        }
+    }
+    if !dry_run {
+        write_index()?;
+    }
+    log!(
+        Verbosity::Quiet,
+        "{} {} generated project(s), freeing {}",
+        if dry_run { "Would delete" } else { "Deleted" },
+        victims.len(),
+        format_size(freed)
+    );
+    Ok(())
+}
+
+/// The entries to prune to meet the criteria: orphans and entries older than the age limit,
+/// then the least recently used of the rest until their total size is within the budget.
+/// Pruning a project also deletes its files from the shared target directory, which is
+/// itself pruned like a project.
+#[must_use]
+pub fn gc_victims<'a>(
+    entries: &'a [CacheEntry],
+    criteria: &GcCriteria,
+    now: SystemTime,
+) -> Vec<&'a CacheEntry> {
+    let too_old = |entry: &CacheEntry| {
+        criteria.older_than.is_some_and(|age| {
+            now.duration_since(entry.last_used())
+                .is_ok_and(|unused| unused > age)
+        })
+    };
+    let (mut victims, mut keep): (Vec<_>, Vec<_>) = entries
+        .iter()
+        .partition(|entry| (criteria.orphans && entry.is_orphan()) || too_old(entry));
+    if let Some(max_size) = criteria.max_size {
+        keep.sort_by_key(|entry| entry.last_used());
+        // Sizes of the shared target directory less the files of the projects pruned so far
+        let mut shared_size = keep
+            .iter()
+            .find(|entry| entry.kind == CacheKind::SharedTarget)
+            .map(|entry| entry.dir_size);
+        let mut total: u64 = keep
+            .iter()
+            .filter(|entry| entry.kind != CacheKind::SharedTarget)
+            .map(|entry| entry.dir_size)
+            .sum();
+        let prune_shared_files = |entry: &CacheEntry, shared_size: &mut Option<u64>| {
+            if let Some(size) = shared_size {
+                *size = size.saturating_sub(entry.shared_size());
+            }
+        };
+        for entry in &victims {
+            prune_shared_files(entry, &mut shared_size);
+        }
+        for entry in keep {
+            if total + shared_size.unwrap_or(0) <= max_size {
+                break;
+            }
+            if entry.kind == CacheKind::SharedTarget {
+                shared_size = None;
+            } else {
+                total -= entry.dir_size;
+                prune_shared_files(entry, &mut shared_size);
+            }
+            victims.push(entry);
+        }
+    }
+    victims
+}
+
+/// All generated projects: those of named scripts, REPL sessions and expressions, with their
+/// files in the shared target directory, followed by the shared target directory if any.
+/// # Errors
+///
+/// Will return `Err` if a cache directory exists but can't be read.
+pub fn cache_entries() -> io::Result<Vec<CacheEntry>> {
+    let mut entries = vec![];
+    for dir in cached_script_dirs()? {
+        entries.push(CacheEntry::read(&dir, CacheKind::Script)?);
+    }
+    for dir in subdirs(&TMPDIR.join(REPL_SUBDIR))? {
+        entries.push(CacheEntry::read(&dir, CacheKind::Repl)?);
+    }
+    let dynamic_dir = TMPDIR.join(DYNAMIC_SUBDIR);
+    if dynamic_dir.is_dir() {
+        entries.push(CacheEntry::read(&dynamic_dir, CacheKind::Expression)?);
+    }
+    let shared_target_dir = TMPDIR.join(SHARED_TARGET_SUBDIR);
+    if shared_target_dir.is_dir() {
+        find_shared_artifacts(&shared_target_dir, &mut entries)?;
+        entries.push(CacheEntry::read_shared_target(&shared_target_dir)?);
+    }
+    Ok(entries)
+}
+
+/// Attribute the files in the shared target directory to the projects they were built for,
+/// by the hash-qualified names of their binary targets: the executables and their dependency
+/// info in each profile directory, their copies and incremental compilation data in its
+/// `deps` and `incremental` subdirectories, and the `.fingerprint` directories that share
+/// their copies' metadata hashes. Dependencies are shared, so stay unattributed.
+/// # Errors
+///
+/// Will return `Err` if the shared target directory exists but can't be read.
+pub fn find_shared_artifacts(shared_dir: &Path, entries: &mut [CacheEntry]) -> io::Result<()> {
+    let mut files = vec![];
+    for profile_dir in subdirs(shared_dir)? {
+        for (dir, kind) in [
+            (profile_dir.clone(), ArtifactDir::Profile),
+            (profile_dir.join("deps"), ArtifactDir::Crate),
+            (profile_dir.join("incremental"), ArtifactDir::Crate),
+            (profile_dir.join(".fingerprint"), ArtifactDir::Fingerprint),
+        ] {
+            if !dir.is_dir() {
+                continue;
+            }
+            for file in fs::read_dir(&dir)? {
+                let path = file?.path();
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                files.push((name.into_owned(), kind, path));
+            }
+        }
+    }
+    for entry in entries {
+        let bin_name = entry.shared_bin_name();
+        // Rustc names a binary crate after the target, with underscores for hyphens
+        let crate_prefix = format!("{}-", bin_name.replace('-', "_"));
+        let bin_prefix = format!("{bin_name}.");
+        // Cargo's fingerprints are named after the package but share the metadata hash
+        let fingerprint_suffixes: Vec<String> = files
+            .iter()
+            .filter(|(name, kind, _)| *kind == ArtifactDir::Crate && !name.contains('.'))
+            .filter_map(|(name, _, _)| name.strip_prefix(&crate_prefix))
+            .map(|hash| format!("-{hash}"))
+            .collect();
+        entry.shared_artifacts.clear();
+        for (name, kind, path) in &files {
+            let matched = match kind {
+                ArtifactDir::Profile => *name == bin_name || name.starts_with(&bin_prefix),
+                ArtifactDir::Crate => name.starts_with(&crate_prefix),
+                ArtifactDir::Fingerprint => fingerprint_suffixes
+                    .iter()
+                    .any(|suffix| name.ends_with(suffix)),
+            };
+            if matched {
+                let metadata = fs::symlink_metadata(path)?;
+                let size = if metadata.is_dir() {
+                    dir_size(path)?
+                } else {
+                    metadata.len()
+                };
+                entry.shared_artifacts.push((path.clone(), size));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The directories of a profile in the shared target directory that hold a project's files,
+/// which are named differently in each.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ArtifactDir {
+    Profile,
+    Crate,
+    Fingerprint,
+}
+
+/// Delete a project's files from the shared target directory.
+/// # Errors
+///
+/// Will return `Err` if a file can't be deleted.
+fn remove_shared_artifacts(entry: &CacheEntry, dry_run: bool) -> io::Result<()> {
+    for (path, _) in &entry.shared_artifacts {
+        if dry_run {
+            debug_log!("Would delete {}", path.display());
+        } else if path.is_dir() {
+            fs::remove_dir_all(path)?;
+        } else {
+            fs::remove_file(path)?;
+        }
+    }
+    if !entry.shared_artifacts.is_empty() {
+        log!(
+            Verbosity::Verbose,
+            "{} the files of {} in the shared target directory",
+            if dry_run { "Would delete" } else { "Deleted" },
+            entry.name
+        );
+    }
+    Ok(())
+}
+
+/// Total size of the files in a directory and its subdirectories, not following symlinks.
+fn dir_size(dir: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else if file_type.is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+    Ok(size)
+}
+
+/// A size in bytes in human-readable binary units, e.g. `4.3 MiB`.
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64;
+    let mut unit = "B";
+    for next_unit in UNITS {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next_unit;
+    }
+    format!("{size:.1} {unit}")
+}
+
+/// How long ago a time was, in the largest whole unit, e.g. `3h ago`.
+#[must_use]
+pub fn format_age(now: SystemTime, time: SystemTime) -> String {
+    let secs = now.duration_since(time).unwrap_or_default().as_secs();
+    match secs {
+        0..=59 => format!("{secs}s ago"),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86_399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86_400),
+    }
+}
+
+/// The generated project directories of named scripts, sorted by name.
+/// # Errors
+///
+/// Will return `Err` if the cache directory exists but can't be read.
+pub fn cached_script_dirs() -> io::Result<Vec<PathBuf>> {
+    subdirs(&TMPDIR.join(PACKAGE_NAME))
+}
+
+/// The file stems of the named scripts that have generated projects, sorted and without
+/// duplicates, for offering as completions.
+/// # Errors
+///
+/// Will return `Err` if the cache directory exists but can't be read.
+pub fn cached_script_stems() -> io::Result<Vec<String>> {
+    let mut stems = cached_script_dirs()?
+        .into_iter()
+        .filter_map(|dir| match fs::read_to_string(dir.join(SOURCE_PATH_FILE)) {
+            Ok(source_path) => Path::new(&source_path)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned()),
+            // A project from before script paths were recorded is named after the script
+            Err(_) => dir
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned()),
+        })
+        .collect::<Vec<_>>();
+    stems.sort();
+    stems.dedup();
+    Ok(stems)
+}
+
+/// The immediate subdirectories of a directory, sorted by name. A missing directory has none.
+fn subdirs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        debug_log!("No directory {dir:?}");
+        return Ok(vec![]);
+    }
+    let mut dirs = fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect::<Vec<_>>();
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// The generated project directory of a named script, which must end in `.rs`, moving it
+/// from where it used to be kept if need be.
+fn script_project_dir(script: &str) -> Result<PathBuf, BuildRunError> {
+    if !script.ends_with(RS_SUFFIX) {
+        return Err(BuildRunError::Command(format!(
+            "Script name must end in {RS_SUFFIX}"
+        )));
+    }
+    let source_path = canonical_script_path(Path::new(script));
+    let dir = script_target_dir(&source_path);
+    migrate_legacy_dir(&source_path, &dir)?;
+    Ok(dir)
+}
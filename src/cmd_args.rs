@@ -1,29 +1,41 @@
+use crate::code_utils::ManifestStyle;
+use crate::completions::{complete_if_requested, complete_script, COMPLETION_SHELLS};
 use crate::errors::BuildRunError;
 use crate::log;
-use crate::logging::{self, Verbosity};
+use crate::logging::{self, MessageFormat, Verbosity};
+use crate::shared::BuildOptions;
 use crate::RS_SUFFIX;
 
 use bitflags::bitflags;
-use clap::Parser;
+use clap::{ArgGroup, Parser, Subcommand};
+use clap_complete::engine::{ArgValueCompleter, PathCompleter};
 use core::{fmt, str};
 use std::error::Error;
+use std::path::PathBuf;
+use std::time::Duration;
 
 /// rs-script script runner and REPL
 #[allow(clippy::struct_excessive_bools)]
 #[derive(Clone, Default, Parser, Debug)]
 #[command(name = "rs_script")]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Commands>,
     /// Optional name of a script to run
+    #[arg(add = ArgValueCompleter::new(complete_script))]
     pub script: Option<String>,
     /// Set the arguments for the script
     #[arg(last = true)]
     pub args: Vec<String>,
     /// Set verbose mode
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub verbose: bool,
     /// Display timings
-    #[arg(short, long)]
+    #[arg(short, long, global = true, overrides_with = "no_timings")]
     pub timings: bool,
+    /// Don't display timings, even if the configuration file turns them on
+    #[arg(long, global = true, overrides_with = "timings")]
+    pub no_timings: bool,
     /// Generate Rust source and individual cargo .toml if compiled file is stale
     #[arg(short = 'g', long = "gen")]
     pub generate: bool,
@@ -31,8 +43,11 @@ pub struct Cli {
     #[arg(short, long)]
     pub build: bool,
     /// Force generation of Rust source and individual Cargo.toml, and build, even if compiled file is not stale
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub force: bool,
+    /// Write the dependencies inferred from the script into its toml block
+    #[arg(long, global = true)]
+    pub update_toml: bool,
     ///  (Default) Carry out generation and build steps (if necessary or forced) and run the compiled script
     #[arg(short, long, default_value = "true")]
     pub all: bool,
@@ -48,6 +63,18 @@ pub struct Cli {
     /// Evaluate a quoted expression on the fly
     #[arg(short, long = "expr", conflicts_with_all(["all", "generate", "build", "run", "repl", "script", "stdin"]))]
     pub expression: Option<String>,
+    /// Evaluate a quoted expression for each line of stdin, with `line` and `line_no` bound
+    #[arg(short = 'L', long = "loop", value_name = "EXPR", conflicts_with_all(["generate", "build", "run", "repl", "script", "expression", "stdin", "edit"]))]
+    pub loop_expr: Option<String>,
+    /// Print the result of each evaluation of the --loop expression
+    #[arg(short, long)]
+    pub print: bool,
+    /// Code to run once before the first line in --loop mode, e.g. to declare variables
+    #[arg(long, value_name = "CODE")]
+    pub begin: Option<String>,
+    /// Code to run once after the last line in --loop mode, e.g. to print a summary
+    #[arg(long, value_name = "CODE")]
+    pub end: Option<String>,
     /// Read script from stdin.
     #[arg(short, long, conflicts_with_all(["all", "expression", "generate", "build", "run", "repl", "script"]))]
     pub stdin: bool,
@@ -55,19 +82,421 @@ pub struct Cli {
     #[arg(short = 'd', long, conflicts_with_all(["all", "expression", "generate", "build", "run", "repl", "script"]))]
     pub edit: bool,
     /// Suppress unnecessary output
-    #[arg(short, long, conflicts_with("verbose"))]
+    #[arg(short, long, global = true, conflicts_with("verbose"))]
     pub quiet: bool,
     /// Allow multiple main methods
-    #[arg(short, long)]
+    #[arg(short, long, global = true, overrides_with = "no_multimain")]
     pub multimain: bool,
+    /// Don't allow multiple main methods, even if the configuration file allows them
+    #[arg(long, global = true, overrides_with = "multimain")]
+    pub no_multimain: bool,
+    /// Check the script with cargo check instead of building and running it
+    #[arg(long, conflicts_with_all(["norun", "repl", "run", "clippy"]))]
+    pub check: bool,
+    /// Lint the script with cargo clippy instead of building and running it
+    #[arg(long, conflicts_with_all(["norun", "repl", "run"]))]
+    pub clippy: bool,
+    /// Watch the script and rerun it whenever it or a module file it declares changes
+    #[arg(short, long, conflicts_with_all(["repl", "expression", "loop_expr", "stdin", "edit"]))]
+    pub watch: bool,
+    /// Run the script's #[test] functions with cargo test. Arguments after -- go to the test harness.
+    #[arg(long, conflicts_with_all(["norun", "repl", "run", "check", "clippy"]))]
+    pub test: bool,
+    /// Build in release mode (same as --profile release)
+    #[arg(long, global = true, conflicts_with("profile"))]
+    pub release: bool,
+    /// Build with the named cargo profile, e.g. a custom profile from the toml block
+    #[arg(long, global = true, value_name = "NAME")]
+    pub profile: Option<String>,
+    /// Build with the named rustup toolchain, e.g. nightly. May also be given as +<toolchain> first.
+    #[arg(long, global = true, value_name = "TOOLCHAIN")]
+    pub toolchain: Option<String>,
+    /// Comma-separated list of features of the script's package to activate
+    #[arg(short = 'F', long, global = true, value_delimiter = ',')]
+    pub features: Vec<String>,
+    /// Don't activate the default feature of the script's package
+    #[arg(long, global = true)]
+    pub no_default_features: bool,
+    /// Resolve inferred dependencies from the local registry only and build without network access
+    #[arg(long, global = true)]
+    pub offline: bool,
+    /// Kill the script if it runs for longer than this, e.g. 30s, 500ms, 2m or 1h
+    #[arg(long, global = true, value_name = "DURATION", value_parser = parse_duration)]
+    pub timeout: Option<Duration>,
+    /// Build in a cargo target directory shared by all scripts, so that dependencies compile only once
+    #[arg(long, global = true, overrides_with = "no_shared_target")]
+    pub shared_target: bool,
+    /// Build in the script's own target directory, even if the configuration file turns on --shared-target
+    #[arg(long, global = true, overrides_with = "shared_target")]
+    pub no_shared_target: bool,
+    /// Run the script with resource limits, no network and no filesystem writes outside a scratch directory (Linux only)
+    #[arg(long, global = true)]
+    pub sandbox: bool,
+    /// Allow network access in the --sandbox
+    #[arg(long, global = true, requires = "sandbox")]
+    pub allow_net: bool,
+    /// Write messages as coloured text, or as newline-delimited JSON events for tools
+    #[arg(long, global = true, value_enum, value_name = "FORMAT", default_value_t = MessageFormat::Human)]
+    pub message_format: MessageFormat,
+}
+
+/// Subcommands, as alternatives to the equivalent mode options above.
+#[derive(Clone, Debug, Subcommand)]
+pub enum Commands {
+    /// Generate and build a script if stale, then run it (the default with a script name)
+    Run {
+        /// Name of the script to run
+        #[arg(add = ArgValueCompleter::new(complete_script))]
+        script: String,
+        /// Set the arguments for the script
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Generate and build a script if stale, without running it
+    Build {
+        /// Name of the script to build
+        #[arg(add = ArgValueCompleter::new(complete_script))]
+        script: String,
+    },
+    /// Check that a script compiles with cargo check, without building or running it
+    Check {
+        /// Name of the script to check
+        #[arg(add = ArgValueCompleter::new(complete_script))]
+        script: String,
+    },
+    /// Lint a script with cargo clippy, without building or running it
+    Clippy {
+        /// Name of the script to lint
+        #[arg(add = ArgValueCompleter::new(complete_script))]
+        script: String,
+    },
+    /// Run the #[test] functions of a script with cargo test
+    Test {
+        /// Name of the script to test
+        #[arg(add = ArgValueCompleter::new(complete_script))]
+        script: String,
+        /// Test name filter and test harness options such as --nocapture
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Evaluate a quoted expression on the fly
+    Expr {
+        /// The expression to evaluate
+        expression: String,
+    },
+    /// REPL mode (read–eval–print loop) for Rust expressions
+    Repl {
+        /// Optional existing REPL script name
+        #[arg(add = ArgValueCompleter::new(complete_script))]
+        script: Option<String>,
+    },
+    /// Export a script as a standalone Cargo project
+    Export {
+        /// Name of the script to export
+        #[arg(add = ArgValueCompleter::new(complete_script))]
+        script: String,
+        /// Directory for the new project
+        #[arg(long, value_name = "DIR")]
+        to: PathBuf,
+    },
+    /// Import a Cargo project as a single-file script
+    Import {
+        /// Directory of the Cargo project
+        #[arg(add = ArgValueCompleter::new(PathCompleter::dir()))]
+        project: PathBuf,
+        /// Name of the script to write, by default <package name>.rs
+        #[arg(long, value_name = "SCRIPT")]
+        to: Option<PathBuf>,
+    },
+    /// Rewrite the embedded manifest of a script in another style
+    Convert {
+        /// Name of the script to convert
+        #[arg(add = ArgValueCompleter::new(complete_script))]
+        script: String,
+        /// Style to write the manifest in
+        #[arg(long, value_enum, value_name = "STYLE")]
+        to: ManifestStyle,
+    },
+    /// Delete the generated project of a script, or of all scripts
+    Clean {
+        /// Name of the script whose generated project is to be deleted
+        #[arg(required_unless_present = "all", add = ArgValueCompleter::new(complete_script))]
+        script: Option<String>,
+        /// Delete all generated projects, REPL sessions and expressions
+        #[arg(long, conflicts_with = "script")]
+        all: bool,
+    },
+    /// List the generated projects of scripts run so far
+    List,
+    /// List, inspect and prune the generated projects of scripts, REPL sessions and expressions
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    /// Manage the versions found for dependencies inferred from scripts
+    Deps {
+        #[command(subcommand)]
+        command: DepsCommand,
+    },
+    /// Print a shell completion script, e.g. `source <(rs_script completions bash)`
+    Completions {
+        /// The shell to generate completions for
+        #[arg(value_parser = COMPLETION_SHELLS)]
+        shell: String,
+    },
+}
+
+/// Subcommands of the `cache` subcommand.
+#[derive(Clone, Debug, Subcommand)]
+pub enum CacheCommand {
+    /// List the generated projects with their source, last build time, executable size and total size
+    List,
+    /// Show the details of the generated project of a script
+    Show {
+        /// Name of the script
+        #[arg(add = ArgValueCompleter::new(complete_script))]
+        script: String,
+    },
+    /// Delete the generated project of a script, or of all scripts
+    Clean {
+        /// Name of the script whose generated project is to be deleted
+        #[arg(required_unless_present = "all", add = ArgValueCompleter::new(complete_script))]
+        script: Option<String>,
+        /// Delete all generated projects, REPL sessions and expressions
+        #[arg(long, conflicts_with = "script")]
+        all: bool,
+    },
+    /// Delete generated projects that are unused, orphaned or over a total size budget
+    #[command(group(ArgGroup::new("criteria").required(true).multiple(true)))]
+    Gc {
+        /// Delete projects not built for longer than this, e.g. 30d or 12h
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration, group = "criteria")]
+        older_than: Option<Duration>,
+        /// Delete the least recently built projects until the cache fits, e.g. 2G or 500M
+        #[arg(long, value_name = "SIZE", value_parser = parse_size, group = "criteria")]
+        max_size: Option<u64>,
+        /// Delete projects whose script no longer exists
+        #[arg(long, group = "criteria")]
+        orphans: bool,
+        /// Show what would be deleted without deleting it
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+/// Subcommands of the `deps` subcommand.
+#[derive(Clone, Debug, Subcommand)]
+pub enum DepsCommand {
+    /// List, refresh, pin or forget the cached versions of inferred dependencies
+    Cache {
+        #[command(subcommand)]
+        command: DepsCacheCommand,
+    },
+}
+
+/// Subcommands of the `deps cache` subcommand.
+#[derive(Clone, Debug, Subcommand)]
+pub enum DepsCacheCommand {
+    /// List the cached crates with their versions, when they were looked up and whether they are pinned
+    List,
+    /// Look up the latest versions of the named crates, or of all cached crates that aren't pinned
+    Refresh {
+        /// Names of the crates to look up
+        crates: Vec<String>,
+    },
+    /// Pin a crate to a version, or to its cached version, so that it is never looked up again
+    Pin {
+        /// Name of the crate
+        crate_name: String,
+        /// Version requirement, e.g. 1.0.200 or 1.0
+        version: Option<String>,
+    },
+    /// Remove the named crates, or all crates, from the cache
+    Forget {
+        /// Names of the crates to remove
+        #[arg(required_unless_present = "all")]
+        crates: Vec<String>,
+        /// Remove all crates
+        #[arg(long, conflicts_with = "crates")]
+        all: bool,
+    },
+}
+
+impl Cli {
+    /// The cargo profile selected by `--release` or `--profile`, if any.
+    #[must_use]
+    pub fn cargo_profile(&self) -> Option<String> {
+        if self.release {
+            Some("release".to_string())
+        } else {
+            self.profile.clone()
+        }
+    }
+
+    /// The toolchain and feature options given on the command line.
+    #[must_use]
+    pub fn build_options(&self) -> BuildOptions {
+        BuildOptions {
+            toolchain: self.toolchain.clone(),
+            features: self.features.clone(),
+            no_default_features: self.no_default_features,
+        }
+    }
+
+    /// Translate a pipeline subcommand into the equivalent mode options, so that the
+    /// rest of the processing only has to deal with one form.
+    /// # Errors
+    ///
+    /// Will return `Err` if a subcommand is combined with a script name or one of the
+    /// mode options it replaces.
+    pub fn apply_command(&mut self) -> Result<(), BuildRunError> {
+        let Some(command) = self.command.clone() else {
+            return Ok(());
+        };
+        if self.script.is_some()
+            || self.expression.is_some()
+            || self.loop_expr.is_some()
+            || self.repl
+            || self.stdin
+            || self.edit
+            || self.norun
+            || self.check
+            || self.clippy
+            || self.test
+        {
+            return Err(BuildRunError::Command(
+                "Use either a subcommand or the equivalent options, not both".to_string(),
+            ));
+        }
+        match command {
+            Commands::Run { script, args } => {
+                self.script = Some(script);
+                self.args = args;
+            }
+            Commands::Build { script } => {
+                self.script = Some(script);
+                self.norun = true;
+            }
+            Commands::Check { script } => {
+                self.script = Some(script);
+                self.check = true;
+            }
+            Commands::Clippy { script } => {
+                self.script = Some(script);
+                self.clippy = true;
+            }
+            Commands::Test { script, args } => {
+                self.script = Some(script);
+                self.args = args;
+                self.test = true;
+            }
+            Commands::Export { script, .. } => {
+                self.script = Some(script);
+                self.norun = true;
+            }
+            Commands::Expr { expression } => self.expression = Some(expression),
+            Commands::Repl { script } => {
+                self.repl = true;
+                self.script = script;
+            }
+            Commands::Import { .. }
+            | Commands::Convert { .. }
+            | Commands::Clean { .. }
+            | Commands::List
+            | Commands::Cache { .. }
+            | Commands::Deps { .. }
+            | Commands::Completions { .. } => (),
+        }
+        Ok(())
+    }
 }
 
 /// Getter for clap command-line arguments
 pub fn get_args() -> Cli {
-    Cli::parse()
+    complete_if_requested();
+    Cli::parse_from(expand_toolchain_arg(std::env::args()))
+}
+
+/// Parse a duration given as a number with an optional unit of `ms`, `s` (the default),
+/// `m`, `h` or `d`, e.g. `30s`, `1.5m`, `250ms` or `30d`.
+/// # Errors
+///
+/// Will return `Err` if the number or unit is invalid, or the duration is too long.
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let duration = duration.trim();
+    let invalid = || format!("Invalid duration {duration}: expected e.g. 30s, 500ms, 2m, 1h or 7d");
+    let split_at = duration
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(duration.len());
+    let (number, unit) = duration.split_at(split_at);
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    let seconds = match unit.trim() {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        "d" => number * 86_400.0,
+        other => {
+            return Err(format!(
+                "Invalid duration unit {other}: expected ms, s, m, h or d"
+            ))
+        }
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|_| invalid())
+}
+
+/// Parse a size in bytes given as a number with an optional binary unit of `K`, `M`, `G`
+/// or `T`, e.g. `500M` or `1.5G`. A trailing `B` or `iB` is allowed, as in `500MiB`.
+/// # Errors
+///
+/// Will return `Err` if the number or unit is invalid.
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let split_at = size
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split_at);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid size {size}: expected e.g. 500M or 2G"))?;
+    let unit = unit.trim().to_ascii_uppercase();
+    let multiplier: u64 = match unit.trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(format!("Invalid size unit {unit}: expected K, M, G or T")),
+    };
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Translate a rustup-style `+<toolchain>` first argument into `--toolchain <toolchain>`.
+pub fn expand_toolchain_arg(args: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut args = args.into_iter().collect::<Vec<_>>();
+    if let Some(toolchain) = args.get(1).and_then(|arg| arg.strip_prefix('+')) {
+        let toolchain = toolchain.to_string();
+        args.splice(1..2, ["--toolchain".to_string(), toolchain]);
+    }
+    args
 }
 
 pub fn validate_args(args: &Cli, proc_flags: &ProcFlags) -> Result<(), Box<dyn Error>> {
+    if (args.print || args.begin.is_some() || args.end.is_some()) && args.loop_expr.is_none() {
+        return Err(Box::new(BuildRunError::Command(
+            "--print, --begin and --end only apply to --loop".to_string(),
+        )));
+    }
+    if args.update_toml && (args.script.is_none() || proc_flags.contains(ProcFlags::REPL)) {
+        return Err(Box::new(BuildRunError::Command(
+            "--update-toml only applies to a script file".to_string(),
+        )));
+    }
     if let Some(ref script) = args.script {
         if !script.ends_with(RS_SUFFIX) {
             return Err(Box::new(BuildRunError::Command(format!(
@@ -75,6 +504,7 @@ pub fn validate_args(args: &Cli, proc_flags: &ProcFlags) -> Result<(), Box<dyn E
             ))));
         }
     } else if !proc_flags.contains(ProcFlags::EXPR)
+        && !proc_flags.contains(ProcFlags::LOOP)
         && !proc_flags.contains(ProcFlags::REPL)
         && !proc_flags.contains(ProcFlags::STDIN)
         && !proc_flags.contains(ProcFlags::EDIT)
@@ -107,6 +537,12 @@ bitflags! {
         const QUIET = 2048;
         const MULTI = 4096;
         const NORUN = 8192;
+        const CHECK = 16384;
+        const CLIPPY = 32768;
+        const TEST = 65536;
+        const WATCH = 131072;
+        const LOOP = 262144;
+        const UPDATE_TOML = 524288;
     }
 }
 
@@ -140,30 +576,39 @@ impl str::FromStr for ProcFlags {
 /// Will panic if the internal correctness check fails.
 pub fn get_proc_flags(args: &Cli) -> Result<ProcFlags, Box<dyn Error>> {
     let is_expr = args.expression.is_some();
+    let is_loop = args.loop_expr.is_some();
     let proc_flags = {
         let mut proc_flags = ProcFlags::empty();
         // TODO: out? once clap default_value_ifs is working
         proc_flags.set(
             ProcFlags::GENERATE,
-            args.generate | args.force | args.all | is_expr,
+            args.generate | args.force | args.all | is_expr | is_loop,
         );
         proc_flags.set(
             ProcFlags::BUILD,
-            args.build | args.force | args.all | is_expr,
+            args.build | args.force | args.all | is_expr | is_loop,
         );
         proc_flags.set(ProcFlags::FORCE, args.force);
+        proc_flags.set(ProcFlags::UPDATE_TOML, args.update_toml);
         proc_flags.set(ProcFlags::QUIET, args.quiet);
         proc_flags.set(ProcFlags::MULTI, args.multimain);
         proc_flags.set(ProcFlags::VERBOSE, args.verbose);
         proc_flags.set(ProcFlags::TIMINGS, args.timings);
-        proc_flags.set(ProcFlags::NORUN, args.norun);
-        proc_flags.set(ProcFlags::RUN, !args.norun);
-        proc_flags.set(ProcFlags::ALL, !args.norun);
+        // Checking, linting and testing produce no executable to run
+        let norun = args.norun | args.check | args.clippy | args.test;
+        proc_flags.set(ProcFlags::NORUN, norun);
+        proc_flags.set(ProcFlags::RUN, !norun);
+        proc_flags.set(ProcFlags::ALL, !norun);
+        proc_flags.set(ProcFlags::CHECK, args.check);
+        proc_flags.set(ProcFlags::CLIPPY, args.clippy);
+        proc_flags.set(ProcFlags::TEST, args.test);
+        proc_flags.set(ProcFlags::WATCH, args.watch);
         if !(proc_flags.contains(ProcFlags::ALL)) {
             proc_flags.set(ProcFlags::ALL, args.generate & args.build & args.run);
         }
         proc_flags.set(ProcFlags::REPL, args.repl);
         proc_flags.set(ProcFlags::EXPR, is_expr);
+        proc_flags.set(ProcFlags::LOOP, is_loop);
         proc_flags.set(ProcFlags::STDIN, args.stdin);
         proc_flags.set(ProcFlags::EDIT, args.edit);
 
@@ -175,6 +620,7 @@ pub fn get_proc_flags(args: &Cli) -> Result<ProcFlags, Box<dyn Error>> {
             Verbosity::Normal
         };
         logging::set_global_verbosity(verbosity);
+        logging::set_global_message_format(args.message_format);
 
         // Check all good
         let formatted = proc_flags.to_string();
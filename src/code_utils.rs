@@ -1,26 +1,38 @@
 use crate::builder::gen_build_run;
 use crate::cmd_args::{Cli, ProcFlags};
 use crate::errors::BuildRunError;
+use crate::knowledge::Usages;
 use crate::log;
 use crate::logging::Verbosity;
-use crate::shared::{debug_timings, Ast, BuildState};
+use crate::shared::{debug_timings, Ast, BuildOptions, BuildState};
 use crate::{debug_log, nu_color_println, nu_resolve_style};
 use crate::{DYNAMIC_SUBDIR, REPL_SUBDIR, TEMP_SCRIPT_NAME, TMPDIR};
 
-use cargo_toml::Manifest;
+use cargo_toml::{Dependency, Manifest};
+use clap::ValueEnum;
 use lazy_static::lazy_static;
 use quote::quote;
 use regex::Regex;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
 use std::error::Error;
+use std::fmt;
 use std::fs;
 use std::fs::{remove_dir_all, remove_file, OpenOptions};
 use std::io::{self, BufRead, Write};
+use std::ops::Range;
 use std::option::Option;
 use std::path::{Path, PathBuf};
-use std::process::{Command, ExitStatus, Output};
-use std::time::{Instant, SystemTime};
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::time::Instant;
+use syn::punctuated::Punctuated;
 use syn::visit::Visit;
-use syn::{parse_str, Expr, File, Item, ItemExternCrate, ItemMod, Stmt, UsePath, UseRename};
+use syn::{
+    parse_str, Attribute, ConstParam, Expr, File, GenericParam, Item, ItemEnum, ItemExternCrate,
+    ItemMod, ItemStruct, ItemTrait, ItemTraitAlias, ItemType, ItemUnion, ItemUse, Macro,
+    Path as SynPath, Stmt, Token, TypeParam, UseName, UsePath, UseRename, UseTree,
+};
+use toml_edit::DocumentMut;
 
 /// Read the contents of a file. For reading the Rust script.
 pub fn read_file_contents(path: &Path) -> Result<String, BuildRunError> {
@@ -28,34 +40,64 @@ pub fn read_file_contents(path: &Path) -> Result<String, BuildRunError> {
     Ok(fs::read_to_string(path)?)
 }
 
+/// Crates and path roots that never need to be added as dependencies.
+const BUILT_IN_CRATES: &[&str] = &[
+    "std",
+    "core",
+    "alloc",
+    "proc_macro",
+    "test",
+    "collections",
+    "fmt",
+    "crate",
+    "self",
+    "super",
+    "Self",
+    // Tool attribute namespaces, as in `#[rustfmt::skip]`
+    "rustfmt",
+    "clippy",
+    "diagnostic",
+    "rustdoc",
+];
+
+/// Primitive types, whose associated items, e.g. `u64::MAX`, are reached by paths too.
+const PRIMITIVE_TYPES: &[&str] = &[
+    "bool", "char", "str", "f32", "f64", "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16",
+    "u32", "u64", "u128", "usize",
+];
+
 /// Infer dependencies from the abstract syntax tree to put in a Cargo.toml.
 pub fn infer_deps_from_ast(syntax_tree: &Ast) -> Vec<String> {
     let use_crates = find_use_crates_ast(syntax_tree);
     let extern_crates = find_extern_crates_ast(syntax_tree);
+    let path_crates = find_path_crates_ast(syntax_tree);
     let use_renames = find_use_renames_ast(syntax_tree);
     let modules = find_modules_ast(syntax_tree);
+    let local_names = find_local_names_ast(syntax_tree);
 
     let mut dependencies = Vec::new();
-    let built_in_crates = ["std", "core", "alloc", "collections", "fmt", "crate"];
 
-    for crate_name in use_crates {
+    for crate_name in use_crates.iter().chain(&extern_crates) {
         filter_deps_ast(
-            &crate_name,
-            &built_in_crates,
+            crate_name,
+            BUILT_IN_CRATES,
             &use_renames,
             &modules,
             &mut dependencies,
         );
     }
 
-    for crate_name in extern_crates {
-        filter_deps_ast(
-            &crate_name,
-            &built_in_crates,
-            &use_renames,
-            &modules,
-            &mut dependencies,
-        );
+    // Qualified paths may also start with a type or with a name brought in by `use`
+    for crate_name in path_crates {
+        if !is_type_name(&crate_name) && !local_names.contains(&crate_name) {
+            filter_deps_ast(
+                &crate_name,
+                BUILT_IN_CRATES,
+                &use_renames,
+                &modules,
+                &mut dependencies,
+            );
+        }
     }
 
     // Deduplicate the list of dependencies
@@ -68,7 +110,7 @@ pub fn infer_deps_from_ast(syntax_tree: &Ast) -> Vec<String> {
 /// Filter out crates that don't need to be added as dependencies: abstract syntax tree-based version.
 fn filter_deps_ast(
     crate_name: &str,
-    built_in_crates: &[&str; 6],
+    built_in_crates: &[&str],
     use_renames: &[String],
     modules: &[String],
     dependencies: &mut Vec<String>,
@@ -132,15 +174,34 @@ fn find_modules_ast(syntax_tree: &Ast) -> Vec<String> {
 }
 
 /// Identify use crate statements for inclusion in Cargo.toml metadata: abstract syntax tree-based version.
+/// Only the roots of use trees are taken, including those in groups such as `use {anyhow, regex::Regex};`.
 fn find_use_crates_ast(syntax_tree: &Ast) -> Vec<String> {
     #[derive(Default)]
     struct FindCrates {
         use_crates: Vec<String>,
     }
 
+    impl FindCrates {
+        fn add_roots(&mut self, tree: &UseTree) {
+            match tree {
+                UseTree::Path(UsePath { ident, .. })
+                | UseTree::Name(UseName { ident })
+                | UseTree::Rename(UseRename { ident, .. }) => {
+                    self.use_crates.push(ident.to_string());
+                }
+                UseTree::Group(use_group) => {
+                    for tree in &use_group.items {
+                        self.add_roots(tree);
+                    }
+                }
+                UseTree::Glob(_) => (),
+            }
+        }
+    }
+
     impl<'a> Visit<'a> for FindCrates {
-        fn visit_use_path(&mut self, node: &'a UsePath) {
-            self.use_crates.push(node.ident.to_string());
+        fn visit_item_use(&mut self, node: &'a ItemUse) {
+            self.add_roots(&node.tree);
         }
     }
 
@@ -179,6 +240,257 @@ fn find_extern_crates_ast(syntax_tree: &Ast) -> Vec<String> {
     finder.extern_crates
 }
 
+/// Identify the leading segments of qualified paths in expressions, types, patterns, macro
+/// invocations and attributes, such as `regex` in `regex::Regex::new(..)`, for inclusion in
+/// Cargo.toml metadata: abstract syntax tree-based version. The arguments of macros such as
+/// `println!` are searched too, if they parse as expressions.
+fn find_path_crates_ast(syntax_tree: &Ast) -> Vec<String> {
+    #[derive(Default)]
+    struct FindCrates {
+        path_crates: Vec<String>,
+    }
+
+    impl FindCrates {
+        fn add_root(&mut self, path: &SynPath) {
+            if path.segments.len() > 1 {
+                self.path_crates.push(path.segments[0].ident.to_string());
+            }
+        }
+    }
+
+    impl<'a> Visit<'a> for FindCrates {
+        fn visit_path(&mut self, node: &'a SynPath) {
+            self.add_root(node);
+            syn::visit::visit_path(self, node);
+        }
+
+        fn visit_attribute(&mut self, node: &'a Attribute) {
+            if node.path().is_ident("derive") {
+                if let Ok(derives) =
+                    node.parse_args_with(Punctuated::<SynPath, Token![,]>::parse_terminated)
+                {
+                    for derive in &derives {
+                        self.add_root(derive);
+                    }
+                }
+            }
+            syn::visit::visit_attribute(self, node);
+        }
+
+        fn visit_macro(&mut self, node: &'a Macro) {
+            if let Ok(args) = node.parse_body_with(Punctuated::<Expr, Token![,]>::parse_terminated)
+            {
+                for arg in &args {
+                    self.visit_expr(arg);
+                }
+            }
+            syn::visit::visit_macro(self, node);
+        }
+    }
+
+    let mut finder = FindCrates::default();
+
+    match syntax_tree {
+        Ast::File(ast) => finder.visit_file(ast),
+        Ast::Expr(ast) => finder.visit_expr(ast),
+    }
+
+    debug_log!("path_crates from ast={:#?}", finder.path_crates);
+    finder.path_crates
+}
+
+/// Identify names defined or imported by the script that qualified paths may start with:
+/// local type and trait names, generic parameters, and the names brought in by `use`.
+/// For exclusion from Cargo.toml metadata: abstract syntax tree-based version.
+fn find_local_names_ast(syntax_tree: &Ast) -> Vec<String> {
+    #[derive(Default)]
+    struct FindNames {
+        local_names: Vec<String>,
+    }
+
+    impl<'a> Visit<'a> for FindNames {
+        fn visit_item(&mut self, node: &'a Item) {
+            match node {
+                Item::Struct(ItemStruct { ident, .. })
+                | Item::Enum(ItemEnum { ident, .. })
+                | Item::Union(ItemUnion { ident, .. })
+                | Item::Trait(ItemTrait { ident, .. })
+                | Item::TraitAlias(ItemTraitAlias { ident, .. })
+                | Item::Type(ItemType { ident, .. }) => self.local_names.push(ident.to_string()),
+                _ => (),
+            }
+            syn::visit::visit_item(self, node);
+        }
+
+        fn visit_generic_param(&mut self, node: &'a GenericParam) {
+            match node {
+                GenericParam::Type(TypeParam { ident, .. })
+                | GenericParam::Const(ConstParam { ident, .. }) => {
+                    self.local_names.push(ident.to_string());
+                }
+                GenericParam::Lifetime(_) => (),
+            }
+            syn::visit::visit_generic_param(self, node);
+        }
+
+        fn visit_use_path(&mut self, node: &'a UsePath) {
+            // `use std::io::{self, Write};` brings `io` itself into scope
+            let is_self = |tree: &UseTree| matches!(tree, UseTree::Name(UseName { ident }) if ident == "self");
+            let imports_self = match &*node.tree {
+                UseTree::Group(use_group) => use_group.items.iter().any(is_self),
+                tree => is_self(tree),
+            };
+            if imports_self {
+                self.local_names.push(node.ident.to_string());
+            }
+            syn::visit::visit_use_path(self, node);
+        }
+
+        fn visit_use_name(&mut self, node: &'a UseName) {
+            if node.ident != "self" {
+                self.local_names.push(node.ident.to_string());
+            }
+        }
+    }
+
+    let mut finder = FindNames::default();
+
+    match syntax_tree {
+        Ast::File(ast) => finder.visit_file(ast),
+        Ast::Expr(ast) => finder.visit_expr(ast),
+    }
+
+    debug_log!("local_names from ast={:#?}", finder.local_names);
+    finder.local_names
+}
+
+/// Whether a path segment names a type rather than a crate: a primitive type, or a
+/// capitalised name such as `String` or `Self`, since crate names are snake case.
+fn is_type_name(name: &str) -> bool {
+    PRIMITIVE_TYPES.contains(&name) || name.starts_with(char::is_uppercase)
+}
+
+/// Find the attributes, derive macros and paths used in a script, for the knowledge base of
+/// the features and companion crates that dependencies need: abstract syntax tree-based version.
+#[must_use]
+pub fn find_usages_ast(syntax_tree: &Ast) -> Usages {
+    #[derive(Default)]
+    struct FindUsages {
+        usages: Usages,
+    }
+
+    impl<'a> Visit<'a> for FindUsages {
+        fn visit_attribute(&mut self, node: &'a Attribute) {
+            if node.path().is_ident("derive") {
+                if let Ok(derives) =
+                    node.parse_args_with(Punctuated::<SynPath, Token![,]>::parse_terminated)
+                {
+                    for derive in derives {
+                        if let Some(segment) = derive.segments.last() {
+                            self.usages.derives.insert(segment.ident.to_string());
+                        }
+                        self.usages.paths.insert(path_string(&derive));
+                    }
+                }
+            } else {
+                self.usages.attributes.insert(path_string(node.path()));
+            }
+            syn::visit::visit_attribute(self, node);
+        }
+
+        fn visit_path(&mut self, node: &'a SynPath) {
+            self.usages.paths.insert(path_string(node));
+            syn::visit::visit_path(self, node);
+        }
+
+        fn visit_item_use(&mut self, node: &'a ItemUse) {
+            use_tree_paths(&node.tree, "", &mut self.usages.paths);
+        }
+    }
+
+    fn path_string(path: &SynPath) -> String {
+        path.segments
+            .iter()
+            .map(|segment| segment.ident.to_string())
+            .collect::<Vec<_>>()
+            .join("::")
+    }
+
+    /// The full path of each item imported by a use tree.
+    fn use_tree_paths(tree: &UseTree, prefix: &str, paths: &mut BTreeSet<String>) {
+        let join = |ident: &syn::Ident| {
+            if prefix.is_empty() {
+                ident.to_string()
+            } else {
+                format!("{prefix}::{ident}")
+            }
+        };
+        match tree {
+            UseTree::Path(use_path) => {
+                use_tree_paths(&use_path.tree, &join(&use_path.ident), paths)
+            }
+            UseTree::Name(use_name) => {
+                paths.insert(join(&use_name.ident));
+            }
+            UseTree::Rename(use_rename) => {
+                paths.insert(join(&use_rename.ident));
+            }
+            UseTree::Glob(_) => {
+                paths.insert(prefix.to_string());
+            }
+            UseTree::Group(use_group) => {
+                for tree in &use_group.items {
+                    use_tree_paths(tree, prefix, paths);
+                }
+            }
+        }
+    }
+
+    let mut finder = FindUsages::default();
+
+    match syntax_tree {
+        Ast::File(ast) => finder.visit_file(ast),
+        Ast::Expr(ast) => finder.visit_expr(ast),
+    }
+
+    debug_log!("usages from ast={:#?}", finder.usages);
+    finder.usages
+}
+
+/// Find the attributes, derive macros and paths used in a script.
+/// Fallback version for when an abstract syntax tree cannot be parsed.
+#[must_use]
+pub fn find_usages_source(code: &str) -> Usages {
+    lazy_static! {
+        static ref ATTRIBUTE_REGEX: Regex = Regex::new(r"#\[\s*(\w+(?:\s*::\s*\w+)*)").unwrap();
+        static ref DERIVE_REGEX: Regex = Regex::new(r"#\[\s*derive\s*\(([^)]*)\)").unwrap();
+        static ref PATH_REGEX: Regex = Regex::new(r"\b\w+(?:::\w+)+").unwrap();
+    }
+
+    let mut usages = Usages::default();
+    for cap in ATTRIBUTE_REGEX.captures_iter(code) {
+        let attribute: String = cap[1].split_whitespace().collect();
+        if attribute != "derive" {
+            usages.attributes.insert(attribute);
+        }
+    }
+    for cap in DERIVE_REGEX.captures_iter(code) {
+        for derive in cap[1].split(',') {
+            if let Some(name) = derive.rsplit("::").next().map(str::trim) {
+                if !name.is_empty() {
+                    usages.derives.insert(name.to_string());
+                }
+            }
+        }
+    }
+    for found in PATH_REGEX.find_iter(code) {
+        usages.paths.insert(found.as_str().to_string());
+    }
+
+    debug_log!("usages from source={usages:#?}");
+    usages
+}
+
 /// Infer dependencies from source code to put in a Cargo.toml.
 /// Fallback version for when an abstract syntax tree cannot be parsed.
 pub fn infer_deps_from_source(code: &str) -> Vec<String> {
@@ -187,35 +499,77 @@ pub fn infer_deps_from_source(code: &str) -> Vec<String> {
         static ref MACRO_USE_REGEX: Regex = Regex::new(r"(?m)^[\s]*#\[macro_use\((\w+)\)").unwrap();
         static ref EXTERN_CRATE_REGEX: Regex =
             Regex::new(r"(?m)^[\s]*extern\s+crate\s+([^;{]+)").unwrap();
+        static ref USE_GROUP_REGEX: Regex =
+            Regex::new(r"(?m)^[\s]*use\s+(?:::)?\{([^;]*)\}").unwrap();
+        static ref PATH_ROOT_REGEX: Regex =
+            Regex::new(r"(?m)(?:^|[^\w:$.])([a-z_][a-z0-9_]*)\s*::(\s*<)?").unwrap();
+        static ref LINE_COMMENT_REGEX: Regex = Regex::new(r"//[^\n]*").unwrap();
     }
 
     debug_log!("In code_utils::infer_deps_from_source");
     let use_renames = find_use_renames_source(code);
     let modules = find_modules_source(code);
+    let use_names = find_use_names_source(code);
 
     let mut dependencies = Vec::new();
 
-    let built_in_crates = ["std", "core", "alloc", "collections", "fmt", "crate"];
+    let built_in_crates = BUILT_IN_CRATES;
 
     for cap in USE_REGEX.captures_iter(code) {
         let crate_name = cap[1].to_string();
         debug_log!("dependency={crate_name}");
         filter_deps_source(
             &crate_name,
-            &built_in_crates,
+            built_in_crates,
             &use_renames,
             &modules,
             &mut dependencies,
         );
     }
 
+    // Groups at the root of a use tree, such as `use {anyhow, regex::Regex};`
+    for cap in USE_GROUP_REGEX.captures_iter(code) {
+        for item in split_use_group(&cap[1]) {
+            let root = item
+                .split(|c: char| !c.is_alphanumeric() && c != '_')
+                .next()
+                .unwrap_or(item);
+            filter_deps_source(
+                root,
+                built_in_crates,
+                &use_renames,
+                &modules,
+                &mut dependencies,
+            );
+        }
+    }
+
+    // Qualified paths such as `regex::Regex::new(..)`, excluding names brought in by `use`
+    // and method turbofish such as `s.parse::<i32>()`
+    let code_sans_comments = LINE_COMMENT_REGEX.replace_all(code, "");
+    for cap in PATH_ROOT_REGEX.captures_iter(&code_sans_comments) {
+        let crate_name = &cap[1];
+        if cap.get(2).is_none()
+            && !PRIMITIVE_TYPES.contains(&crate_name)
+            && !use_names.iter().any(|name| name == crate_name)
+        {
+            filter_deps_source(
+                crate_name,
+                built_in_crates,
+                &use_renames,
+                &modules,
+                &mut dependencies,
+            );
+        }
+    }
+
     // Similar checks for other regex patterns
 
     for cap in MACRO_USE_REGEX.captures_iter(code) {
         let crate_name = cap[1].to_string();
         filter_deps_source(
             &crate_name,
-            &built_in_crates,
+            built_in_crates,
             &use_renames,
             &modules,
             &mut dependencies,
@@ -226,7 +580,7 @@ pub fn infer_deps_from_source(code: &str) -> Vec<String> {
         let crate_name = cap[1].to_string();
         filter_deps_source(
             &crate_name,
-            &built_in_crates,
+            built_in_crates,
             &use_renames,
             &modules,
             &mut dependencies,
@@ -243,7 +597,7 @@ pub fn infer_deps_from_source(code: &str) -> Vec<String> {
 /// Filter out crates that don't need to be added as dependencies: fallback version using regex on source code.
 fn filter_deps_source(
     crate_name: &str,
-    built_in_crates: &[&str; 6],
+    built_in_crates: &[&str],
     use_renames: &[String],
     modules: &[String],
     dependencies: &mut Vec<String>,
@@ -265,6 +619,69 @@ fn filter_deps_source(
     }
 }
 
+/// Split the items of a use group on the commas at its own level, returning each trimmed item.
+fn split_use_group(group: &str) -> Vec<&str> {
+    let mut items = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in group.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                items.push(group[start..i].trim());
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    items.push(group[start..].trim());
+    items.retain(|item| !item.is_empty() && *item != "self" && !item.starts_with('*'));
+    items
+}
+
+/// Identify the names that use statements bring into scope, such as `fs` in `use std::fs;`,
+/// for exclusion from Cargo.toml metadata when qualified paths start with them.
+/// Fallback version for when an abstract syntax tree cannot be parsed.
+fn find_use_names_source(code: &str) -> Vec<String> {
+    lazy_static! {
+        static ref USE_STMT_REGEX: Regex =
+            Regex::new(r"(?m)^[\s]*(?:pub(?:\([^)]*\))?\s+)?use\s+([^;]+);").unwrap();
+        static ref USE_TOKEN_REGEX: Regex = Regex::new(r"(\w+)\s*(::)?|[{}]").unwrap();
+    }
+
+    let mut use_names = vec![];
+    for cap in USE_STMT_REGEX.captures_iter(code) {
+        // The segment before each enclosing group, for `self` in `use std::io::{self, Write};`
+        let mut group_parents: Vec<Option<&str>> = vec![];
+        let mut parent = None;
+        for token in USE_TOKEN_REGEX.captures_iter(&cap[1]) {
+            match (token.get(1), token.get(2)) {
+                (Some(segment), Some(_)) => parent = Some(segment.as_str()),
+                (Some(name), None) => {
+                    match name.as_str() {
+                        "as" => (),
+                        "self" => {
+                            if let Some(Some(group_parent)) = group_parents.last() {
+                                use_names.push((*group_parent).to_string());
+                            }
+                        }
+                        name => use_names.push(name.to_string()),
+                    }
+                    parent = None;
+                }
+                (None, _) if &token[0] == "{" => group_parents.push(parent.take()),
+                (None, _) => {
+                    group_parents.pop();
+                }
+            }
+        }
+    }
+
+    debug_log!("use_names from source={use_names:#?}");
+    use_names
+}
+
 /// Identify use ... as statements for exclusion from Cargo.toml metadata.
 /// Fallback version for when an abstract syntax tree cannot be parsed.
 pub fn find_use_renames_source(code: &str) -> Vec<String> {
@@ -307,7 +724,7 @@ pub fn find_modules_source(code: &str) -> Vec<String> {
     modules
 }
 
-/// Extract embedded Cargo.toml metadata from a Rust source string.
+/// Extract embedded Cargo.toml metadata from a Rust source string, in any supported style.
 pub fn extract_manifest(
     rs_full_source: &str,
     start_parsing_rs: Instant,
@@ -327,10 +744,331 @@ pub fn extract_manifest(
     Ok(rs_manifest)
 }
 
+/// Extract the toolchain and feature options from an optional `[rs-script]` table
+/// in the toml block of a Rust source string, e.g.
+/// ```toml
+/// [rs-script]
+/// toolchain = "nightly"
+/// features = ["simd"]
+/// no-default-features = true
+/// ```
+/// # Errors
+///
+/// Will return `Err` if the toml block is invalid or the table contains an invalid setting.
+pub fn extract_build_options(rs_full_source: &str) -> Result<BuildOptions, BuildRunError> {
+    #[derive(Default, Deserialize)]
+    #[serde(default)]
+    struct TomlBlock {
+        #[serde(rename = "rs-script")]
+        build_options: BuildOptions,
+    }
+
+    let Some(rs_toml_str) = extract_toml_block(rs_full_source) else {
+        return Ok(BuildOptions::default());
+    };
+    let toml_block: TomlBlock = toml::from_str(&rs_toml_str)?;
+    Ok(toml_block.build_options)
+}
+
+/// Parse the toml block of a Rust source string, if any, into a raw toml table.
+/// # Errors
+///
+/// Will return `Err` if the toml block is not valid TOML.
+pub fn extract_toml_table(rs_full_source: &str) -> Result<Option<toml::Table>, BuildRunError> {
+    let Some(rs_toml_str) = extract_toml_block(rs_full_source) else {
+        return Ok(None);
+    };
+    Ok(Some(rs_toml_str.parse::<toml::Table>()?))
+}
+
+/// The ways of embedding a manifest in a script.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ManifestStyle {
+    /// A `/*[toml] ... */` block comment
+    #[default]
+    Toml,
+    /// Cargo-script front matter: a `---cargo ... ---` fence at the top of the script
+    Frontmatter,
+    /// A rust-script style ```` ```cargo ```` code block in `//!` doc comments
+    DocComment,
+}
+
+impl fmt::Display for ManifestStyle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Toml => "toml block",
+            Self::Frontmatter => "front matter",
+            Self::DocComment => "doc comment",
+        })
+    }
+}
+
+/// A manifest embedded in a Rust source string.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ManifestBlock {
+    pub style: ManifestStyle,
+    /// Byte range of the whole block, delimiters included, in the source.
+    pub range: Range<usize>,
+    /// The TOML content of the block.
+    pub toml: String,
+}
+
+/// Find the manifest embedded in a Rust source string, in any of the supported styles:
+/// cargo-script front matter, a `/*[toml]` block or a `//! ```cargo` doc comment block.
+#[must_use]
+pub fn find_manifest_block(rs_full_source: &str) -> Option<ManifestBlock> {
+    lazy_static! {
+        static ref TOML_BLOCK_REGEX: Regex = Regex::new(r"(?s)/\*\[toml\](.*?)\*/").unwrap();
+        static ref DOC_COMMENT_REGEX: Regex =
+            Regex::new(r"(?m)^//![ \t]?```cargo[ \t]*\n((?://!.*\n)*?)//![ \t]?```[ \t]*$")
+                .unwrap();
+        static ref DOC_PREFIX_REGEX: Regex = Regex::new(r"(?m)^//! ?").unwrap();
+    }
+    if let Some(block) = find_frontmatter(rs_full_source) {
+        return Some(block);
+    }
+    if let Some(caps) = TOML_BLOCK_REGEX.captures(rs_full_source) {
+        return Some(ManifestBlock {
+            style: ManifestStyle::Toml,
+            range: caps.get(0)?.range(),
+            toml: caps[1].to_string(),
+        });
+    }
+    let caps = DOC_COMMENT_REGEX.captures(rs_full_source)?;
+    Some(ManifestBlock {
+        style: ManifestStyle::DocComment,
+        range: caps.get(0)?.range(),
+        toml: DOC_PREFIX_REGEX.replace_all(&caps[1], "").to_string(),
+    })
+}
+
+/// Find cargo-script front matter: an opening fence of three or more dashes, optionally
+/// followed by `cargo`, before anything but a shebang and blank lines, and a closing fence
+/// of as many dashes.
+fn find_frontmatter(rs_full_source: &str) -> Option<ManifestBlock> {
+    let mut offset = 0;
+    let mut lines = rs_full_source.split_inclusive('\n');
+    let opening = loop {
+        let line = lines.next()?;
+        let is_shebang = offset == 0 && line.starts_with("#!") && !line.starts_with("#![");
+        if !is_shebang && !line.trim().is_empty() {
+            break line;
+        }
+        offset += line.len();
+    };
+    let start = offset;
+    let fence = opening.trim_end();
+    let dashes = fence.len() - fence.trim_start_matches('-').len();
+    if dashes < 3 || !matches!(fence[dashes..].trim(), "" | "cargo") {
+        return None;
+    }
+    offset += opening.len();
+    let content_start = offset;
+    for line in lines {
+        if line.trim_end() == &fence[..dashes] {
+            return Some(ManifestBlock {
+                style: ManifestStyle::Frontmatter,
+                range: start..offset + line.trim_end().len(),
+                toml: rs_full_source[content_start..offset].to_string(),
+            });
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/// Write a manifest as a block in the given style.
+#[must_use]
+pub fn render_manifest_block(style: ManifestStyle, toml: &str) -> String {
+    let toml = toml.trim_start_matches('\n');
+    let newline = if toml.is_empty() || toml.ends_with('\n') {
+        ""
+    } else {
+        "\n"
+    };
+    match style {
+        ManifestStyle::Toml => format!("/*[toml]\n{toml}{newline}*/"),
+        ManifestStyle::Frontmatter => format!("---cargo\n{toml}{newline}---"),
+        ManifestStyle::DocComment => {
+            let mut block = "//! ```cargo\n".to_string();
+            for line in toml.lines() {
+                block.push_str(format!("//! {line}").trim_end());
+                block.push('\n');
+            }
+            block.push_str("//! ```");
+            block
+        }
+    }
+}
+
+/// Turn any cargo-script front matter into a `/*[toml]` block, which unlike front matter
+/// is valid Rust on stable, keeping the line numbers of the rest of the source. The content
+/// is kept verbatim, blank lines and all, for the same reason.
+#[must_use]
+pub fn comment_out_frontmatter(rs_source: &str) -> String {
+    match find_frontmatter(rs_source) {
+        Some(block) => format!(
+            "{}/*[toml]\n{}*/{}",
+            &rs_source[..block.range.start],
+            block.toml,
+            &rs_source[block.range.end..]
+        ),
+        None => rs_source.to_string(),
+    }
+}
+
+/// Remove the embedded manifest, if any, from a Rust source string.
+pub fn strip_toml_block(rs_full_source: &str) -> String {
+    let Some(block) = find_manifest_block(rs_full_source) else {
+        return rs_full_source.trim_start().to_string();
+    };
+    let rest = &rs_full_source[block.range.end..];
+    format!(
+        "{}{}",
+        &rs_full_source[..block.range.start],
+        rest.strip_prefix('\n').unwrap_or(rest)
+    )
+    .trim_start()
+    .to_string()
+}
+
+/// The byte offset just after any shebang line, where a new manifest goes.
+fn after_shebang(rs_full_source: &str) -> usize {
+    // `#![` starts an inner attribute, not a shebang
+    if rs_full_source.starts_with("#!") && !rs_full_source.starts_with("#![") {
+        rs_full_source
+            .find('\n')
+            .map_or(rs_full_source.len(), |pos| pos + 1)
+    } else {
+        0
+    }
+}
+
+/// Add any of the given dependencies that are missing from the embedded manifest of a Rust
+/// source string, creating a toml block after any shebang if there is none. Existing
+/// entries, and everything outside the `[dependencies]` table, are left exactly as they
+/// were, as is the rest of the source. Returns the updated source and the names of the
+/// dependencies added.
+/// # Errors
+///
+/// Will return `Err` if the existing manifest is not valid TOML or a dependency can't
+/// be written as TOML.
+pub fn update_toml_block(
+    rs_full_source: &str,
+    dependencies: &BTreeMap<String, Dependency>,
+) -> Result<(String, Vec<String>), BuildRunError> {
+    let block = find_manifest_block(rs_full_source);
+    let mut document = match block {
+        Some(ref block) => block
+            .toml
+            .parse::<DocumentMut>()
+            .map_err(|err| BuildRunError::FromStr(format!("Invalid toml block: {err}")))?,
+        None => DocumentMut::new(),
+    };
+
+    let table = document
+        .entry("dependencies")
+        .or_insert_with(toml_edit::table)
+        .as_table_like_mut()
+        .ok_or_else(|| BuildRunError::FromStr("[dependencies] is not a table".to_string()))?;
+    let mut added = vec![];
+    for (name, dependency) in dependencies {
+        let spelled_alike = |key: &str| key.replace('-', "_") == name.replace('-', "_");
+        if table.iter().any(|(key, _)| spelled_alike(key)) {
+            continue;
+        }
+        table.insert(name, dependency_item(dependency)?);
+        added.push(name.clone());
+    }
+    if added.is_empty() {
+        return Ok((rs_full_source.to_string(), added));
+    }
+
+    let toml_str = document.to_string();
+    let updated = match block {
+        Some(block) => {
+            let old_block = &rs_full_source[block.range.clone()];
+            // Keep the opening and closing lines of the block as they were
+            let new_block = match (old_block.find('\n'), old_block.rfind('\n')) {
+                (Some(first), Some(last)) if block.style != ManifestStyle::DocComment => {
+                    let toml_str = toml_str.trim_start_matches('\n');
+                    let newline = if toml_str.ends_with('\n') { "" } else { "\n" };
+                    format!(
+                        "{}{toml_str}{newline}{}",
+                        &old_block[..=first],
+                        &old_block[last + 1..]
+                    )
+                }
+                _ => render_manifest_block(block.style, &toml_str),
+            };
+            format!(
+                "{}{new_block}{}",
+                &rs_full_source[..block.range.start],
+                &rs_full_source[block.range.end..]
+            )
+        }
+        None => {
+            let insert_at = after_shebang(rs_full_source);
+            format!(
+                "{}{}\n\n{}",
+                &rs_full_source[..insert_at],
+                render_manifest_block(ManifestStyle::Toml, &toml_str),
+                &rs_full_source[insert_at..]
+            )
+        }
+    };
+    Ok((updated, added))
+}
+
+/// Rewrite the embedded manifest of a Rust source string in another style. The manifest
+/// stays where it is if it can, but front matter and doc comments have to come first, so
+/// these are moved to the top of the script, after any shebang.
+/// # Errors
+///
+/// Will return `Err` if the source has no embedded manifest.
+pub fn convert_manifest_style(
+    rs_full_source: &str,
+    style: ManifestStyle,
+) -> Result<String, BuildRunError> {
+    let block = find_manifest_block(rs_full_source).ok_or_else(|| {
+        BuildRunError::Command("No toml block, front matter or cargo doc comment found".into())
+    })?;
+    if block.style == style {
+        return Ok(rs_full_source.to_string());
+    }
+    let rendered = render_manifest_block(style, &block.toml);
+    let insert_at = after_shebang(rs_full_source);
+    let at_top = rs_full_source[insert_at..block.range.start]
+        .trim()
+        .is_empty();
+    if style == ManifestStyle::Toml || at_top {
+        return Ok(format!(
+            "{}{rendered}{}",
+            &rs_full_source[..block.range.start],
+            &rs_full_source[block.range.end..]
+        ));
+    }
+    let rest = &rs_full_source[block.range.end..];
+    Ok(format!(
+        "{}{rendered}\n\n{}{}",
+        &rs_full_source[..insert_at],
+        &rs_full_source[insert_at..block.range.start],
+        rest.strip_prefix('\n').unwrap_or(rest)
+    ))
+}
+
+/// A dependency as an item of a toml table: a version string, or an inline table for a
+/// dependency with more detail.
+fn dependency_item(dependency: &Dependency) -> Result<toml_edit::Item, BuildRunError> {
+    let value = toml::Value::try_from(dependency)?;
+    let document = format!("dependency = {value}")
+        .parse::<DocumentMut>()
+        .map_err(|err| BuildRunError::FromStr(err.to_string()))?;
+    Ok(document["dependency"].clone())
+}
+
 fn extract_toml_block(input: &str) -> Option<String> {
-    let re = Regex::new(r"(?s)/\*\[toml\](.*?)\*/").unwrap();
-    re.captures(input)
-        .and_then(|caps| caps.get(1).map(|m| m.as_str().to_string()))
+    find_manifest_block(input).map(|block| block.toml)
 }
 
 /// Parse a Rust expression source string into a syntax tree.
@@ -349,6 +1087,10 @@ pub fn extract_ast(rs_source: &str) -> Result<Expr, syn::Error> {
 }
 
 /// Process a Rust expression
+/// # Errors
+///
+/// Will return `Err` if the expression can't be generated or built, or if the program fails,
+/// so that the caller can exit with the program's status.
 pub fn process_expr(
     expr_ast: &Expr,
     build_state: &mut BuildState,
@@ -359,9 +1101,7 @@ pub fn process_expr(
 ) -> Result<(), Box<dyn Error>> {
     let syntax_tree = Some(Ast::Expr(expr_ast.clone()));
     write_source(&build_state.source_path, rs_source)?;
-    let result = gen_build_run(options, proc_flags, build_state, syntax_tree, start);
-    log!(Verbosity::Normal, "{result:?}");
-    Ok(())
+    gen_build_run(options, proc_flags, build_state, syntax_tree, start)
 }
 
 /// Convert a Path to a string value, assuming the path contains only valid characters.
@@ -456,52 +1196,6 @@ pub fn handle_outcome(
     Ok(())
 }
 
-/// Check if executable is stale, i.e. if raw source script or individual Cargo.toml
-/// has a more recent modification date and time
-pub fn modified_since_compiled(build_state: &BuildState) -> Option<(&PathBuf, SystemTime)> {
-    let executable = &build_state.target_path;
-    assert!(executable.exists(), "Missing executable");
-    let Ok(metadata) = fs::metadata(executable) else {
-        return None;
-    };
-
-    // Panic because this shouldn't happen
-    let baseline_modified = metadata
-        .modified()
-        .expect("Missing metadata for executable file {executable:#?}");
-
-    let files = [&build_state.source_path, &build_state.cargo_toml_path];
-    let mut most_recent: Option<(&PathBuf, SystemTime)> = None;
-    for &file in &files {
-        let Ok(metadata) = fs::metadata(file) else {
-            continue;
-        };
-
-        let modified_time = metadata
-            .modified()
-            .expect("Missing metadata for file {file:#?}"); // Handle potential errors
-        debug_log!("File: {file:?} modified time is {modified_time:#?}");
-
-        if modified_time < baseline_modified {
-            continue;
-        }
-
-        if most_recent.is_none() || modified_time > most_recent.unwrap().1 {
-            most_recent = Some((file, modified_time));
-        }
-    }
-    if let Some(file) = most_recent {
-        log!(
-            Verbosity::Verbose,
-            "The most recently modified file compared to {executable:#?} is: {file:#?}"
-        );
-        debug_log!("Executable modified time is{baseline_modified:#?}");
-    } else {
-        debug_log!("Neither file was modified more recently than {executable:#?}");
-    }
-    most_recent
-}
-
 /// Count the number of `main()` methods in an abstract syntax tree.
 pub fn count_main_methods(syntax_tree: &Ast) -> usize {
     #[derive(Default)]
@@ -527,6 +1221,35 @@ pub fn count_main_methods(syntax_tree: &Ast) -> usize {
     finder.main_method_count
 }
 
+/// Check whether the code enables unstable features with `#![feature(...)]`,
+/// using the syntax tree if available.
+pub fn uses_unstable_features(syntax_tree: Option<&Ast>, rs_source: &str) -> bool {
+    #[derive(Default)]
+    struct FindFeatureAttrs {
+        found: bool,
+    }
+
+    impl<'a> Visit<'a> for FindFeatureAttrs {
+        fn visit_attribute(&mut self, node: &'a syn::Attribute) {
+            if matches!(node.style, syn::AttrStyle::Inner(_)) && node.path().is_ident("feature") {
+                self.found = true;
+            }
+        }
+    }
+
+    lazy_static! {
+        static ref FEATURE_REGEX: Regex = Regex::new(r"(?m)^\s*#!\[\s*feature\s*\(").unwrap();
+    }
+
+    let mut finder = FindFeatureAttrs::default();
+    match syntax_tree {
+        Some(Ast::File(ast)) => finder.visit_file(ast),
+        Some(Ast::Expr(ast)) => finder.visit_expr(ast),
+        None => return FEATURE_REGEX.is_match(rs_source),
+    }
+    finder.found
+}
+
 /// Parse the code into an abstract syntax tree for inspection
 /// if possible (should work if the code will compike)
 pub fn to_ast(source_code: &str) -> Option<Ast> {
@@ -553,6 +1276,26 @@ pub fn to_ast(source_code: &str) -> Option<Ast> {
     }
 }
 
+/// Take the items other than macros out of a snippet, so that they can go at the top level
+/// of the program instead of inside `main`, where the test harness can't find `#[test]`
+/// functions. Macros stay put because `macro_rules!` definitions are textually scoped.
+pub fn hoist_items(syntax_tree: &mut Ast) -> Vec<Item> {
+    let Ast::Expr(Expr::Block(expr_block)) = syntax_tree else {
+        return vec![];
+    };
+    let (items, stmts): (Vec<Stmt>, Vec<Stmt>) = std::mem::take(&mut expr_block.block.stmts)
+        .into_iter()
+        .partition(|stmt| matches!(stmt, Stmt::Item(item) if !matches!(item, Item::Macro(_))));
+    expr_block.block.stmts = stmts;
+    items
+        .into_iter()
+        .filter_map(|stmt| match stmt {
+            Stmt::Item(item) => Some(item),
+            _ => None,
+        })
+        .collect()
+}
+
 /// Convert a Rust code snippet into a program by wrapping it in a main method and other scaffolding.
 pub fn wrap_snippet(rs_source: &str) -> String {
     use std::fmt::Write;
@@ -621,6 +1364,39 @@ Ok(())
     wrapped_snippet
 }
 
+/// Convert an expression into a snippet that evaluates it for each line of stdin, with the
+/// line bound to `line` and its 1-based number to `line_no`, framed by the optional `begin`
+/// and `end` code. If `print` is specified and the expression returns a value, as judged
+/// by `is_last_stmt_unit`, the value is printed for each line.
+#[must_use]
+pub fn loop_snippet(
+    loop_expr: &str,
+    print: bool,
+    begin: Option<&str>,
+    end: Option<&str>,
+) -> String {
+    // Classify the expression as the block it will be evaluated in
+    let prints_value = print
+        && extract_ast(&format!("{{\n{loop_expr}\n}}")).is_ok_and(|expr| !is_last_stmt_unit(&expr));
+    debug_log!("prints_value={prints_value}");
+    let body = if prints_value {
+        format!("println!(\"{{}}\", {{\n{loop_expr}\n}});")
+    } else {
+        format!("{{\n{loop_expr}\n}};")
+    };
+    format!(
+        r"{}
+for (line_no, line) in std::io::stdin().lock().lines().enumerate() {{
+let line_no = line_no + 1;
+let line = line?;
+{body}
+}}
+{}",
+        begin.unwrap_or_default(),
+        end.unwrap_or_default()
+    )
+}
+
 pub fn write_source(to_rs_path: &PathBuf, rs_source: &str) -> Result<fs::File, BuildRunError> {
     let mut to_rs_file = OpenOptions::new()
         .write(true)
@@ -757,44 +1533,72 @@ pub fn display_dir_contents(path: &PathBuf) -> io::Result<()> {
     Ok(())
 }
 
-/// Format a Rust source file in situ using rustfmt.
-pub fn rustfmt(build_state: &BuildState) -> Result<(), BuildRunError> {
-    let target_rs_path = build_state.target_dir_path.clone();
-    let target_rs_path = target_rs_path.join(&build_state.source_name);
-    let source_path_str = target_rs_path
-        .to_str()
-        .ok_or(String::from("Error accessing path to source file"))?;
-
-    if Command::new("rustfmt").arg("--version").output().is_ok() {
-        // Run rustfmt on the source file
-        let mut command = Command::new("rustfmt");
-        command.arg("--verbose");
-        command.arg("--edition");
-        command.arg("2021");
-        command.arg(source_path_str);
-        let output = command.output().expect("Failed to run rustfmt");
-
-        if output.status.success() {
-            debug_log!("Successfully formatted {} with rustfmt.", source_path_str);
-            debug_log!(
-                "{}\n{}",
-                source_path_str,
-                String::from_utf8_lossy(&output.stdout)
-            );
-        } else {
-            debug_log!(
-                "Failed to format {} with rustfmt\n{}",
-                source_path_str,
-                String::from_utf8_lossy(&output.stderr)
-            );
-        }
-    } else {
+/// Format Rust source code with rustfmt, returning it unchanged if rustfmt is missing or fails.
+/// # Errors
+///
+/// Will return `Err` if the source can't be passed to rustfmt.
+pub fn rustfmt(build_state: &BuildState, rs_source: &str) -> Result<String, BuildRunError> {
+    // Use the same toolchain as the build, in case the script uses unstable syntax
+    let toolchain_arg = build_state
+        .build_options
+        .toolchain
+        .as_ref()
+        .map(|toolchain| format!("+{toolchain}"));
+
+    if Command::new("rustfmt")
+        .args(&toolchain_arg)
+        .arg("--version")
+        .output()
+        .is_err()
+    {
         log!(
             Verbosity::Quiet,
             "`rustfmt` not found. Please install it to use this script."
         );
+        return Ok(rs_source.to_string());
     }
-    Ok(())
+
+    // Format via stdin and stdout, so that the generated file is only written if it changes
+    let mut child = Command::new("rustfmt")
+        .args(&toolchain_arg)
+        .args(["--edition", "2021"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(rs_source.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+
+    if output.status.success() {
+        debug_log!(
+            "Successfully formatted {} with rustfmt.",
+            build_state.source_name
+        );
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        debug_log!(
+            "Failed to format {} with rustfmt\n{}",
+            build_state.source_name,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        Ok(rs_source.to_string())
+    }
+}
+
+/// Write a file only if its contents would change, leaving its modification time alone
+/// otherwise. Returns whether the file was written.
+/// # Errors
+///
+/// Will return `Err` if the file can't be written.
+pub fn write_if_changed(path: &Path, contents: &str) -> Result<bool, BuildRunError> {
+    if fs::read_to_string(path).is_ok_and(|existing| existing == contents) {
+        debug_log!("Leaving unchanged {path:?} alone");
+        return Ok(false);
+    }
+    fs::write(path, contents)?;
+    Ok(true)
 }
 
 /// Strip a set of curly braces off a Rust script, if present. This is intended to
@@ -866,43 +1670,18 @@ pub fn is_last_stmt_unit(expr: &Expr) -> bool {
             true
         }
         Expr::If(expr_if) => {
-            // let is_none = expr_if.else_branch.is_none();
-            // debug_log!("%%%%%%%%  Expr::If(expr_if) with expr_if.else_branch.is_none()={is_none}",);
-            // is_none
-            if let Some(last_stmt) = expr_if.then_branch.stmts.last() {
-                let mut then_is_unit_type = is_stmt_unit_type(last_stmt);
-                while then_is_unit_type {
-                    if let Some(ref stmt) = expr_if.else_branch {
-                        let expr1 = &*stmt.1;
-                        // The else branch expression may only be an If or Block expression,
-                        // not any of the other types of expression.
-                        match expr1 {
-                            Expr::Block(expr_block) => {
-                                then_is_unit_type = if let Some(last_stmt) =
-                                    expr_block.block.stmts.last()
-                                {
-                                    is_stmt_unit_type(last_stmt)
-                                } else {
-                                    debug_log!("%%%%%%%% Not if let Some(last_stmt) = expr_block.block.stmts.last()");
-                                    false
-                                };
-                            }
-                            Expr::If(expr_if) => {
-                                if let Some(last_stmt) = expr_if.then_branch.stmts.last() {
-                                    then_is_unit_type = is_stmt_unit_type(last_stmt);
-                                }
-                            }
-                            _ => panic!("Expected else branch expression to be If or Block"),
-                        }
-                    }
-                }
-                then_is_unit_type
-            } else {
-                debug_log!(
-                    "%%%%%%%% Not if let Some(last_stmt) = expr_if.then_branch.stmts.last()"
-                );
-                false
-            }
+            // An `if` without an `else` can only return unit. Otherwise the else branch
+            // may only be an If or Block expression, which we drill down into in turn.
+            let Some((_, ref else_expr)) = expr_if.else_branch else {
+                debug_log!("%%%%%%%% Expr::If without else");
+                return true;
+            };
+            expr_if
+                .then_branch
+                .stmts
+                .last()
+                .is_some_and(is_stmt_unit_type)
+                && is_last_stmt_unit(else_expr)
         }
         Expr::Block(expr_block) => {
             if let Some(last_stmt) = expr_block.block.stmts.last() {
@@ -912,6 +1691,10 @@ pub fn is_last_stmt_unit(expr: &Expr) -> bool {
                 false
             }
         }
+        Expr::Macro(expr_macro) => {
+            debug_log!("%%%%%%%% Expr::Macro(expr_macro)");
+            is_print_macro(&expr_macro.mac)
+        }
         _ => {
             debug_log!("%%%%%%%% Matches something else");
             false
@@ -937,8 +1720,8 @@ fn is_stmt_unit_type(last_stmt: &Stmt) -> bool {
         Stmt::Macro(m) => {
             let is_some = m.semi_token.is_some();
             debug_log!("%%%%%%%% Stmt::Macro({m:#?}), m.semi_token.is_some()={is_some}");
-            is_some
-        } // Macro with a semicolon returns unit
+            is_some || is_print_macro(&m.mac)
+        } // Macro with a semicolon returns unit, as do the print macros
         _ => {
             debug_log!("%%%%%%%% Something else, returning false");
             false
@@ -946,6 +1729,13 @@ fn is_stmt_unit_type(last_stmt: &Stmt) -> bool {
     }
 }
 
+/// Whether a macro is one of the standard printing macros, which return unit.
+fn is_print_macro(mac: &syn::Macro) -> bool {
+    mac.path.get_ident().is_some_and(|ident| {
+        ["print", "println", "eprint", "eprintln"].contains(&ident.to_string().as_str())
+    })
+}
+
 pub fn returns_unit(expr: &Expr) -> bool {
     // Check if the expression returns a unit value
     let is_unit_type = matches!(expr, Expr::Tuple(tuple) if tuple.elems.is_empty());
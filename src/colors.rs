@@ -1,3 +1,4 @@
+use crate::config::CONFIG;
 use crate::debug_log;
 use crate::log;
 use crate::logging::Verbosity;
@@ -5,6 +6,7 @@ use crate::shared;
 
 use lazy_static::lazy_static;
 use ratatui::crossterm::tty::IsTty;
+use serde::Deserialize;
 use std::{fmt::Display, str::FromStr};
 use strum::{Display, EnumIter, EnumString, IntoEnumIterator};
 use supports_color::Stream;
@@ -31,6 +33,9 @@ lazy_static! {
         if std::env::var("TEST_ENV").is_ok() || !std::io::stdout().is_tty() {
             return TermTheme::Dark;
         }
+        if let Some(theme) = CONFIG.color_theme {
+            return theme;
+        }
         let timeout = std::time::Duration::from_millis(100);
         debug_log!("Check terminal background color");
         let theme = termbg::theme(timeout);
@@ -68,7 +73,8 @@ pub enum ColorSupport {
     None,
 }
 
-#[derive(Debug, EnumString, Display, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, EnumString, Display, PartialEq)]
+#[serde(rename_all = "lowercase")]
 pub enum TermTheme {
     Light,
     Dark,
@@ -0,0 +1,92 @@
+use crate::cache::cached_script_stems;
+use crate::cmd_args::Cli;
+use crate::errors::BuildRunError;
+use crate::RS_SUFFIX;
+
+use clap::CommandFactory;
+use clap_complete::engine::{CompletionCandidate, PathCompleter, ValueCompleter};
+use clap_complete::env::Shells;
+use clap_complete::CompleteEnv;
+use std::ffi::OsStr;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// The environment variable through which the shell asks `rs_script` for completions.
+const COMPLETE_VAR: &str = "COMPLETE";
+
+/// The shells that the `completions` subcommand can generate completions for.
+pub const COMPLETION_SHELLS: [&str; 3] = ["bash", "zsh", "fish"];
+
+/// If the shell is asking for completions, print them and exit, otherwise return so that
+/// the command line can be processed as usual. Must run before anything is written to stdout.
+pub fn complete_if_requested() {
+    CompleteEnv::with_factory(Cli::command)
+        .var(COMPLETE_VAR)
+        .complete();
+}
+
+/// Print the completion script for the named shell, to be sourced from the shell's startup
+/// file. The script calls back into `rs_script` for the candidates, so that flags stay in step
+/// with the installed version and cached scripts are offered as they appear.
+/// # Errors
+///
+/// Will return `Err` if the shell is not supported or the script can't be written.
+pub fn completions(shell: &str) -> Result<(), BuildRunError> {
+    let completer = std::env::current_exe()?;
+    write_registration(shell, &completer.to_string_lossy(), &mut io::stdout())
+}
+
+/// Write the completion script for the named shell, calling `completer` for the candidates.
+/// # Errors
+///
+/// Will return `Err` if the shell is not supported or the script can't be written.
+pub fn write_registration(
+    shell: &str,
+    completer: &str,
+    buf: &mut dyn Write,
+) -> Result<(), BuildRunError> {
+    let shells = Shells::builtins();
+    let env_completer = shells
+        .completer(shell)
+        .filter(|_| COMPLETION_SHELLS.contains(&shell))
+        .ok_or_else(|| BuildRunError::Command(format!("Unsupported shell {shell}")))?;
+    let name = Cli::command().get_name().to_string();
+    env_completer.write_registration(COMPLETE_VAR, &name, &name, completer, buf)?;
+    Ok(())
+}
+
+/// Completion candidates for a script name: `.rs` files and directories relative to the
+/// current directory, followed by scripts that already have a generated project in the cache.
+#[must_use]
+pub fn complete_script(current: &OsStr) -> Vec<CompletionCandidate> {
+    let cached_stems = cached_script_stems().unwrap_or_default();
+    let current_dir = std::env::current_dir().unwrap_or_default();
+    script_candidates(current, &current_dir, &cached_stems)
+}
+
+/// Completion candidates for a script name in the given directory, plus the cached scripts
+/// with the given stems that match what has been typed so far.
+#[must_use]
+pub fn script_candidates(
+    current: &OsStr,
+    current_dir: &Path,
+    cached_stems: &[String],
+) -> Vec<CompletionCandidate> {
+    let mut candidates = PathCompleter::any()
+        .current_dir(current_dir)
+        .filter(|path| path.is_dir() || path.extension().is_some_and(|ext| ext == "rs"))
+        .complete(current);
+
+    let current = current.to_string_lossy();
+    for stem in cached_stems {
+        let script = format!("{stem}{RS_SUFFIX}");
+        if script.starts_with(current.as_ref())
+            && !candidates
+                .iter()
+                .any(|candidate| candidate.get_value() == script.as_str())
+        {
+            candidates.push(CompletionCandidate::new(script).help(Some("cached".into())));
+        }
+    }
+    candidates
+}
@@ -0,0 +1,292 @@
+use crate::cmd_args::{parse_duration, Cli};
+use crate::colors::TermTheme;
+use crate::debug_log;
+use crate::errors::BuildRunError;
+use crate::knowledge::InferenceRule;
+use crate::log;
+use crate::logging::Verbosity;
+use crate::PACKAGE_NAME;
+
+use home::home_dir;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Deserializer};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+/// Name of the user-level configuration file, found under `~/.config/rs-script/`.
+pub const CONFIG_FILE: &str = "config.toml";
+/// Name of the project-local configuration file, found in the current directory or one of its ancestors.
+pub const PROJECT_CONFIG_FILE: &str = ".rs-script.toml";
+/// Default number of entries kept in the REPL history file.
+pub const DEFAULT_HISTORY_SIZE: usize = 25;
+
+lazy_static! {
+    /// The merged user-level and project-local configuration, loaded on first use.
+    pub static ref CONFIG: Config = {
+        match load_config() {
+            Ok(config) => config,
+            Err(err) => {
+                log!(Verbosity::Quiet, "Ignoring invalid configuration: {err}");
+                Config::default()
+            }
+        }
+    };
+}
+
+/// Default options, paths and editor settings from the configuration files.
+/// Any setting left out falls back to the built-in default, and command-line flags
+/// take precedence over all of them.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Config {
+    /// Default verbosity: `quiet`, `normal` or `verbose`.
+    pub verbosity: Option<Verbosity>,
+    /// Display timings by default.
+    pub timings: Option<bool>,
+    /// Allow multiple main methods by default.
+    pub multimain: Option<bool>,
+    /// Root directory for generated projects in place of the system temporary directory.
+    /// Only taken from the user-level configuration file, as are the other paths, the editor,
+    /// `[sandbox]` and `[[inference]]`.
+    pub cache_dir: Option<PathBuf>,
+    /// REPL history file in place of `$CARGO_HOME/rs_eval_hist.txt`.
+    pub history_file: Option<PathBuf>,
+    /// Number of entries to keep in the REPL history.
+    pub history_size: Option<usize>,
+    /// Editor command line, e.g. `"code --wait"`, in place of the `VISUAL` / `EDITOR` lookup.
+    pub editor: Option<String>,
+    /// Terminal colour theme: `light` or `dark`, in place of background detection.
+    pub color_theme: Option<TermTheme>,
+    /// Default cargo profile, e.g. `release`.
+    pub profile: Option<String>,
+    /// Build all generated projects in one shared cargo target directory by default.
+    pub shared_target: Option<bool>,
+    /// Dependency resolution cache in place of `$CARGO_HOME/rs_deps_cache.toml`.
+    pub deps_cache_file: Option<PathBuf>,
+    /// How long a cached dependency version is trusted before it is looked up again, e.g. `7d`.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub deps_cache_ttl: Option<Duration>,
+    /// Limits for scripts run with `--sandbox`, in a `[sandbox]` table. Only taken from the
+    /// user-level configuration file.
+    pub sandbox: SandboxConfig,
+    /// Extra rules for the features and companion crates that inferred dependencies need,
+    /// in `[[inference]]` tables.
+    pub inference: Vec<InferenceRule>,
+}
+
+/// Limits for scripts run with `--sandbox`. Any limit left out falls back to the default
+/// in the `sandbox` module.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct SandboxConfig {
+    /// CPU time limit in seconds.
+    pub cpu_secs: Option<u64>,
+    /// Address space limit in MiB.
+    pub memory_mb: Option<u64>,
+    /// Limit in MiB on the size of any file written.
+    pub file_size_mb: Option<u64>,
+    /// Limit on the number of processes the script may have running. Where user namespaces are
+    /// unavailable, the user's other processes count towards it too.
+    pub processes: Option<u64>,
+    /// Allow network access by default, as with `--allow-net`.
+    pub allow_net: Option<bool>,
+}
+
+impl SandboxConfig {
+    /// Overlay `other` on these limits: any limit present in `other` wins.
+    #[must_use]
+    pub fn merge(self, other: SandboxConfig) -> SandboxConfig {
+        SandboxConfig {
+            cpu_secs: other.cpu_secs.or(self.cpu_secs),
+            memory_mb: other.memory_mb.or(self.memory_mb),
+            file_size_mb: other.file_size_mb.or(self.file_size_mb),
+            processes: other.processes.or(self.processes),
+            allow_net: other.allow_net.or(self.allow_net),
+        }
+    }
+}
+
+impl Config {
+    /// Overlay `other` on this configuration: any setting present in `other` wins.
+    #[must_use]
+    pub fn merge(self, other: Config) -> Config {
+        Config {
+            verbosity: other.verbosity.or(self.verbosity),
+            timings: other.timings.or(self.timings),
+            multimain: other.multimain.or(self.multimain),
+            cache_dir: other.cache_dir.or(self.cache_dir),
+            history_file: other.history_file.or(self.history_file),
+            history_size: other.history_size.or(self.history_size),
+            editor: other.editor.or(self.editor),
+            color_theme: other.color_theme.or(self.color_theme),
+            profile: other.profile.or(self.profile),
+            shared_target: other.shared_target.or(self.shared_target),
+            deps_cache_file: other.deps_cache_file.or(self.deps_cache_file),
+            deps_cache_ttl: other.deps_cache_ttl.or(self.deps_cache_ttl),
+            sandbox: self.sandbox.merge(other.sandbox),
+            inference: self.inference.into_iter().chain(other.inference).collect(),
+        }
+    }
+
+    /// Keep only the presentation and build defaults that a project-local configuration file
+    /// may set, so that a checked-out project can't choose where files are written, the
+    /// editor that is run, the companion crates added to scripts, or loosen the limits that
+    /// `--sandbox` puts on its scripts.
+    #[must_use]
+    pub fn project_local(self, path: &Path) -> Config {
+        let ignored = [
+            ("cache-dir", self.cache_dir.is_some()),
+            ("history-file", self.history_file.is_some()),
+            ("editor", self.editor.is_some()),
+            ("deps-cache-file", self.deps_cache_file.is_some()),
+            ("[sandbox]", self.sandbox != SandboxConfig::default()),
+            ("[[inference]]", !self.inference.is_empty()),
+        ]
+        .into_iter()
+        .filter_map(|(setting, present)| present.then_some(setting))
+        .collect::<Vec<_>>();
+        if !ignored.is_empty() {
+            log!(
+                Verbosity::Quiet,
+                "Ignoring {} in project-local configuration file {path:?}: only allowed in the user-level file",
+                ignored.join(", ")
+            );
+        }
+        Config {
+            verbosity: self.verbosity,
+            timings: self.timings,
+            multimain: self.multimain,
+            history_size: self.history_size,
+            color_theme: self.color_theme,
+            profile: self.profile,
+            shared_target: self.shared_target,
+            deps_cache_ttl: self.deps_cache_ttl,
+            ..Config::default()
+        }
+    }
+
+    /// Fill in any command-line options that were not specified with the configured defaults.
+    pub fn apply_to_cli(&self, args: &mut Cli) {
+        if !args.verbose && !args.quiet {
+            match self.verbosity {
+                Some(Verbosity::Verbose) => args.verbose = true,
+                Some(Verbosity::Quiet) => args.quiet = true,
+                Some(Verbosity::Normal) | None => (),
+            }
+        }
+        if !args.timings && !args.no_timings {
+            args.timings = self.timings.unwrap_or(false);
+        }
+        if !args.multimain && !args.no_multimain {
+            args.multimain = self.multimain.unwrap_or(false);
+        }
+        if !args.shared_target && !args.no_shared_target {
+            args.shared_target = self.shared_target.unwrap_or(false);
+        }
+        if !args.release && args.profile.is_none() {
+            args.profile.clone_from(&self.profile);
+        }
+    }
+}
+
+/// Parse the contents of a configuration file.
+/// # Errors
+///
+/// Will return `Err` if the contents are not valid TOML or contain an invalid setting.
+pub fn parse_config(toml_str: &str) -> Result<Config, BuildRunError> {
+    let mut config: Config = toml::from_str(toml_str)?;
+    config.cache_dir = config.cache_dir.map(|path| expand_tilde(&path));
+    config.history_file = config.history_file.map(|path| expand_tilde(&path));
+    config.deps_cache_file = config.deps_cache_file.map(|path| expand_tilde(&path));
+    Ok(config)
+}
+
+/// Deserialize a duration given in the same form as on the command line, e.g. `12h` or `7d`.
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let duration = String::deserialize(deserializer)?;
+    parse_duration(&duration)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+/// Load the user-level configuration file, then overlay any project-local configuration file.
+/// # Errors
+///
+/// Will return `Err` if either file exists but can't be read or parsed.
+pub fn load_config() -> Result<Config, BuildRunError> {
+    let mut config = Config::default();
+    if let Some(path) = user_config_path() {
+        config = read_config(&path)?;
+    }
+    if let Some(path) = project_config_path() {
+        config = config.merge(read_config(&path)?.project_local(&path));
+    }
+    debug_log!("config={config:#?}");
+    Ok(config)
+}
+
+/// Read and parse a configuration file.
+/// # Errors
+///
+/// Will return `Err` if the file can't be read or parsed.
+fn read_config(path: &Path) -> Result<Config, BuildRunError> {
+    debug_log!("Loading configuration from {path:?}");
+    let contents = fs::read_to_string(path)?;
+    parse_config(&contents).map_err(|err| {
+        BuildRunError::Command(format!("Error in configuration file {path:?}: {err}"))
+    })
+}
+
+/// Path of the user-level configuration file, if it exists.
+pub fn user_config_path() -> Option<PathBuf> {
+    let config_dir = match std::env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => home_dir()?.join(".config"),
+    };
+    let path = config_dir.join(PACKAGE_NAME).join(CONFIG_FILE);
+    path.is_file().then_some(path)
+}
+
+/// Path of the nearest project-local configuration file, searching up from the current directory.
+pub fn project_config_path() -> Option<PathBuf> {
+    let current_dir = std::env::current_dir().ok()?;
+    current_dir
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_FILE))
+        .find(|path| path.is_file())
+}
+
+/// Expand a leading `~` in a configured path to the user's home directory.
+fn expand_tilde(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Edit a file with the configured editor command if there is one, otherwise via the `edit` crate.
+/// # Errors
+///
+/// Will return `Err` if the editor can't be launched or exits with an error.
+pub fn edit_file(path: &Path) -> Result<(), BuildRunError> {
+    let Some(ref editor) = CONFIG.editor else {
+        return Ok(edit::edit_file(path)?);
+    };
+    let mut words = editor.split_whitespace();
+    let program = words
+        .next()
+        .ok_or_else(|| BuildRunError::Command("Empty editor command in configuration".into()))?;
+    let status = Command::new(program).args(words).arg(path).status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(BuildRunError::Command(format!(
+            "Editor {editor} exited with {status}"
+        )))
+    }
+}
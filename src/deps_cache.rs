@@ -0,0 +1,296 @@
+use crate::cache::format_age;
+use crate::cmd_args::DepsCacheCommand;
+use crate::code_utils::write_if_changed;
+use crate::colors::{nu_resolve_style, MessageLevel};
+use crate::config::CONFIG;
+use crate::debug_log;
+use crate::errors::BuildRunError;
+use crate::log;
+use crate::logging::Verbosity;
+use crate::manifest::{cargo_search, CommandRunner, RealCommandRunner};
+use crate::shared::cargo_home;
+
+use semver::VersionReq;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Default name of the dependency resolution cache, found in `$CARGO_HOME`.
+pub const DEPS_CACHE_FILE: &str = "rs_deps_cache.toml";
+/// How long a cached version is trusted by default before it is looked up again.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(7 * 86_400);
+
+/// The version found for an inferred dependency, and the crate's registered name, which
+/// may be hyphenated where scripts use an underscore.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CachedDep {
+    pub name: String,
+    pub version: String,
+    /// When the version was found, in seconds since the Unix epoch.
+    pub resolved: u64,
+    /// Whether the version was pinned by the user rather than found by a search.
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+impl CachedDep {
+    #[must_use]
+    pub fn resolved_time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.resolved)
+    }
+
+    /// Whether the version may be used without looking it up again.
+    #[must_use]
+    pub fn is_fresh(&self, ttl: Duration, now: SystemTime) -> bool {
+        self.pinned
+            || now
+                .duration_since(self.resolved_time())
+                .is_ok_and(|age| age < ttl)
+    }
+}
+
+/// The persistent cache of versions found for inferred dependencies, keyed on the crate
+/// name as written in scripts, i.e. with underscores.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct DepsCache {
+    #[serde(default)]
+    pub crates: BTreeMap<String, CachedDep>,
+}
+
+/// The key of a crate in the cache, so that `serde-json` and `serde_json` are the same crate.
+#[must_use]
+pub fn cache_key(crate_name: &str) -> String {
+    crate_name.replace('-', "_")
+}
+
+impl DepsCache {
+    /// Load the cache from a file, which need not exist yet.
+    /// # Errors
+    ///
+    /// Will return `Err` if the file exists but can't be read or parsed.
+    pub fn load(path: &Path) -> Result<Self, BuildRunError> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Save the cache to a file, creating its directory if need be.
+    /// # Errors
+    ///
+    /// Will return `Err` if the file can't be written.
+    pub fn save(&self, path: &Path) -> Result<(), BuildRunError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_if_changed(path, &toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// The cached version of a crate if it is pinned or was found within the `ttl`.
+    #[must_use]
+    pub fn lookup(&self, crate_name: &str, ttl: Duration, now: SystemTime) -> Option<&CachedDep> {
+        self.crates
+            .get(&cache_key(crate_name))
+            .filter(|cached| cached.is_fresh(ttl, now))
+    }
+
+    /// Record the version found for a crate, unless the crate is pinned.
+    pub fn insert(&mut self, crate_name: &str, name: &str, version: &str, now: SystemTime) {
+        let key = cache_key(crate_name);
+        if self.crates.get(&key).is_some_and(|cached| cached.pinned) {
+            return;
+        }
+        self.crates.insert(
+            key,
+            CachedDep {
+                name: name.to_string(),
+                version: version.to_string(),
+                resolved: epoch_secs(now),
+                pinned: false,
+            },
+        );
+    }
+
+    /// Pin a crate to a version, or if none is given, to its cached version. A crate that is
+    /// not cached is first looked up with cargo search, for its registered name, which may be
+    /// hyphenated where scripts use an underscore, and its latest version.
+    /// # Errors
+    ///
+    /// Will return `Err` if the version is not a valid version requirement, or if the crate
+    /// is not cached and cargo search can't find it.
+    pub fn pin<R: CommandRunner>(
+        &mut self,
+        runner: &R,
+        crate_name: &str,
+        version: Option<&str>,
+        now: SystemTime,
+    ) -> Result<&CachedDep, BuildRunError> {
+        if let Some(version) = version {
+            VersionReq::parse(version).map_err(|err| {
+                BuildRunError::Command(format!("Invalid version {version}: {err}"))
+            })?;
+        }
+        let key = cache_key(crate_name);
+        let (name, found_version) = match self.crates.get(&key) {
+            Some(cached) => (cached.name.clone(), cached.version.clone()),
+            None => cargo_search(runner, crate_name).map_err(|err| {
+                BuildRunError::Command(format!("Couldn't find crate {crate_name} to pin: {err}"))
+            })?,
+        };
+        let pinned = CachedDep {
+            name,
+            version: version.map_or(found_version, ToString::to_string),
+            resolved: epoch_secs(now),
+            pinned: true,
+        };
+        self.crates.insert(key.clone(), pinned);
+        Ok(&self.crates[&key])
+    }
+
+    /// Remove the named crates from the cache, returning the names of those that were cached.
+    pub fn forget(&mut self, crate_names: &[String]) -> Vec<String> {
+        crate_names
+            .iter()
+            .filter(|crate_name| self.crates.remove(&cache_key(crate_name)).is_some())
+            .cloned()
+            .collect()
+    }
+}
+
+/// The configured dependency resolution cache file, or the default one in `$CARGO_HOME`.
+/// # Errors
+///
+/// Will return `Err` if none is configured and `$CARGO_HOME` can't be resolved.
+pub fn deps_cache_path() -> Result<PathBuf, BuildRunError> {
+    match CONFIG.deps_cache_file {
+        Some(ref path) => Ok(path.clone()),
+        None => Ok(cargo_home()?.join(DEPS_CACHE_FILE)),
+    }
+}
+
+/// How long a cached version is trusted before it is looked up again.
+#[must_use]
+pub fn ttl() -> Duration {
+    CONFIG.deps_cache_ttl.unwrap_or(DEFAULT_TTL)
+}
+
+fn epoch_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Run a `deps cache` subcommand.
+/// # Errors
+///
+/// Will return `Err` if the cache can't be read or written, or the subcommand fails.
+pub fn run_command(command: &DepsCacheCommand) -> Result<(), BuildRunError> {
+    let path = deps_cache_path()?;
+    let mut deps_cache = DepsCache::load(&path)?;
+    let now = SystemTime::now();
+    match command {
+        DepsCacheCommand::List => {
+            list(&deps_cache, &path, now);
+            return Ok(());
+        }
+        DepsCacheCommand::Refresh { crates } => refresh(&mut deps_cache, crates, now),
+        DepsCacheCommand::Pin {
+            crate_name,
+            version,
+        } => {
+            let pinned = deps_cache.pin(&RealCommandRunner, crate_name, version.as_deref(), now)?;
+            log!(
+                Verbosity::Normal,
+                "Pinned {} = \"{}\"",
+                pinned.name,
+                pinned.version
+            );
+        }
+        DepsCacheCommand::Forget { crates, all } => {
+            let forgotten = if *all {
+                let forgotten = deps_cache.crates.keys().cloned().collect();
+                deps_cache.crates.clear();
+                forgotten
+            } else {
+                deps_cache.forget(crates)
+            };
+            for crate_name in crates.iter().filter(|name| !forgotten.contains(name)) {
+                log!(Verbosity::Normal, "{crate_name} is not cached");
+            }
+            log!(Verbosity::Normal, "Forgot {} crate(s)", forgotten.len());
+        }
+    }
+    debug_log!("Saving dependency cache to {path:?}");
+    deps_cache.save(&path)
+}
+
+/// List the cached crates with their versions, ages and whether they are pinned or due to
+/// be looked up again.
+fn list(deps_cache: &DepsCache, path: &Path, now: SystemTime) {
+    if deps_cache.crates.is_empty() {
+        log!(
+            Verbosity::Quiet,
+            "No cached dependencies in {}",
+            path.display()
+        );
+        return;
+    }
+    let ttl = ttl();
+    for cached in deps_cache.crates.values() {
+        let status = if cached.pinned {
+            "pinned"
+        } else if cached.is_fresh(ttl, now) {
+            ""
+        } else {
+            "expired"
+        };
+        log!(
+            Verbosity::Quiet,
+            "{} {:<12} {:>10}  {status}",
+            nu_resolve_style(MessageLevel::Emphasis).paint(format!("{:<24}", cached.name)),
+            cached.version,
+            format_age(now, cached.resolved_time()),
+        );
+    }
+    log!(
+        Verbosity::Normal,
+        "{} cached crate(s) in {}",
+        deps_cache.crates.len(),
+        path.display()
+    );
+}
+
+/// Look up the named crates, or all cached crates that aren't pinned, with cargo search.
+fn refresh(deps_cache: &mut DepsCache, crates: &[String], now: SystemTime) {
+    let crate_names: Vec<String> = if crates.is_empty() {
+        deps_cache
+            .crates
+            .iter()
+            .filter(|(_, cached)| !cached.pinned)
+            .map(|(key, _)| key.clone())
+            .collect()
+    } else {
+        crates.to_vec()
+    };
+    for crate_name in &crate_names {
+        let cached = deps_cache.crates.get(&cache_key(crate_name));
+        if cached.is_some_and(|cached| cached.pinned) {
+            log!(
+                Verbosity::Normal,
+                "{crate_name} is pinned: forget it first to look it up again"
+            );
+            continue;
+        }
+        // Search for the crate chosen for this name before, if any
+        let search_name =
+            cached.map_or_else(|| cache_key(crate_name), |cached| cached.name.clone());
+        match cargo_search(&RealCommandRunner, &search_name) {
+            Ok((name, version)) => deps_cache.insert(crate_name, &name, &version, now),
+            Err(err) => log!(Verbosity::Quiet, "Couldn't refresh {crate_name}: {err}"),
+        }
+    }
+}
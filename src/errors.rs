@@ -1,4 +1,8 @@
+use crate::picker::CrateCandidate;
+use crate::sandbox::Violation;
+
 use std::ffi::OsString;
+use std::time::Duration;
 use std::{error::Error, io};
 use toml::de::Error as TomlDeError;
 use toml::ser::Error as TomlSerError;
@@ -17,9 +21,46 @@ pub enum BuildRunError {
     TomlDe(TomlDeError),           // For TOML deserialization errors
     TomlSer(TomlSerError),         // For TOML serialization errors
     Toml(cargo_toml::Error),       // For cargo_toml errors
+    ScriptExit(i32),               // For a script exiting with a failure status code
+    ScriptSignal(i32),             // For a script terminated by a signal
+    Timeout(Duration),             // For a script killed for exceeding --timeout
+    Sandbox(String),               // For a sandbox that can't be set up
+    SandboxViolation(Violation),   // For a sandboxed script exceeding one of its limits
+    UnresolvedCrate(String, Vec<CrateCandidate>), // For an inferred crate that cargo search only found near misses for
+}
+
+impl BuildRunError {
+    /// The status code for rs-script to exit with when the script it ran failed: the
+    /// script's own status code, 128 + the signal number if it was terminated by a signal,
+    /// as in the shell, or 124 if it timed out, as with the `timeout` command.
+    #[must_use]
+    pub fn exit_code(&self) -> Option<i32> {
+        match self {
+            BuildRunError::ScriptExit(code) => Some(*code),
+            BuildRunError::ScriptSignal(signal) => Some(128 + signal),
+            BuildRunError::Timeout(_) => Some(124),
+            BuildRunError::SandboxViolation(violation) => Some(128 + violation.signal()),
+            _ => None,
+        }
+    }
 }
 
-impl BuildRunError {}
+/// The conventional name of a signal number, for reporting.
+fn signal_name(signal: i32) -> String {
+    match signal {
+        1 => "SIGHUP".to_string(),
+        2 => "SIGINT".to_string(),
+        3 => "SIGQUIT".to_string(),
+        4 => "SIGILL".to_string(),
+        6 => "SIGABRT".to_string(),
+        8 => "SIGFPE".to_string(),
+        9 => "SIGKILL".to_string(),
+        11 => "SIGSEGV".to_string(),
+        13 => "SIGPIPE".to_string(),
+        15 => "SIGTERM".to_string(),
+        _ => format!("signal {signal}"),
+    }
+}
 
 impl From<io::Error> for BuildRunError {
     fn from(err: io::Error) -> Self {
@@ -70,7 +111,8 @@ impl std::fmt::Display for BuildRunError {
             BuildRunError::ClapError(e) => write!(f, "{e:?}"),
             BuildRunError::Command(s)
             | BuildRunError::FromStr(s)
-            | BuildRunError::NoneOption(s) => {
+            | BuildRunError::NoneOption(s)
+            | BuildRunError::Sandbox(s) => {
                 for line in s.lines() {
                     writeln!(f, "{line}")?;
                 }
@@ -85,6 +127,27 @@ impl std::fmt::Display for BuildRunError {
             BuildRunError::TomlDe(e) => write!(f, "{e:?}"),
             BuildRunError::TomlSer(e) => write!(f, "{e:?}"),
             BuildRunError::Toml(e) => write!(f, "{e:?}"),
+            BuildRunError::ScriptExit(code) => write!(f, "Script exited with status {code}"),
+            BuildRunError::ScriptSignal(signal) => {
+                write!(f, "Script terminated by {}", signal_name(*signal))
+            }
+            BuildRunError::Timeout(timeout) => {
+                write!(f, "Script timed out after {timeout:?} and was killed")
+            }
+            BuildRunError::SandboxViolation(violation) => write!(f, "{violation}"),
+            BuildRunError::UnresolvedCrate(crate_name, candidates) => {
+                writeln!(
+                    f,
+                    "Cargo search found no crate named {crate_name}, only these candidates:"
+                )?;
+                for candidate in candidates {
+                    writeln!(f, "    {candidate}")?;
+                }
+                write!(
+                    f,
+                    "Specify the right one in a toml block, or run interactively to choose one"
+                )
+            }
         }
     }
 }
@@ -97,7 +160,8 @@ impl Error for BuildRunError {
             // underlying type already implements the `Error` trait.
             BuildRunError::Command(ref _e)
             | BuildRunError::FromStr(ref _e)
-            | BuildRunError::NoneOption(ref _e) => Some(self),
+            | BuildRunError::NoneOption(ref _e)
+            | BuildRunError::Sandbox(ref _e) => Some(self),
             BuildRunError::ClapError(ref e) => Some(e),
             BuildRunError::Io(ref e) => Some(e),
             BuildRunError::OsString(ref _o) => Some(self),
@@ -105,7 +169,12 @@ impl Error for BuildRunError {
             BuildRunError::TomlDe(ref e) => Some(e),
             BuildRunError::TomlSer(ref e) => Some(e),
             BuildRunError::Toml(ref e) => Some(e),
-            BuildRunError::Cancelled => Some(self),
+            BuildRunError::Cancelled
+            | BuildRunError::ScriptExit(_)
+            | BuildRunError::ScriptSignal(_)
+            | BuildRunError::Timeout(_)
+            | BuildRunError::SandboxViolation(_)
+            | BuildRunError::UnresolvedCrate(..) => Some(self),
         }
     }
 }
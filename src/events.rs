@@ -0,0 +1,174 @@
+use crate::logging::{global_message_format, MessageFormat};
+
+use serde::Serialize;
+use serde_json::Value;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait for the rest of a finished script's output.
+const OUTPUT_WAIT: Duration = Duration::from_secs(1);
+
+/// A pipeline event, written as a line of JSON in `--message-format json` mode. The
+/// `phase` field says which phase of the pipeline the event reports on.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "phase", rename_all = "kebab-case")]
+pub enum Event {
+    /// The script source was read and parsed.
+    Parse {
+        source_path: PathBuf,
+        /// Whether the source parsed into a syntax tree, as opposed to falling back on
+        /// source code analysis.
+        parsed: bool,
+        elapsed_ms: u128,
+    },
+    /// The manifest was merged from the toml block and the inferred dependencies.
+    Manifest {
+        inferred_deps: Vec<String>,
+        dependencies: Vec<String>,
+        elapsed_ms: u128,
+    },
+    /// An inferred dependency was looked up in the dependency resolution cache.
+    DepsCache {
+        crate_name: String,
+        found: Option<String>,
+        version: Option<String>,
+        /// Whether the cached version was pinned with `deps cache pin`.
+        pinned: bool,
+        elapsed_ms: u128,
+    },
+    /// An inferred dependency was looked up in the local registry index and download cache.
+    LocalRegistry {
+        crate_name: String,
+        found: Option<String>,
+        version: Option<String>,
+        elapsed_ms: u128,
+    },
+    /// Cargo search was used to find the latest version of an inferred dependency.
+    CargoSearch {
+        crate_name: String,
+        /// The name of the crate found, which may be hyphenated where the script used an
+        /// underscore, and its latest version.
+        found: Option<String>,
+        version: Option<String>,
+        elapsed_ms: u128,
+    },
+    /// The Rust source and Cargo.toml of the script's project were generated.
+    Generate {
+        target_dir_path: PathBuf,
+        cargo_toml_path: PathBuf,
+        skipped: bool,
+        elapsed_ms: u128,
+    },
+    /// The project was built, checked or linted with cargo.
+    Build {
+        command: String,
+        target_path: PathBuf,
+        skipped: bool,
+        success: bool,
+        /// The compiler messages reported by cargo, in cargo's JSON format.
+        diagnostics: Vec<Value>,
+        elapsed_ms: u128,
+    },
+    /// A line of output from the test harness in `test` mode.
+    TestOutput { line: String },
+    /// A line that the script wrote to stdout while it ran.
+    RunOutput { line: String },
+    /// The built script was run.
+    Run {
+        target_path: PathBuf,
+        success: bool,
+        exit_status: Option<i32>,
+        /// The signal that terminated the script, if any.
+        signal: Option<i32>,
+        /// Whether the script was killed for exceeding `--timeout`.
+        timed_out: bool,
+        elapsed_ms: u128,
+    },
+}
+
+/// Whether pipeline events are to be emitted, i.e. `--message-format json` was specified.
+#[must_use]
+pub fn json_enabled() -> bool {
+    global_message_format() == MessageFormat::Json
+}
+
+/// Write the event as a line of JSON to stdout if `--message-format json` was specified.
+pub fn emit(event: &Event) {
+    if !json_enabled() {
+        return;
+    }
+    match serde_json::to_string(event) {
+        Ok(json) => println!("{json}"),
+        Err(err) => eprintln!("Error serializing event {event:?}: {err}"),
+    }
+}
+
+/// Relays a running script's stdout as `run-output` events in `--message-format json` mode,
+/// so that the event stream stays all JSON.
+pub struct OutputEvents(Receiver<()>);
+
+impl OutputEvents {
+    /// Have the command's stdout piped for relaying if events are to be emitted.
+    pub fn pipe(command: &mut Command) {
+        if json_enabled() {
+            command.stdout(Stdio::piped());
+        }
+    }
+
+    /// Start relaying the child's stdout, if it is piped.
+    #[must_use]
+    pub fn start(child: &mut Child) -> Option<Self> {
+        let stdout = child.stdout.take()?;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).split(b'\n') {
+                let Ok(line) = line else { break };
+                let line = String::from_utf8_lossy(&line).into_owned();
+                emit(&Event::RunOutput { line });
+            }
+            let _ = sender.send(());
+        });
+        Some(OutputEvents(receiver))
+    }
+
+    /// Wait for the rest of a finished script's output, unless its stdout is still open
+    /// shortly after, e.g. in a process that the script started.
+    pub fn finish(self) {
+        let _ = self.0.recv_timeout(OUTPUT_WAIT);
+    }
+}
+
+/// Milliseconds elapsed since the start of a phase.
+#[must_use]
+pub fn elapsed_ms(start: &Instant) -> u128 {
+    start.elapsed().as_millis()
+}
+
+/// The compiler messages from the lines of JSON output by cargo with `--message-format json`.
+#[must_use]
+pub fn compiler_messages(cargo_stdout: &str) -> Vec<Value> {
+    cargo_stdout
+        .lines()
+        .filter_map(cargo_message)
+        .filter_map(compiler_message)
+        .collect()
+}
+
+/// Parse a line of JSON output by cargo with `--message-format json`, as opposed to a line
+/// of output from a program that cargo runs, such as the test harness.
+#[must_use]
+pub fn cargo_message(line: &str) -> Option<Value> {
+    serde_json::from_str::<Value>(line)
+        .ok()
+        .filter(|message| message["reason"].is_string())
+}
+
+/// The compiler message that a cargo message carries, if any.
+#[must_use]
+pub fn compiler_message(mut cargo_message: Value) -> Option<Value> {
+    (cargo_message["reason"] == "compiler-message").then(|| cargo_message["message"].take())
+}
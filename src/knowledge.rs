@@ -0,0 +1,267 @@
+use crate::config::CONFIG;
+use crate::deps_cache::cache_key;
+use crate::errors::BuildRunError;
+use crate::log;
+use crate::logging::Verbosity;
+
+use cargo_toml::{Dependency, DependencyDetail};
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+/// The built-in knowledge base: each usage, the crate it belongs to, the features of the
+/// crate that it needs, and any other crates that it needs.
+const BUILT_IN_RULES: &[(&str, &str, &[&str], &[&str])] = &[
+    (
+        "#[tokio::main]",
+        "tokio",
+        &["macros", "rt-multi-thread"],
+        &[],
+    ),
+    ("#[tokio::test]", "tokio", &["macros", "rt"], &[]),
+    ("tokio::fs", "tokio", &["fs"], &[]),
+    ("tokio::io", "tokio", &["io-util", "io-std"], &[]),
+    ("tokio::net", "tokio", &["net"], &[]),
+    ("tokio::process", "tokio", &["process"], &[]),
+    ("tokio::signal", "tokio", &["signal"], &[]),
+    ("tokio::spawn", "tokio", &["rt"], &[]),
+    ("tokio::sync", "tokio", &["sync"], &[]),
+    ("tokio::task", "tokio", &["rt"], &[]),
+    ("tokio::time", "tokio", &["time"], &[]),
+    ("#[async_std::main]", "async-std", &["attributes"], &[]),
+    ("#[derive(Serialize)]", "serde", &["derive"], &[]),
+    ("#[derive(Deserialize)]", "serde", &["derive"], &[]),
+    ("#[serde_as]", "serde_with", &["macros"], &["serde"]),
+    ("#[derive(Parser)]", "clap", &["derive"], &[]),
+    ("#[derive(Args)]", "clap", &["derive"], &[]),
+    ("#[derive(Subcommand)]", "clap", &["derive"], &[]),
+    ("#[derive(ValueEnum)]", "clap", &["derive"], &[]),
+    ("#[derive(EnumIter)]", "strum", &["derive"], &[]),
+    ("#[derive(EnumString)]", "strum", &["derive"], &[]),
+    ("#[derive(Display)]", "derive_more", &["display"], &[]),
+    ("rand::thread_rng", "rand", &["std", "std_rng"], &[]),
+    ("reqwest::blocking", "reqwest", &["blocking"], &[]),
+    ("uuid::Uuid::new_v4", "uuid", &["v4"], &[]),
+    ("chrono::serde", "chrono", &["serde"], &["serde"]),
+];
+
+lazy_static! {
+    static ref BUILT_IN: Vec<InferenceRule> = BUILT_IN_RULES
+        .iter()
+        .map(|(usage, crate_name, features, companions)| InferenceRule {
+            usage: usage.parse().expect("Invalid built-in usage"),
+            crate_name: (*crate_name).to_string(),
+            features: features.iter().map(ToString::to_string).collect(),
+            companions: companions.iter().map(ToString::to_string).collect(),
+        })
+        .collect();
+}
+
+/// A way of using a crate in a script that calls for some of its features, or for other
+/// crates. Written as in the script: `#[tokio::main]`, `#[derive(Serialize)]` or a path
+/// such as `rand::thread_rng`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum Usage {
+    /// An attribute, by its path.
+    Attribute(String),
+    /// A derive macro, by its name.
+    Derive(String),
+    /// A path, which matches any path that starts with it.
+    Path(String),
+}
+
+impl FromStr for Usage {
+    type Err = BuildRunError;
+
+    fn from_str(usage: &str) -> Result<Self, Self::Err> {
+        let usage = usage.trim();
+        let invalid = || BuildRunError::FromStr(format!("Invalid usage {usage}"));
+        if let Some(attribute) = usage.strip_prefix("#[") {
+            let attribute = attribute.strip_suffix(']').ok_or_else(invalid)?.trim();
+            return match attribute.strip_prefix("derive(") {
+                Some(derive) => Ok(Self::Derive(
+                    last_segment(derive.strip_suffix(')').ok_or_else(invalid)?).to_string(),
+                )),
+                None => Ok(Self::Attribute(attribute.to_string())),
+            };
+        }
+        if usage.is_empty() || usage.contains(char::is_whitespace) {
+            return Err(invalid());
+        }
+        Ok(Self::Path(usage.to_string()))
+    }
+}
+
+impl TryFrom<String> for Usage {
+    type Error = BuildRunError;
+
+    fn try_from(usage: String) -> Result<Self, Self::Error> {
+        usage.parse()
+    }
+}
+
+impl Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Attribute(path) => write!(f, "#[{path}]"),
+            Self::Derive(name) => write!(f, "#[derive({name})]"),
+            Self::Path(path) => f.write_str(path),
+        }
+    }
+}
+
+/// An entry in the knowledge base: the features of a crate, and any other crates, that a
+/// script needs when it uses the crate in a certain way. Extra rules may be configured in
+/// `[[inference]]` tables.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct InferenceRule {
+    pub usage: Usage,
+    #[serde(rename = "crate")]
+    pub crate_name: String,
+    #[serde(default)]
+    pub features: Vec<String>,
+    #[serde(default)]
+    pub companions: Vec<String>,
+}
+
+impl InferenceRule {
+    /// Whether the usage names the rule's crate, as `#[tokio::main]` names `tokio`, so that
+    /// the crate is needed even if the script doesn't otherwise refer to it.
+    #[must_use]
+    pub fn names_crate(&self) -> bool {
+        let path = match self.usage {
+            Usage::Attribute(ref path) | Usage::Path(ref path) => path,
+            Usage::Derive(_) => return false,
+        };
+        path.split("::").next().map(cache_key) == Some(cache_key(&self.crate_name))
+    }
+}
+
+/// The attributes, derive macros and paths found in a script.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Usages {
+    pub attributes: BTreeSet<String>,
+    pub derives: BTreeSet<String>,
+    pub paths: BTreeSet<String>,
+}
+
+impl Usages {
+    /// Whether the script uses the crate in this way.
+    #[must_use]
+    pub fn contains(&self, usage: &Usage) -> bool {
+        match usage {
+            Usage::Attribute(path) => self.attributes.contains(path),
+            Usage::Derive(name) => self.derives.contains(name),
+            Usage::Path(prefix) => self.paths.iter().any(|path| {
+                path.strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            }),
+        }
+    }
+}
+
+fn last_segment(path: &str) -> &str {
+    path.rsplit("::").next().unwrap_or(path).trim()
+}
+
+/// The configured inference rules followed by the built-in ones.
+#[must_use]
+pub fn rules() -> Vec<InferenceRule> {
+    CONFIG
+        .inference
+        .iter()
+        .chain(BUILT_IN.iter())
+        .cloned()
+        .collect()
+}
+
+/// The rules that apply to a script: those whose usage it contains, provided it depends on
+/// the rule's crate, according to `is_dependency`, or the usage names the crate.
+#[must_use]
+pub fn applicable_rules(
+    rules: &[InferenceRule],
+    usages: &Usages,
+    is_dependency: impl Fn(&str) -> bool,
+) -> Vec<InferenceRule> {
+    rules
+        .iter()
+        .filter(|rule| usages.contains(&rule.usage))
+        .filter(|rule| rule.names_crate() || is_dependency(&rule.crate_name))
+        .cloned()
+        .collect()
+}
+
+/// The crates that the applicable rules call for, including the rules' own crates, that
+/// are not among the known dependencies yet.
+#[must_use]
+pub fn required_crates(
+    rules: &[InferenceRule],
+    is_dependency: impl Fn(&str) -> bool,
+) -> Vec<String> {
+    let mut required: Vec<String> = vec![];
+    for rule in rules {
+        for crate_name in std::iter::once(&rule.crate_name).chain(&rule.companions) {
+            if is_dependency(crate_name)
+                || required
+                    .iter()
+                    .any(|name| cache_key(name) == cache_key(crate_name))
+            {
+                continue;
+            }
+            log!(
+                Verbosity::Verbose,
+                "Adding crate {crate_name}, which {} needs",
+                rule.usage
+            );
+            required.push(cache_key(crate_name));
+        }
+    }
+    required
+}
+
+/// Enable the features that the applicable rules call for on the dependencies, explaining
+/// each choice in verbose output.
+pub fn apply_features(rules: &[InferenceRule], dependencies: &mut BTreeMap<String, Dependency>) {
+    for rule in rules.iter().filter(|rule| !rule.features.is_empty()) {
+        let Some((name, dependency)) = dependencies.iter_mut().find(|(name, dependency)| {
+            let crate_name = dependency.package().unwrap_or(name);
+            cache_key(crate_name) == cache_key(&rule.crate_name)
+        }) else {
+            continue;
+        };
+        let added = add_features(dependency, &rule.features);
+        if !added.is_empty() {
+            log!(
+                Verbosity::Verbose,
+                "Enabling feature(s) {} of {name} for {}",
+                added.join(", "),
+                rule.usage
+            );
+        }
+    }
+}
+
+/// Add any of the features that the dependency doesn't have yet, returning those added.
+fn add_features(dependency: &mut Dependency, features: &[String]) -> Vec<String> {
+    if let Dependency::Simple(version) = dependency {
+        let version = std::mem::take(version);
+        *dependency = Dependency::Detailed(Box::new(DependencyDetail {
+            version: Some(version),
+            ..Default::default()
+        }));
+    }
+    let Dependency::Detailed(detail) = dependency else {
+        return vec![];
+    };
+    let added: Vec<String> = features
+        .iter()
+        .filter(|feature| !detail.features.contains(feature))
+        .cloned()
+        .collect();
+    detail.features.extend(added.iter().cloned());
+    added
+}
@@ -4,22 +4,32 @@ use std::path::PathBuf;
 
 // Re-export the modules you need
 pub mod builder;
+pub mod cache;
 pub mod cmd_args;
 pub mod code_utils;
 pub mod colors;
+pub mod completions;
+pub mod config;
+pub mod deps_cache;
 pub mod errors;
+pub mod events;
+pub mod knowledge;
 pub mod logging;
 pub mod manifest;
+pub mod picker;
+pub mod project;
+pub mod registry;
 pub mod repl;
+pub mod sandbox;
 pub mod shared;
 pub mod stdin;
+pub mod watch;
 
 // Re-export commonly used items for convenience
 pub use builder::{execute, gen_build_run};
-pub use cmd_args::{get_args, get_proc_flags, validate_args, Cli, ProcFlags};
+pub use cmd_args::{get_args, get_proc_flags, validate_args, Cli, Commands, ProcFlags};
 pub use code_utils::{
-    create_next_repl_file, create_temp_source_file, extract_ast, extract_manifest,
-    modified_since_compiled, process_expr,
+    create_next_repl_file, create_temp_source_file, extract_ast, extract_manifest, process_expr,
 };
 pub use colors::{nu_resolve_style, MessageLevel};
 pub use errors::BuildRunError;
@@ -37,9 +47,15 @@ pub const RS_SUFFIX: &str = ".rs";
 pub const FLOWER_BOX_LEN: usize = 70;
 pub const REPL_SUBDIR: &str = "rs_repl";
 pub const DYNAMIC_SUBDIR: &str = "rs_dyn";
+pub const SHARED_TARGET_SUBDIR: &str = "rs_target";
 pub const TEMP_SCRIPT_NAME: &str = "temp.rs";
 pub const TOML_NAME: &str = "Cargo.toml";
 
 lazy_static! {
-    pub static ref TMPDIR: PathBuf = env::temp_dir();
+    /// Root directory for generated projects: the configured cache directory if any,
+    /// otherwise the system temporary directory.
+    pub static ref TMPDIR: PathBuf = config::CONFIG
+        .cache_dir
+        .clone()
+        .unwrap_or_else(env::temp_dir);
 }
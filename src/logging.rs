@@ -1,21 +1,38 @@
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+use clap::ValueEnum;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
 pub enum Verbosity {
     Quiet,
     Normal,
     Verbose,
 }
 
+/// How messages are written: as coloured text for people, or as newline-delimited JSON
+/// events for tools, in which case the text messages are suppressed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum MessageFormat {
+    #[default]
+    Human,
+    Json,
+}
+
 pub struct Logger {
     pub verbosity: Verbosity,
+    pub message_format: MessageFormat,
 }
 
 impl Logger {
     pub fn new(verbosity: Verbosity) -> Self {
-        Logger { verbosity }
+        Logger {
+            verbosity,
+            message_format: MessageFormat::Human,
+        }
     }
 
     pub fn log(&self, verbosity: Verbosity, message: &str) {
-        if verbosity as u8 <= self.verbosity as u8 {
+        if self.message_format == MessageFormat::Human && verbosity as u8 <= self.verbosity as u8 {
             println!("{}", message);
         }
     }
@@ -23,6 +40,10 @@ impl Logger {
     pub fn set_verbosity(&mut self, verbosity: Verbosity) {
         self.verbosity = verbosity;
     }
+
+    pub fn set_message_format(&mut self, message_format: MessageFormat) {
+        self.message_format = message_format;
+    }
 }
 
 use lazy_static::lazy_static;
@@ -37,6 +58,15 @@ pub fn set_global_verbosity(verbosity: Verbosity) {
     logger.set_verbosity(verbosity);
 }
 
+pub fn set_global_message_format(message_format: MessageFormat) {
+    let mut logger = LOGGER.lock().unwrap();
+    logger.set_message_format(message_format);
+}
+
+pub fn global_message_format() -> MessageFormat {
+    LOGGER.lock().unwrap().message_format
+}
+
 #[macro_export]
 macro_rules! log {
     ($verbosity:expr, $($arg:tt)*) => {
@@ -1,21 +1,34 @@
 #![allow(clippy::uninlined_format_args)]
-use cargo_toml::{Dependency, Manifest, PatchSet as Patches};
+use cargo_toml::{Dependency, DependencyDetail, Manifest, PatchSet as Patches};
 use lazy_static::lazy_static;
 use mockall::automock;
 use regex::Regex;
 use std::collections::BTreeMap;
 use std::error::Error;
-use std::io::{self, BufRead};
+use std::io;
+use std::path::Path;
 use std::process::{Command, Output};
-use std::time::Instant;
+use std::time::{Instant, SystemTime};
 
-use crate::code_utils::{infer_deps_from_ast, infer_deps_from_source}; // Valid if no circular dependency
+use crate::code_utils::{
+    find_usages_ast, find_usages_source, infer_deps_from_ast, infer_deps_from_source,
+}; // Valid if no circular dependency
 use crate::colors::{nu_resolve_style, MessageLevel};
 use crate::debug_log;
+use crate::deps_cache::{self, cache_key, DepsCache};
 use crate::errors::BuildRunError;
+use crate::events::{elapsed_ms, emit, Event};
+use crate::knowledge;
 use crate::log;
 use crate::logging::Verbosity;
+use crate::picker::{self, CrateCandidate};
+use crate::registry;
 use crate::shared::{debug_timings, escape_path_for_windows, Ast, BuildState};
+use crate::stdin::CrosstermEventReader;
+
+/// Number of crates to ask cargo search for, to offer as candidates if none of them has
+/// exactly the name used in the script.
+pub const SEARCH_LIMIT: usize = 10;
 
 #[automock]
 pub trait CommandRunner {
@@ -48,22 +61,11 @@ See below for how to avoid this and speed up future builds.
         "search".to_string(),
         dep_crate.to_string(),
         "--limit".to_string(),
-        "1".to_string(),
+        SEARCH_LIMIT.to_string(),
     ];
     let search_output = runner.run_command("cargo", &args)?;
 
-    let first_line = if search_output.status.success() {
-        search_output
-            .stdout
-            .lines()
-            .map_while(Result::ok)
-            .next()
-            .ok_or_else(|| {
-                Box::new(BuildRunError::Command(format!(
-                    "Something went wrong with Cargo search for [{dep_crate}]"
-                )))
-            })?
-    } else {
+    if !search_output.status.success() {
         let error_msg = String::from_utf8_lossy(&search_output.stderr);
         error_msg.lines().for_each(|line| {
             debug_log!("{line}");
@@ -73,43 +75,68 @@ See below for how to avoid this and speed up future builds.
         ))));
     };
 
-    debug_log!("first_line={first_line}");
-    let result = capture_dep(&first_line);
-    let (name, version) = match result {
-        Ok((name, version)) => {
-            if name != dep_crate && name.replace('-', "_") != dep_crate {
-                debug_log!("First line of cargo search for crate {dep_crate} found non-matching crate {name}");
-                return Err(Box::new(BuildRunError::Command(format!(
-                    "Cargo search failed for [{dep_crate}]: returned non-matching crate [{name}]"
-                ))));
-            }
+    let candidates = capture_candidates(&String::from_utf8_lossy(&search_output.stdout));
+    debug_log!("candidates={candidates:#?}");
+    let Some(found) = candidates
+        .iter()
+        .find(|candidate| candidate.name.replace('-', "_") == dep_crate.replace('-', "_"))
+    else {
+        if candidates.is_empty() {
+            return Err(Box::new(BuildRunError::Command(format!(
+                "Cargo search found nothing for [{dep_crate}]"
+            ))));
+        }
+        debug_log!("Cargo search for crate {dep_crate} found only non-matching crates");
+        return Err(Box::new(BuildRunError::UnresolvedCrate(
+            dep_crate.to_string(),
+            candidates,
+        )));
+    };
+    let (name, version) = (found.name.clone(), found.version.clone());
 
-            let dep_crate_styled = nu_resolve_style(MessageLevel::Emphasis).paint(&name);
-            let dep_version_styled = nu_resolve_style(MessageLevel::Emphasis).paint(&version);
+    let dep_crate_styled = nu_resolve_style(MessageLevel::Emphasis).paint(&name);
+    let dep_version_styled = nu_resolve_style(MessageLevel::Emphasis).paint(&version);
 
-            log!(
-                Verbosity::Normal,
-                r#"Cargo found the following dependency, which you can copy into the toml block
+    log!(
+        Verbosity::Normal,
+        r#"Cargo found the following dependency, which you can copy into the toml block
 as shown if you don't need special features:
 /*[toml]
 [dependencies]
 {dep_crate_styled} = "{dep_version_styled}"
 */
 "#
-            );
-            (name, version)
-        }
-        Err(err) => {
-            debug_log!("Failure! err={err}");
-            return Err(err);
-        }
-    };
+    );
 
     debug_timings(&start_search, "Completed search");
 
     Ok((name, version))
 }
 
+/// The crates listed in the output of cargo search, in order of relevance.
+#[must_use]
+pub fn capture_candidates(search_output: &str) -> Vec<CrateCandidate> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(
+            r#"^(?P<name>[\w-]+) = "(?P<version>[^"]+)"\s*(?:#\s*(?P<description>.*))?$"#
+        )
+        .unwrap();
+    }
+    search_output
+        .lines()
+        .filter_map(|line| RE.captures(line.trim_end()))
+        .map(|captures| CrateCandidate {
+            name: captures["name"].to_string(),
+            version: captures["version"].to_string(),
+            description: captures
+                .name("description")
+                .map_or_else(String::new, |description| {
+                    description.as_str().trim().to_string()
+                }),
+        })
+        .collect()
+}
+
 pub fn capture_dep(first_line: &str) -> Result<(String, String), Box<dyn Error>> {
     debug_log!("first_line={first_line}");
     lazy_static! {
@@ -140,7 +167,11 @@ pub fn default_manifest_from_build_state(
     let binding = build_state.target_dir_path.join(source_name);
     let gen_src_path = escape_path_for_windows(binding.to_string_lossy().as_ref());
 
-    default_manifest(source_stem, &gen_src_path)
+    let mut manifest = default_manifest(source_stem, &gen_src_path)?;
+    if let Some(bin) = manifest.bin.first_mut() {
+        bin.name = Some(build_state.bin_name());
+    }
+    Ok(manifest)
 }
 
 pub fn default_manifest(source_stem: &str, gen_src_path: &str) -> Result<Manifest, BuildRunError> {
@@ -188,10 +219,16 @@ pub fn merge_manifest(
 
     // debug_log!("cargo_manifest (before deps)={cargo_manifest:#?}");
 
-    let rs_inferred_deps = if let Some(ref syntax_tree) = syntax_tree {
-        infer_deps_from_ast(syntax_tree)
+    let (mut rs_inferred_deps, usages) = if let Some(ref syntax_tree) = syntax_tree {
+        (
+            infer_deps_from_ast(syntax_tree),
+            find_usages_ast(syntax_tree),
+        )
     } else {
-        infer_deps_from_source(rs_source)
+        (
+            infer_deps_from_source(rs_source),
+            find_usages_source(rs_source),
+        )
     };
 
     // debug_log!("rs_inferred_deps={rs_inferred_deps:#?}\n");
@@ -213,6 +250,21 @@ pub fn merge_manifest(
 
     // let btree_map = BTreeMap::<std::string::String, Dependency>::new();
     let mut rs_dep_map = rs_manifest.dependencies;
+
+    // Look up the features and companion crates that the script's usage calls for
+    let is_dependency = |crate_name: &str| {
+        let key = cache_key(crate_name);
+        rs_inferred_deps.iter().any(|dep| cache_key(dep) == key)
+            || rs_dep_map.iter().any(|(name, dep)| {
+                cache_key(name) == key
+                    || dep
+                        .package()
+                        .is_some_and(|package| cache_key(package) == key)
+            })
+    };
+    let rules = knowledge::applicable_rules(&knowledge::rules(), &usages, is_dependency);
+    let required_crates = knowledge::required_crates(&rules, is_dependency);
+    rs_inferred_deps.extend(required_crates);
     //     rs_dep_map.clone()
     // } else {
     //     btree_map
@@ -227,9 +279,15 @@ pub fn merge_manifest(
 
     if !rs_inferred_deps.is_empty() {
         debug_log!("rs_dep_map (before inferred) {rs_dep_map:#?}");
-        search_deps(rs_inferred_deps, &mut rs_dep_map);
+        search_deps(
+            rs_inferred_deps.clone(),
+            &mut rs_dep_map,
+            &build_state.cargo_home,
+            build_state.offline,
+        )?;
         debug_log!("rs_dep_map (after inferred) {rs_dep_map:#?}");
     }
+    knowledge::apply_features(&rules, &mut rs_dep_map);
 
     // Clone and merge dependencies specified in toml block or inferred from code.
     let manifest_deps = cargo_manifest.dependencies.clone();
@@ -266,6 +324,11 @@ pub fn merge_manifest(
     }
 
     debug_timings(&start_merge_manifest, "Processed features");
+    emit(&Event::Manifest {
+        inferred_deps: rs_inferred_deps,
+        dependencies: cargo_manifest.dependencies.keys().cloned().collect(),
+        elapsed_ms: elapsed_ms(&start_merge_manifest),
+    });
     // debug_log!("cargo_manifest (after merge)={:#?}", cargo_manifest);
 
     Ok(cargo_manifest.clone())
@@ -320,7 +383,37 @@ fn merge_patch(manifest_patches: &Patches, rs_patch_map: &Patches) -> Patches {
     manifest_patches_clone
 }
 
-fn search_deps(rs_inferred_deps: Vec<String>, rs_dep_map: &mut BTreeMap<String, Dependency>) {
+/// Find a version for each inferred dependency not already specified, first in the
+/// dependency resolution cache, then in the local registry and then, unless `offline`,
+/// with cargo search, whose findings, including any crate the user chose from a near miss,
+/// are added to the cache.
+/// # Errors
+///
+/// Will return `Err` if cargo search found only crates of other names and the user can't
+/// be asked or declines to choose one of them.
+#[allow(clippy::too_many_lines)]
+fn search_deps(
+    rs_inferred_deps: Vec<String>,
+    rs_dep_map: &mut BTreeMap<String, Dependency>,
+    cargo_home: &Path,
+    offline: bool,
+) -> Result<(), Box<dyn Error>> {
+    let now = SystemTime::now();
+    let ttl = deps_cache::ttl();
+    let deps_cache_path = deps_cache::deps_cache_path().ok();
+    let mut deps_cache = match deps_cache_path.as_deref().map(DepsCache::load) {
+        Some(Ok(deps_cache)) => Some(deps_cache),
+        Some(Err(err)) => {
+            log!(
+                Verbosity::Quiet,
+                "Ignoring invalid dependency cache {deps_cache_path:?}: {err}"
+            );
+            None
+        }
+        None => None,
+    };
+    let mut deps_cache_changed = false;
+
     for dep_name in rs_inferred_deps {
         if rs_dep_map.contains_key(&dep_name)
             || rs_dep_map.contains_key(&dep_name.replace('_', "-"))
@@ -328,25 +421,183 @@ fn search_deps(rs_inferred_deps: Vec<String>, rs_dep_map: &mut BTreeMap<String,
         {
             continue;
         }
-        debug_log!("Starting Cargo search for key dep_name [{dep_name}]");
-        let command_runner = RealCommandRunner;
-        let cargo_search_result = cargo_search(&command_runner, &dep_name);
-        // If the crate name is hyphenated, Cargo search will nicely search for underscore version and return the correct
-        // hyphenated name. So we must replace the incorrect underscored version we searched on with the corrected
-        // hyphenated version that the Cargo search returned.
-        let (dep_name, dep) = if let Ok((dep_name, version)) = cargo_search_result {
-            (dep_name, Dependency::Simple(version))
-        } else {
-            // return Err(Box::new(BuildRunError::Command(format!(
-            //     "Cargo search couldn't find crate [{dep_name}]"
-            // ))));
+        let start_lookup = Instant::now();
+        let cached = deps_cache
+            .as_ref()
+            .and_then(|deps_cache| deps_cache.lookup(&dep_name, ttl, now))
+            .cloned();
+        emit(&Event::DepsCache {
+            crate_name: dep_name.clone(),
+            found: cached.as_ref().map(|cached| cached.name.clone()),
+            version: cached.as_ref().map(|cached| cached.version.clone()),
+            pinned: cached.as_ref().is_some_and(|cached| cached.pinned),
+            elapsed_ms: elapsed_ms(&start_lookup),
+        });
+        if let Some(cached) = cached {
+            log!(
+                Verbosity::Verbose,
+                "Using cached crate {} = \"{}\"{}",
+                cached.name,
+                cached.version,
+                if cached.pinned { " (pinned)" } else { "" }
+            );
+            let (name, dep) = inferred_dependency(&dep_name, &cached.name, &cached.version);
+            rs_dep_map.insert(name, dep);
+            continue;
+        }
+
+        let start_lookup = Instant::now();
+        let local = registry::resolve_local(cargo_home, &dep_name, offline);
+        emit(&Event::LocalRegistry {
+            crate_name: dep_name.clone(),
+            found: local.as_ref().map(|(name, _)| name.clone()),
+            version: local.as_ref().map(|(_, version)| version.clone()),
+            elapsed_ms: elapsed_ms(&start_lookup),
+        });
+        if let Some((dep_name, version)) = local {
+            log!(
+                Verbosity::Verbose,
+                "Found crate {dep_name} = \"{version}\" in the local registry"
+            );
+            rs_dep_map.insert(dep_name, Dependency::Simple(version));
+            continue;
+        }
+        if offline {
             log!(
                 Verbosity::Quiet,
-                "Cargo search couldn't find crate [{dep_name}]"
+                "Couldn't find crate [{dep_name}] in the local registry, and cargo search is not allowed offline"
             );
             continue;
+        }
+        // Search for the crate chosen last time, if its cached version has expired
+        let search_name = deps_cache
+            .as_ref()
+            .and_then(|deps_cache| deps_cache.crates.get(&cache_key(&dep_name)))
+            .map_or_else(|| dep_name.clone(), |cached| cached.name.clone());
+        debug_log!("Starting Cargo search for key dep_name [{dep_name}] as [{search_name}]");
+        let found = match search_crate(&dep_name, &search_name) {
+            Ok(found) => found,
+            Err(err) => {
+                if deps_cache_changed {
+                    save_deps_cache(deps_cache.as_ref(), deps_cache_path.as_deref());
+                }
+                return Err(err);
+            }
         };
-        rs_dep_map.insert(dep_name, dep);
+        // If the crate name is hyphenated, Cargo search will nicely search for underscore version and return the correct
+        // hyphenated name. So we must replace the incorrect underscored version we searched on with the corrected
+        // hyphenated version that the Cargo search returned.
+        if let Some((found_name, version)) = found {
+            if let Some(ref mut deps_cache) = deps_cache {
+                deps_cache.insert(&dep_name, &found_name, &version, now);
+                deps_cache_changed = true;
+            }
+            let (name, dep) = inferred_dependency(&dep_name, &found_name, &version);
+            rs_dep_map.insert(name, dep);
+        }
+    }
+    if deps_cache_changed {
+        save_deps_cache(deps_cache.as_ref(), deps_cache_path.as_deref());
     }
     debug_log!("rs_dep_map (after inferred) = {rs_dep_map:#?}");
+    Ok(())
+}
+
+/// Find the crate that a script refers to as `dep_name` with cargo search for `search_name`.
+/// If the search finds only crates of other names, let the user choose one of them, or fail
+/// with the list if the user can't be asked. Returns the crate name and version found, if any.
+fn search_crate(
+    dep_name: &str,
+    search_name: &str,
+) -> Result<Option<(String, String)>, Box<dyn Error>> {
+    let start_search = Instant::now();
+    let cargo_search_result = cargo_search(&RealCommandRunner, search_name);
+    let found = cargo_search_result.as_ref().ok();
+    emit(&Event::CargoSearch {
+        crate_name: search_name.to_string(),
+        found: found.map(|(name, _)| name.clone()),
+        version: found.map(|(_, version)| version.clone()),
+        elapsed_ms: elapsed_ms(&start_search),
+    });
+    let err = match cargo_search_result {
+        Ok(found) => return Ok(Some(found)),
+        Err(err) => err,
+    };
+    match err.downcast::<BuildRunError>().map(|err| *err) {
+        Ok(BuildRunError::UnresolvedCrate(_, candidates)) if picker::is_interactive() => {
+            let chosen = picker::pick_crate(&CrosstermEventReader, dep_name, &candidates)?;
+            let dep_line = if cache_key(&chosen.name) == cache_key(dep_name) {
+                format!("{} = \"{}\"", chosen.name, chosen.version)
+            } else {
+                format!(
+                    "{dep_name} = {{ package = \"{}\", version = \"{}\" }}",
+                    chosen.name, chosen.version
+                )
+            };
+            log!(
+                Verbosity::Normal,
+                r#"Using crate {} for {dep_name}, which you can copy into the toml block:
+/*[toml]
+[dependencies]
+{dep_line}
+*/
+"#,
+                chosen.name
+            );
+            Ok(Some((chosen.name, chosen.version)))
+        }
+        Ok(err @ BuildRunError::UnresolvedCrate(..)) => {
+            log!(Verbosity::Quiet, "{err}");
+            Err(Box::new(err))
+        }
+        Ok(err) => {
+            log!(
+                Verbosity::Quiet,
+                "Cargo search couldn't find crate [{dep_name}]: {err}"
+            );
+            Ok(None)
+        }
+        Err(err) => {
+            log!(
+                Verbosity::Quiet,
+                "Cargo search couldn't find crate [{dep_name}]: {err}"
+            );
+            Ok(None)
+        }
+    }
+}
+
+/// The manifest entry for a crate found for a dependency that the script refers to as
+/// `dep_name`. A crate of another name, as chosen from the candidates of a cargo search,
+/// is renamed to `dep_name` so that the script's paths resolve.
+#[must_use]
+pub fn inferred_dependency(
+    dep_name: &str,
+    crate_name: &str,
+    version: &str,
+) -> (String, Dependency) {
+    if cache_key(crate_name) == cache_key(dep_name) {
+        return (
+            crate_name.to_string(),
+            Dependency::Simple(version.to_string()),
+        );
+    }
+    let detail = DependencyDetail {
+        package: Some(crate_name.to_string()),
+        version: Some(version.to_string()),
+        ..Default::default()
+    };
+    (dep_name.to_string(), Dependency::Detailed(Box::new(detail)))
+}
+
+fn save_deps_cache(deps_cache: Option<&DepsCache>, path: Option<&Path>) {
+    if let (Some(deps_cache), Some(path)) = (deps_cache, path) {
+        if let Err(err) = deps_cache.save(path) {
+            log!(
+                Verbosity::Quiet,
+                "Couldn't save dependency cache {}: {err}",
+                path.display()
+            );
+        }
+    }
 }
@@ -0,0 +1,155 @@
+use crate::errors::BuildRunError;
+use crate::stdin::EventReader;
+
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::Terminal;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::io::{self, IsTerminal};
+
+/// A crate found by cargo search, as offered to the user to choose from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CrateCandidate {
+    pub name: String,
+    pub version: String,
+    pub description: String,
+}
+
+impl Display for CrateCandidate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} = \"{}\"", self.name, self.version)?;
+        if !self.description.is_empty() {
+            write!(f, "    # {}", self.description)?;
+        }
+        Ok(())
+    }
+}
+
+/// What a key press in the picker does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PickerAction {
+    /// Move the highlight to the candidate at this index.
+    Select(usize),
+    /// Choose the candidate at this index.
+    Choose(usize),
+    Cancel,
+    Ignore,
+}
+
+/// The action for an event, given the index of the highlighted candidate and the number
+/// of candidates.
+#[must_use]
+pub fn picker_action(event: &Event, selected: usize, len: usize) -> PickerAction {
+    let Event::Key(KeyEvent {
+        code,
+        modifiers,
+        kind: KeyEventKind::Press,
+        ..
+    }) = event
+    else {
+        return PickerAction::Ignore;
+    };
+    match code {
+        KeyCode::Up | KeyCode::Char('k') => PickerAction::Select(selected.saturating_sub(1)),
+        KeyCode::Down | KeyCode::Char('j') => {
+            PickerAction::Select((selected + 1).min(len.saturating_sub(1)))
+        }
+        KeyCode::Home => PickerAction::Select(0),
+        KeyCode::End => PickerAction::Select(len.saturating_sub(1)),
+        KeyCode::Enter if len > 0 => PickerAction::Choose(selected),
+        KeyCode::Esc | KeyCode::Char('q') => PickerAction::Cancel,
+        KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => PickerAction::Cancel,
+        _ => PickerAction::Ignore,
+    }
+}
+
+/// Whether the user can be asked to choose, i.e. we're talking to a terminal and not to a
+/// tool reading JSON messages.
+#[must_use]
+pub fn is_interactive() -> bool {
+    io::stdin().is_terminal() && io::stdout().is_terminal() && !crate::events::json_enabled()
+}
+
+/// Ask the user which of the candidates found by cargo search is the crate that the script
+/// refers to as `dep_crate`.
+/// # Errors
+///
+/// Will return `Err` if the terminal can't be set up or the user cancels.
+pub fn pick_crate<R: EventReader>(
+    event_reader: &R,
+    dep_crate: &str,
+    candidates: &[CrateCandidate],
+) -> Result<CrateCandidate, Box<dyn Error>> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    enable_raw_mode()?;
+    ratatui::crossterm::execute!(stdout, EnterAlternateScreen)?;
+    let terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+    // Ensure terminal will get reset when it goes out of scope.
+    let mut term = scopeguard::guard(terminal, |mut term| {
+        let _ = disable_raw_mode();
+        let _ = ratatui::crossterm::execute!(term.backend_mut(), LeaveAlternateScreen);
+        let _ = term.show_cursor();
+    });
+
+    let name_width = candidates
+        .iter()
+        .map(|candidate| candidate.name.len())
+        .max()
+        .unwrap_or_default();
+    let items: Vec<ListItem> = candidates
+        .iter()
+        .map(|candidate| {
+            ListItem::new(Line::from(vec![
+                Span::styled(
+                    format!("{:<name_width$}  ", candidate.name),
+                    Style::default().add_modifier(Modifier::BOLD),
+                ),
+                Span::styled(
+                    format!("{:<12} ", candidate.version),
+                    Style::default().fg(Color::Cyan),
+                ),
+                Span::raw(candidate.description.clone()),
+            ]))
+        })
+        .collect();
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!(
+                    " No crate named {dep_crate} was found. Which crate does your script use? "
+                ))
+                .title_style(Style::default().italic()),
+        )
+        .highlight_style(Style::default().bg(Color::Blue))
+        .highlight_symbol("> ");
+    let help = Paragraph::new("↑↓: select  Enter: choose  Esc: cancel")
+        .style(Style::default().fg(Color::DarkGray));
+
+    let mut state = ListState::default().with_selected(Some(0));
+    loop {
+        term.draw(|f| {
+            let [list_area, help_area] =
+                Layout::vertical([Constraint::Fill(1), Constraint::Length(1)]).areas(f.size());
+            f.render_stateful_widget(&list, list_area, &mut state);
+            f.render_widget(&help, help_area);
+        })?;
+        let event = event_reader.read_event()?;
+        let selected = state.selected().unwrap_or_default();
+        match picker_action(&event, selected, candidates.len()) {
+            PickerAction::Select(index) => state.select(Some(index)),
+            PickerAction::Choose(index) => return Ok(candidates[index].clone()),
+            PickerAction::Cancel => return Err(Box::new(BuildRunError::Cancelled)),
+            PickerAction::Ignore => (),
+        }
+    }
+}
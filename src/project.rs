@@ -0,0 +1,244 @@
+use crate::builder::gen_step;
+use crate::cmd_args::{Cli, ProcFlags};
+use crate::code_utils::{
+    convert_manifest_style, extract_toml_table, find_manifest_block, read_file_contents,
+    strip_toml_block, ManifestStyle,
+};
+use crate::colors::{nu_resolve_style, MessageLevel};
+use crate::debug_log;
+use crate::errors::BuildRunError;
+use crate::log;
+use crate::logging::Verbosity;
+use crate::shared::BuildState;
+use crate::{RS_SUFFIX, TOML_NAME};
+
+use cargo_toml::Manifest;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// The package settings carried over from an imported project into the script's toml block.
+const IMPORTED_PACKAGE_KEYS: [&str; 5] = ["name", "version", "authors", "edition", "description"];
+
+/// Export a script as a standalone Cargo project in the given directory, with the
+/// generated manifest and source.
+/// # Errors
+///
+/// Will return `Err` if the directory already holds a Cargo project and `--force` was not
+/// specified, or if the script can't be generated or the project can't be written.
+pub fn export(
+    options: &Cli,
+    proc_flags: &ProcFlags,
+    build_state: &mut BuildState,
+    to: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let cargo_toml_path = to.join(TOML_NAME);
+    if cargo_toml_path.exists() && !proc_flags.contains(ProcFlags::FORCE) {
+        return Err(Box::new(BuildRunError::Command(format!(
+            "{} already exists. Use --force (-f) to overwrite it",
+            cargo_toml_path.display()
+        ))));
+    }
+
+    gen_step(options, proc_flags, build_state, None)?;
+
+    let script_source = read_file_contents(&build_state.source_path)?;
+    let manifest = build_state
+        .cargo_manifest
+        .as_ref()
+        .ok_or_else(|| BuildRunError::NoneOption("Missing generated manifest".to_string()))?;
+    let cargo_toml = export_manifest(manifest, &script_source)?;
+
+    let generated_source =
+        read_file_contents(&build_state.target_dir_path.join(&build_state.source_name))?;
+    let main_rs = strip_toml_block(&generated_source);
+
+    let src_dir = to.join("src");
+    fs::create_dir_all(&src_dir)?;
+    fs::write(&cargo_toml_path, toml::to_string(&cargo_toml)?)?;
+    fs::write(src_dir.join("main.rs"), main_rs)?;
+    let gitignore_path = to.join(".gitignore");
+    if !gitignore_path.exists() {
+        fs::write(gitignore_path, "/target\n")?;
+    }
+    // Pin the same dependency versions as the script was built with
+    let lock_path = build_state.target_dir_path.join("Cargo.lock");
+    if lock_path.exists() {
+        fs::copy(lock_path, to.join("Cargo.lock"))?;
+    }
+
+    log!(
+        Verbosity::Quiet,
+        "Exported {} to Cargo project {}",
+        nu_resolve_style(MessageLevel::Emphasis).paint(&build_state.source_name),
+        to.display()
+    );
+    Ok(())
+}
+
+/// Turn the manifest generated for a script into one for a standalone project: drop the
+/// `[[bin]]` pointing into the temporary directory and the empty `[workspace]`, and keep
+/// the package metadata from the script's toml block.
+/// # Errors
+///
+/// Will return `Err` if the script's toml block is not valid TOML or has an invalid package.
+pub fn export_manifest(
+    generated_manifest: &Manifest,
+    script_source: &str,
+) -> Result<Manifest, BuildRunError> {
+    let mut manifest = generated_manifest.clone();
+    manifest.bin.clear();
+    manifest.workspace = None;
+
+    if let Some(Value::Table(script_package)) =
+        extract_toml_table(script_source)?.and_then(|mut table| table.remove("package"))
+    {
+        debug_log!("script_package={script_package:#?}");
+        let mut package = Table::try_from(&manifest.package)?;
+        package.extend(script_package);
+        manifest.package = Some(Value::Table(package).try_into()?);
+    }
+    Ok(manifest)
+}
+
+/// Import a Cargo project as a single-file script, by default `<package name>.rs` in the
+/// current directory.
+/// # Errors
+///
+/// Will return `Err` if the script already exists and `force` was not specified, or if the
+/// project can't be read or the script can't be written.
+pub fn import(project_dir: &Path, to: Option<&Path>, force: bool) -> Result<(), BuildRunError> {
+    let (package_name, script) = import_script(project_dir)?;
+    let script_path = to.map_or_else(
+        || PathBuf::from(format!("{}{RS_SUFFIX}", package_name.replace('-', "_"))),
+        Path::to_path_buf,
+    );
+    if script_path.exists() && !force {
+        return Err(BuildRunError::Command(format!(
+            "{} already exists. Use --force (-f) to overwrite it",
+            script_path.display()
+        )));
+    }
+    fs::write(&script_path, script)?;
+    log!(
+        Verbosity::Quiet,
+        "Imported Cargo project {} to {}",
+        project_dir.display(),
+        nu_resolve_style(MessageLevel::Emphasis).paint(script_path.display().to_string())
+    );
+    Ok(())
+}
+
+/// Convert a Cargo project into the source of a single-file script: a toml block with the
+/// package details, dependencies, features and patches from its `Cargo.toml`, followed by
+/// its `src/main.rs` with any local module files inlined. Returns the package name too.
+/// # Errors
+///
+/// Will return `Err` if the project's `Cargo.toml` or `src/main.rs` can't be read or parsed.
+pub fn import_script(project_dir: &Path) -> Result<(String, String), BuildRunError> {
+    let manifest = Manifest::from_str(&fs::read_to_string(project_dir.join(TOML_NAME))?)?;
+    let package = manifest
+        .package
+        .as_ref()
+        .ok_or_else(|| BuildRunError::Command(format!("No [package] in {TOML_NAME}")))?;
+
+    let mut package_table = Table::try_from(package)?;
+    package_table.retain(|key, _| IMPORTED_PACKAGE_KEYS.contains(&key));
+    let mut toml_block = Table::new();
+    if !manifest.dependencies.is_empty() {
+        toml_block.insert(
+            "dependencies".to_string(),
+            Value::try_from(&manifest.dependencies)?,
+        );
+    }
+    if !manifest.features.is_empty() {
+        toml_block.insert("features".to_string(), Value::try_from(&manifest.features)?);
+    }
+    if !manifest.patch.is_empty() {
+        toml_block.insert("patch".to_string(), Value::try_from(&manifest.patch)?);
+    }
+
+    let src_dir = project_dir.join("src");
+    let main_rs = fs::read_to_string(src_dir.join("main.rs"))?;
+    let rs_source = inline_modules(&main_rs, &src_dir)?;
+
+    // Keep [package] at the top, as in Cargo.toml
+    let mut toml_str = toml::to_string(&Table::from_iter([(
+        "package".to_string(),
+        Value::Table(package_table),
+    )]))?;
+    if !toml_block.is_empty() {
+        toml_str.push('\n');
+        toml_str.push_str(&toml::to_string(&toml_block)?);
+    }
+    let script = format!("/*[toml]\n{toml_str}*/\n\n{rs_source}");
+    Ok((package.name.clone(), script))
+}
+
+/// Rewrite the embedded manifest of a script in place in another style, e.g. from a
+/// `/*[toml]` block to cargo-script front matter.
+/// # Errors
+///
+/// Will return `Err` if the script can't be read or written or has no embedded manifest.
+pub fn convert(script_path: &Path, style: ManifestStyle) -> Result<(), BuildRunError> {
+    let rs_full_source = read_file_contents(script_path)?;
+    let from = find_manifest_block(&rs_full_source).map(|block| block.style);
+    if from == Some(style) {
+        log!(
+            Verbosity::Normal,
+            "The manifest of {} is already in {style} style",
+            script_path.display()
+        );
+        return Ok(());
+    }
+    fs::write(script_path, convert_manifest_style(&rs_full_source, style)?)?;
+    log!(
+        Verbosity::Quiet,
+        "Converted the manifest of {} to {style} style",
+        nu_resolve_style(MessageLevel::Emphasis).paint(script_path.display().to_string())
+    );
+    Ok(())
+}
+
+/// Replace each `mod x;` declaration with a `mod x { ... }` block holding the contents of
+/// `x.rs` or `x/mod.rs` in `dir`, recursively.
+fn inline_modules(rs_source: &str, dir: &Path) -> Result<String, BuildRunError> {
+    lazy_static! {
+        static ref MOD_DECL_REGEX: Regex =
+            Regex::new(r"(?m)^([ \t]*(?:pub(?:\([^)]*\))?\s+)?mod\s+)(\w+)\s*;").unwrap();
+    }
+
+    let mut result = Ok(());
+    let inlined = MOD_DECL_REGEX.replace_all(rs_source, |caps: &Captures| {
+        let declaration = &caps[1];
+        let name = &caps[2];
+        let module_file = dir.join(format!("{name}{RS_SUFFIX}"));
+        let mod_file = dir.join(name).join("mod.rs");
+        let Some(path) = [module_file, mod_file]
+            .into_iter()
+            .find(|path| path.exists())
+        else {
+            log!(
+                Verbosity::Normal,
+                "No file found for module {name}, leaving its declaration as is"
+            );
+            return caps[0].to_string();
+        };
+        debug_log!("Inlining module {name} from {path:?}");
+        // Submodules of both x.rs and x/mod.rs live in x/
+        match fs::read_to_string(&path)
+            .map_err(BuildRunError::from)
+            .and_then(|contents| inline_modules(&contents, &dir.join(name)))
+        {
+            Ok(contents) => format!("{declaration}{name} {{\n{}\n}}", contents.trim_end()),
+            Err(err) => {
+                result = Err(err);
+                caps[0].to_string()
+            }
+        }
+    });
+    result.map(|()| inlined.into_owned())
+}
@@ -0,0 +1,144 @@
+use crate::debug_log;
+
+use semver::Version;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Suffix of the crate archives downloaded by cargo.
+const CRATE_SUFFIX: &str = ".crate";
+
+/// A version of a crate as recorded in a line of the registry index.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub name: String,
+    pub vers: String,
+    #[serde(default)]
+    pub yanked: bool,
+}
+
+/// The directories of the registries cargo has used under `$CARGO_HOME/registry/<kind>`,
+/// where `kind` is `index` for the index caches or `cache` for the downloaded crates.
+#[must_use]
+pub fn registry_dirs(cargo_home: &Path, kind: &str) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(cargo_home.join("registry").join(kind)) else {
+        return vec![];
+    };
+    let mut dirs: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    dirs.sort();
+    dirs
+}
+
+/// Path of a crate's entry relative to the root of a registry index, following the
+/// crates.io layout: `1/a`, `2/ab`, `3/a/abc` and `ab/cd/abcd...`. `None` if the name is
+/// not a valid crates.io name, i.e. not all ASCII letters, digits, `-` and `_`, as a
+/// non-ASCII identifier in a script may be.
+#[must_use]
+pub fn index_path(crate_name: &str) -> Option<PathBuf> {
+    let is_crate_name = !crate_name.is_empty()
+        && crate_name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_');
+    if !is_crate_name {
+        return None;
+    }
+    let name = crate_name.to_ascii_lowercase();
+    Some(match name.len() {
+        1 => PathBuf::from("1").join(&name),
+        2 => PathBuf::from("2").join(&name),
+        3 => PathBuf::from("3").join(&name[..1]).join(&name),
+        _ => PathBuf::from(&name[..2]).join(&name[2..4]).join(&name),
+    })
+}
+
+/// The entries of a file in cargo's index cache (`.cache` under the index directory). The
+/// file starts with a header and then holds pairs of null-terminated version strings and
+/// JSON index lines, so any field that parses as an index line is taken as one.
+#[must_use]
+pub fn parse_index_cache(contents: &[u8]) -> Vec<IndexEntry> {
+    contents
+        .split(|&byte| byte == 0)
+        .filter(|field| field.first() == Some(&b'{'))
+        .filter_map(|field| serde_json::from_slice::<IndexEntry>(field).ok())
+        .collect()
+}
+
+/// The versions of the crate whose archives are in a registry's download cache.
+#[must_use]
+pub fn downloaded_versions(cache_dir: &Path, crate_name: &str) -> Vec<Version> {
+    let Ok(entries) = fs::read_dir(cache_dir) else {
+        return vec![];
+    };
+    let prefix = format!("{crate_name}-");
+    entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let file_name = entry.file_name();
+            let version = file_name
+                .to_str()?
+                .strip_prefix(&prefix)?
+                .strip_suffix(CRATE_SUFFIX)?;
+            Version::parse(version).ok()
+        })
+        .collect()
+}
+
+/// The newest version of a crate that is known locally: preferably one already downloaded,
+/// or failing that, unless `offline`, one listed in the cached registry index. Yanked and
+/// pre-release versions are passed over. Returns the crate's registered name, which may be
+/// hyphenated where the script used an underscore, and the version. Names that can't be
+/// crates.io crate names are not looked up.
+#[must_use]
+pub fn resolve_local(
+    cargo_home: &Path,
+    dep_crate: &str,
+    offline: bool,
+) -> Option<(String, String)> {
+    let index_dirs = registry_dirs(cargo_home, "index");
+    let cache_dirs = registry_dirs(cargo_home, "cache");
+    let hyphenated = dep_crate.replace('_', "-");
+    let mut names = vec![dep_crate.to_string()];
+    if hyphenated != dep_crate {
+        names.push(hyphenated);
+    }
+
+    for name in names {
+        let entry_path = index_path(&name)?;
+        // Whether each version listed in the index has been yanked
+        let mut indexed: BTreeMap<Version, bool> = BTreeMap::new();
+        for index_dir in &index_dirs {
+            let Ok(contents) = fs::read(index_dir.join(".cache").join(&entry_path)) else {
+                continue;
+            };
+            for entry in parse_index_cache(&contents) {
+                if let Ok(version) = Version::parse(&entry.vers) {
+                    indexed.insert(version, entry.yanked);
+                }
+            }
+        }
+        let downloaded = cache_dirs
+            .iter()
+            .flat_map(|cache_dir| downloaded_versions(cache_dir, &name))
+            .filter(|version| indexed.get(version) != Some(&true));
+        let listed = indexed
+            .iter()
+            .filter(|(_, &yanked)| !yanked)
+            .map(|(version, _)| version.clone())
+            .filter(|_| !offline);
+        let newest = newest_release(downloaded).or_else(|| newest_release(listed));
+        debug_log!("Local registry lookup of {name} found {newest:?}");
+        if let Some(version) = newest {
+            return Some((name, version.to_string()));
+        }
+    }
+    None
+}
+
+fn newest_release(versions: impl Iterator<Item = Version>) -> Option<Version> {
+    versions.filter(|version| version.pre.is_empty()).max()
+}
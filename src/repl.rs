@@ -1,5 +1,6 @@
 use crate::cmd_args::{Cli, ProcFlags};
 use crate::code_utils::{self, clean_up, display_dir_contents, extract_ast, extract_manifest};
+use crate::config::{edit_file, CONFIG, DEFAULT_HISTORY_SIZE};
 use crate::debug_log;
 use crate::errors::BuildRunError;
 use crate::log;
@@ -23,6 +24,7 @@ use reedline::{
 use regex::Regex;
 use std::borrow::Cow;
 use std::error::Error;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::Instant;
 use strum::{EnumIter, EnumString, IntoEnumIterator, IntoStaticStr};
@@ -158,9 +160,10 @@ pub fn run_repl(
         start,
     };
     let context: &mut Context = &mut context;
-    let history_file = context.build_state.cargo_home.clone().join(HISTORY_FILE);
+    let history_file = history_file_path(context.build_state);
+    let history_size = CONFIG.history_size.unwrap_or(DEFAULT_HISTORY_SIZE);
     let history = Box::new(
-        FileBackedHistory::with_file(25, history_file)
+        FileBackedHistory::with_file(history_size, history_file)
             .expect("Error configuring history with file"),
     );
 
@@ -284,15 +287,22 @@ pub fn run_repl(
         let maybe_ast = extract_ast(rs_source);
 
         if let Ok(expr_ast) = maybe_ast {
-            code_utils::process_expr(
+            // A failed build or run is reported, and the user can carry on from there
+            if let Err(err) = code_utils::process_expr(
                 &expr_ast,
                 context.build_state,
                 rs_source,
                 context.options,
                 context.proc_flags,
                 &context.start,
-            )
-            .map_err(|_err| BuildRunError::Command("Error processing expression".to_string()))?;
+            ) {
+                if !matches!(
+                    err.downcast_ref::<BuildRunError>(),
+                    Some(BuildRunError::ScriptExit(_))
+                ) {
+                    nu_color_println!(nu_resolve_style(MessageLevel::Error), "{err}");
+                }
+            }
         } else {
             nu_color_println!(
                 nu_resolve_style(MessageLevel::Error),
@@ -303,6 +313,14 @@ pub fn run_repl(
     Ok(())
 }
 
+/// The configured REPL history file, or the default one in `$CARGO_HOME`.
+fn history_file_path(build_state: &BuildState) -> PathBuf {
+    CONFIG
+        .history_file
+        .clone()
+        .unwrap_or_else(|| build_state.cargo_home.join(HISTORY_FILE))
+}
+
 /// Delete our temporary files
 #[allow(clippy::needless_pass_by_value)]
 #[allow(clippy::unnecessary_wraps)]
@@ -328,9 +346,9 @@ pub fn edit_history(
     _args: ArgMatches,
     context: &mut Context,
 ) -> Result<Option<String>, BuildRunError> {
-    let history_file = context.build_state.cargo_home.clone().join(HISTORY_FILE);
+    let history_file = history_file_path(context.build_state);
     println!("history_file={history_file:#?}");
-    edit::edit_file(history_file)?;
+    edit_file(&history_file)?;
     Ok(Some(String::from("End of history file edit")))
 }
 
@@ -339,7 +357,7 @@ pub fn edit_history(
 pub fn edit(_args: ArgMatches, context: &mut Context) -> Result<Option<String>, BuildRunError> {
     let (build_state, _start) = (&mut context.build_state, context.start);
 
-    edit::edit_file(&build_state.source_path)?;
+    edit_file(&build_state.source_path)?;
 
     Ok(Some(String::from("End of source edit")))
 }
@@ -347,7 +365,7 @@ pub fn edit(_args: ArgMatches, context: &mut Context) -> Result<Option<String>,
 #[allow(clippy::needless_pass_by_value)]
 #[allow(clippy::unnecessary_wraps)]
 pub fn toml(_args: ArgMatches, context: &mut Context) -> Result<Option<String>, BuildRunError> {
-    edit::edit_file(&context.build_state.cargo_toml_path)?;
+    edit_file(&context.build_state.cargo_toml_path)?;
     Ok(Some(String::from("End of Cargo.toml edit")))
 }
 
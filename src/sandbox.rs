@@ -0,0 +1,581 @@
+use crate::config::SandboxConfig;
+use crate::debug_log;
+use crate::errors::BuildRunError;
+
+use lazy_static::lazy_static;
+use regex::bytes::Regex;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process::{Child, Command};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+/// Default CPU time limit for a sandboxed script.
+pub const DEFAULT_CPU_SECS: u64 = 60;
+/// Default limit in MiB on the address space of a sandboxed script.
+pub const DEFAULT_MEMORY_MB: u64 = 1024;
+/// Default limit in MiB on the size of any file written by a sandboxed script.
+pub const DEFAULT_FILE_SIZE_MB: u64 = 64;
+/// Default limit on the number of processes that a sandboxed script may have running. RLIMIT_NPROC
+/// counts all the processes of the user, so the script is run in its own user namespace, where on
+/// Linux 5.14 or later only the script and the processes it starts count. Where user namespaces
+/// are disabled or the kernel is older, the user's other processes count towards the limit too.
+pub const DEFAULT_PROCESSES: u64 = 256;
+/// Subdirectory of the script's generated project that a sandboxed script may write to.
+pub const SCRATCH_SUBDIR: &str = "scratch";
+
+const MIB: u64 = 1024 * 1024;
+/// How much of the end of a sandboxed script's stderr is kept to tell why it aborted.
+const STDERR_TAIL_LEN: usize = 4096;
+/// How long to wait for the rest of a finished script's stderr.
+const STDERR_WAIT: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    /// Messages with which Rust and C++ programs abort when an allocation fails.
+    static ref ALLOC_FAILURE_REGEX: Regex =
+        Regex::new(r"memory allocation of \d+ bytes failed|std::bad_alloc").unwrap();
+}
+
+// The sandbox is only supported on Linux, so the values elsewhere are just placeholders
+#[cfg(unix)]
+const SIGXCPU: i32 = libc::SIGXCPU;
+#[cfg(unix)]
+const SIGXFSZ: i32 = libc::SIGXFSZ;
+#[cfg(unix)]
+const SIGABRT: i32 = libc::SIGABRT;
+#[cfg(not(unix))]
+const SIGXCPU: i32 = 24;
+#[cfg(not(unix))]
+const SIGXFSZ: i32 = 25;
+#[cfg(not(unix))]
+const SIGABRT: i32 = 6;
+
+/// Restrictions applied to a script run with `--sandbox`. Only the run step is sandboxed:
+/// generating and building the script are not.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sandbox {
+    pub cpu_time: Duration,
+    /// Limit on the address space in bytes.
+    pub memory: u64,
+    /// Limit on the size of any file written, in bytes.
+    pub file_size: u64,
+    pub processes: u64,
+    pub allow_net: bool,
+    /// The only directory the script may write to, passed to it as `TMPDIR`.
+    pub scratch_dir: PathBuf,
+}
+
+/// A sandbox limit that the script was terminated for exceeding.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+    CpuTime(Duration),
+    FileSize(u64),
+    Memory(u64),
+}
+
+impl Violation {
+    /// The signal with which the kernel or the Rust runtime terminates a script for the violation.
+    /// SIGABRT only counts as a memory violation if the script reported an allocation failure.
+    #[must_use]
+    pub fn signal(&self) -> i32 {
+        match self {
+            Violation::CpuTime(_) => SIGXCPU,
+            Violation::FileSize(_) => SIGXFSZ,
+            Violation::Memory(_) => SIGABRT,
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::CpuTime(limit) => write!(
+                f,
+                "Script exceeded the sandbox CPU time limit of {}s and was killed",
+                limit.as_secs()
+            ),
+            Violation::FileSize(limit) => write!(
+                f,
+                "Script exceeded the sandbox file size limit of {} MiB and was killed",
+                limit / MIB
+            ),
+            Violation::Memory(limit) => write!(
+                f,
+                "Script ran out of memory under the sandbox memory limit of {} MiB and aborted",
+                limit / MIB
+            ),
+        }
+    }
+}
+
+impl Sandbox {
+    /// A sandbox with the configured limits, or the defaults, writing to the given scratch directory.
+    /// # Errors
+    ///
+    /// Will return `Err` if a limit in MiB is too large to express in bytes.
+    pub fn new(
+        config: &SandboxConfig,
+        allow_net: bool,
+        scratch_dir: PathBuf,
+    ) -> Result<Self, BuildRunError> {
+        Ok(Sandbox {
+            cpu_time: Duration::from_secs(config.cpu_secs.unwrap_or(DEFAULT_CPU_SECS)),
+            memory: mib_to_bytes("memory-mb", config.memory_mb.unwrap_or(DEFAULT_MEMORY_MB))?,
+            file_size: mib_to_bytes(
+                "file-size-mb",
+                config.file_size_mb.unwrap_or(DEFAULT_FILE_SIZE_MB),
+            )?,
+            processes: config.processes.unwrap_or(DEFAULT_PROCESSES),
+            allow_net: allow_net || config.allow_net.unwrap_or(false),
+            scratch_dir,
+        })
+    }
+
+    /// The sandbox limit that a script terminated by the given signal exceeded, if any, given
+    /// the end of what it wrote to stderr. An abort is only put down to the memory limit if
+    /// the script reported an allocation failure, as it may also come from a panic with
+    /// `panic = "abort"`, `std::process::abort()` or a failed assertion in C code.
+    #[must_use]
+    pub fn violation(&self, signal: i32, stderr_tail: &[u8]) -> Option<Violation> {
+        match signal {
+            SIGXCPU => Some(Violation::CpuTime(self.cpu_time)),
+            SIGXFSZ => Some(Violation::FileSize(self.file_size)),
+            SIGABRT if ALLOC_FAILURE_REGEX.is_match(stderr_tail) => {
+                Some(Violation::Memory(self.memory))
+            }
+            _ => None,
+        }
+    }
+
+    /// Spawn the command in the sandbox.
+    /// # Errors
+    ///
+    /// Will return `Err` if the kernel doesn't support the sandbox or it can't be set up.
+    #[cfg(target_os = "linux")]
+    pub fn spawn(&self, command: &mut Command) -> Result<Child, BuildRunError> {
+        use std::os::unix::process::CommandExt;
+
+        fs::create_dir_all(&self.scratch_dir)?;
+        let ruleset = linux::write_ruleset(&self.scratch_dir)?;
+        let ruleset_fd = std::os::fd::AsRawFd::as_raw_fd(&ruleset);
+        let limits = [
+            (
+                libc::RLIMIT_CPU,
+                self.cpu_time.as_secs(),
+                self.cpu_time.as_secs() + 1,
+            ),
+            (libc::RLIMIT_AS, self.memory, self.memory),
+            (libc::RLIMIT_FSIZE, self.file_size, self.file_size),
+            (libc::RLIMIT_NPROC, self.processes, self.processes),
+        ];
+        let id_maps = linux::IdMaps::new();
+        let allow_net = self.allow_net;
+        debug_log!("Sandbox: {self:?}");
+
+        command.env("TMPDIR", &self.scratch_dir);
+        // SAFETY: the closure runs in the forked child before exec, and only makes system
+        // calls on data prepared beforehand, without allocating.
+        unsafe {
+            command.pre_exec(move || {
+                linux::enter_namespaces(&id_maps, allow_net)?;
+                for (resource, soft, hard) in limits {
+                    let limit = libc::rlimit {
+                        rlim_cur: soft,
+                        rlim_max: hard,
+                    };
+                    if libc::setrlimit(resource, &limit) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                linux::restrict_self(ruleset_fd)
+            });
+        }
+        command
+            .spawn()
+            .map_err(|err| BuildRunError::Sandbox(format!("Couldn't sandbox the script: {err}")))
+    }
+
+    /// Spawn the command in the sandbox.
+    /// # Errors
+    ///
+    /// Always returns `Err`, as the sandbox is only supported on Linux.
+    #[cfg(not(target_os = "linux"))]
+    pub fn spawn(&self, _command: &mut Command) -> Result<Child, BuildRunError> {
+        Err(BuildRunError::Sandbox(
+            "--sandbox is only supported on Linux".to_string(),
+        ))
+    }
+}
+
+/// Convert a sandbox limit in MiB to bytes.
+/// # Errors
+///
+/// Will return `Err` if the limit is too large to express in bytes.
+fn mib_to_bytes(setting: &str, mib: u64) -> Result<u64, BuildRunError> {
+    mib.checked_mul(MIB).ok_or_else(|| {
+        BuildRunError::Sandbox(format!("Sandbox limit {setting} = {mib} is too large"))
+    })
+}
+
+/// Relays a sandboxed script's piped stderr to rs-script's own as it is written, keeping the
+/// end of it to tell whether an abort was down to the memory limit.
+pub struct StderrRelay(Receiver<Vec<u8>>);
+
+impl StderrRelay {
+    /// Start relaying the child's stderr, if it is piped.
+    #[must_use]
+    pub fn start(child: &mut Child) -> Option<Self> {
+        let mut stderr = child.stderr.take()?;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut tail = Vec::new();
+            let mut buf = [0; 8192];
+            loop {
+                match stderr.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        let _ = io::stderr().write_all(&buf[..n]);
+                        tail.extend_from_slice(&buf[..n]);
+                        tail.drain(..tail.len().saturating_sub(STDERR_TAIL_LEN));
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+                    Err(_) => break,
+                }
+            }
+            let _ = sender.send(tail);
+        });
+        Some(StderrRelay(receiver))
+    }
+
+    /// The end of the script's stderr once it has closed it, or nothing if it is still open
+    /// shortly after the script finished, e.g. in a process that the script started.
+    #[must_use]
+    pub fn tail(self) -> Vec<u8> {
+        self.0.recv_timeout(STDERR_WAIT).unwrap_or_default()
+    }
+}
+
+/// Spawn the command, in the sandbox if there is one.
+/// # Errors
+///
+/// Will return `Err` if the command or the sandbox fails to start.
+pub fn spawn(command: &mut Command, sandbox: Option<&Sandbox>) -> Result<Child, BuildRunError> {
+    match sandbox {
+        Some(sandbox) => sandbox.spawn(command),
+        None => Ok(command.spawn()?),
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use crate::errors::BuildRunError;
+
+    use std::ffi::CString;
+    use std::io;
+    use std::os::fd::{FromRawFd, OwnedFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    // Landlock filesystem access rights from <linux/landlock.h>
+    const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    const ACCESS_FS_REMOVE_DIR: u64 = 1 << 4;
+    const ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+    const ACCESS_FS_MAKE_CHAR: u64 = 1 << 6;
+    const ACCESS_FS_MAKE_DIR: u64 = 1 << 7;
+    const ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+    const ACCESS_FS_MAKE_SOCK: u64 = 1 << 9;
+    const ACCESS_FS_MAKE_FIFO: u64 = 1 << 10;
+    const ACCESS_FS_MAKE_BLOCK: u64 = 1 << 11;
+    const ACCESS_FS_MAKE_SYM: u64 = 1 << 12;
+    const ACCESS_FS_REFER: u64 = 1 << 13;
+    const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+    const CREATE_RULESET_VERSION: u32 = 1;
+    const RULE_PATH_BENEATH: u32 = 1;
+
+    /// Devices that scripts commonly write to, which remain writable in the sandbox.
+    const WRITABLE_DEVICES: [&str; 3] = ["/dev/null", "/dev/tty", "/dev/zero"];
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    /// Build a Landlock ruleset that denies all writes to the filesystem except to the
+    /// scratch directory and to a few harmless devices. Below Landlock ABI 3 (Linux 6.2),
+    /// truncating files with `truncate` or `O_TRUNC` can't be restricted, so a script can
+    /// still empty any file the user may write to.
+    pub(super) fn write_ruleset(scratch_dir: &Path) -> Result<OwnedFd, BuildRunError> {
+        // SAFETY: querying the ABI version takes no attributes
+        let abi = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<RulesetAttr>(),
+                0,
+                CREATE_RULESET_VERSION,
+            )
+        };
+        if abi < 1 {
+            return Err(BuildRunError::Sandbox(
+                "Landlock is not enabled in this kernel, so filesystem writes can't be restricted"
+                    .to_string(),
+            ));
+        }
+        let file_access = ACCESS_FS_WRITE_FILE | if abi >= 3 { ACCESS_FS_TRUNCATE } else { 0 };
+        let dir_access = file_access
+            | ACCESS_FS_REMOVE_DIR
+            | ACCESS_FS_REMOVE_FILE
+            | ACCESS_FS_MAKE_CHAR
+            | ACCESS_FS_MAKE_DIR
+            | ACCESS_FS_MAKE_REG
+            | ACCESS_FS_MAKE_SOCK
+            | ACCESS_FS_MAKE_FIFO
+            | ACCESS_FS_MAKE_BLOCK
+            | ACCESS_FS_MAKE_SYM
+            | if abi >= 2 { ACCESS_FS_REFER } else { 0 };
+
+        let attr = RulesetAttr {
+            handled_access_fs: dir_access,
+        };
+        // SAFETY: attr is a valid landlock_ruleset_attr of the given size
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr,
+                std::mem::size_of::<RulesetAttr>(),
+                0,
+            )
+        };
+        let ruleset = owned_fd(fd).map_err(|err| {
+            BuildRunError::Sandbox(format!("Couldn't create the sandbox ruleset: {err}"))
+        })?;
+
+        add_rule(&ruleset, scratch_dir, dir_access)?;
+        for device in WRITABLE_DEVICES {
+            let device = Path::new(device);
+            if device.exists() {
+                add_rule(&ruleset, device, file_access)?;
+            }
+        }
+        Ok(ruleset)
+    }
+
+    /// Allow the given access beneath the path.
+    fn add_rule(ruleset: &OwnedFd, path: &Path, access: u64) -> Result<(), BuildRunError> {
+        let sandbox_err =
+            |err| BuildRunError::Sandbox(format!("Couldn't allow writes to {path:?}: {err}"));
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|err| sandbox_err(io::Error::new(io::ErrorKind::InvalidInput, err)))?;
+        // SAFETY: c_path is a valid NUL-terminated string
+        let path_fd =
+            owned_fd(unsafe { libc::open(c_path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC).into() })
+                .map_err(sandbox_err)?;
+        let attr = PathBeneathAttr {
+            allowed_access: access,
+            parent_fd: std::os::fd::AsRawFd::as_raw_fd(&path_fd),
+        };
+        // SAFETY: attr is a valid landlock_path_beneath_attr for the open path
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_add_rule,
+                std::os::fd::AsRawFd::as_raw_fd(ruleset),
+                RULE_PATH_BENEATH,
+                &attr,
+                0,
+            )
+        };
+        if result < 0 {
+            return Err(sandbox_err(io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
+    fn owned_fd(fd: libc::c_long) -> io::Result<OwnedFd> {
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = i32::try_from(fd).map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+        // SAFETY: fd is a newly opened file descriptor that nothing else owns
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// The user and group ID mappings for a new user namespace, which map the current
+    /// user and group to themselves.
+    pub(super) struct IdMaps {
+        uid_map: String,
+        gid_map: String,
+    }
+
+    impl IdMaps {
+        pub(super) fn new() -> Self {
+            // SAFETY: getuid and getgid always succeed
+            let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+            IdMaps {
+                uid_map: format!("{uid} {uid} 1"),
+                gid_map: format!("{gid} {gid} 1"),
+            }
+        }
+    }
+
+    /// Move into a new user namespace, so that the process limit only counts the script and
+    /// its descendants, and unless network access is allowed into a new network namespace,
+    /// leaving the script with only a loopback interface that is down. If user namespaces are
+    /// disabled, fall back on a seccomp filter that denies the creation of internet sockets.
+    /// Runs in the forked child.
+    pub(super) fn enter_namespaces(id_maps: &IdMaps, allow_net: bool) -> io::Result<()> {
+        let flags = if allow_net {
+            libc::CLONE_NEWUSER
+        } else {
+            libc::CLONE_NEWUSER | libc::CLONE_NEWNET
+        };
+        // SAFETY: unshare only affects the calling process
+        if unsafe { libc::unshare(flags) } == 0 {
+            write_proc_file(b"/proc/self/setgroups\0", "deny")?;
+            write_proc_file(b"/proc/self/gid_map\0", &id_maps.gid_map)?;
+            write_proc_file(b"/proc/self/uid_map\0", &id_maps.uid_map)
+        } else if allow_net {
+            Ok(())
+        } else {
+            deny_inet_sockets()
+        }
+    }
+
+    /// Write to a file under /proc, given its NUL-terminated path, without allocating.
+    fn write_proc_file(path: &[u8], contents: &str) -> io::Result<()> {
+        // SAFETY: path is NUL-terminated, and fd is closed after use
+        unsafe {
+            let fd = libc::open(path.as_ptr().cast(), libc::O_WRONLY | libc::O_CLOEXEC);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let written = libc::write(fd, contents.as_ptr().cast(), contents.len());
+            let write_err = io::Error::last_os_error();
+            libc::close(fd);
+            if written < 0 {
+                return Err(write_err);
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xC000_003E;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xC000_00B7;
+    /// System call numbers at or above this are x32 calls on `x86_64`, which are denied
+    /// so that they can't be used to get round the filter.
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+    /// Install a seccomp filter that fails the creation of IPv4 and IPv6 sockets with EACCES.
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    fn deny_inet_sockets() -> io::Result<()> {
+        const fn stmt(code: u32, k: u32) -> libc::sock_filter {
+            #[allow(clippy::cast_possible_truncation)]
+            libc::sock_filter {
+                code: code as u16,
+                jt: 0,
+                jf: 0,
+                k,
+            }
+        }
+        const fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+            #[allow(clippy::cast_possible_truncation)]
+            libc::sock_filter {
+                code: code as u16,
+                jt,
+                jf,
+                k,
+            }
+        }
+        // Offsets of the fields of struct seccomp_data
+        const NR: u32 = 0;
+        const ARCH: u32 = 4;
+        const ARG0: u32 = 16;
+        #[allow(clippy::cast_sign_loss)]
+        let mut filter = [
+            stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, ARCH),
+            jump(
+                libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+                AUDIT_ARCH,
+                1,
+                0,
+            ),
+            stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+            stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, NR),
+            jump(
+                libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K,
+                X32_SYSCALL_BIT,
+                4,
+                0,
+            ),
+            jump(
+                libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+                libc::SYS_socket as u32,
+                0,
+                4,
+            ),
+            stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, ARG0),
+            jump(
+                libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+                libc::AF_INET as u32,
+                1,
+                0,
+            ),
+            jump(
+                libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+                libc::AF_INET6 as u32,
+                0,
+                1,
+            ),
+            stmt(
+                libc::BPF_RET | libc::BPF_K,
+                libc::SECCOMP_RET_ERRNO | libc::EACCES as u32,
+            ),
+            stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW),
+        ];
+        let program = libc::sock_fprog {
+            #[allow(clippy::cast_possible_truncation)]
+            len: filter.len() as u16,
+            filter: filter.as_mut_ptr(),
+        };
+        // SAFETY: program points to a valid filter, which the kernel copies
+        unsafe {
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+                || libc::prctl(
+                    libc::PR_SET_SECCOMP,
+                    libc::SECCOMP_MODE_FILTER,
+                    &program as *const libc::sock_fprog,
+                ) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    fn deny_inet_sockets() -> io::Result<()> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+
+    /// Enforce the Landlock ruleset on the process and its descendants. Runs in the forked child.
+    pub(super) fn restrict_self(ruleset_fd: i32) -> io::Result<()> {
+        // SAFETY: ruleset_fd is the open ruleset, inherited from the parent
+        unsafe {
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+                || libc::syscall(libc::SYS_landlock_restrict_self, ruleset_fd, 0) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,14 +1,19 @@
+use crate::cache::migrate_legacy_dir;
 use crate::cmd_args::{Cli, ProcFlags};
+use crate::code_utils::find_modules_source;
+use crate::config::CONFIG;
 use crate::debug_log;
 use crate::errors::BuildRunError;
 use crate::logging::Verbosity;
-use crate::modified_since_compiled;
+use crate::sandbox::{Sandbox, SCRATCH_SUBDIR};
 use crate::DYNAMIC_SUBDIR;
 use crate::REPL_SUBDIR;
 use crate::RS_SUFFIX;
+use crate::SHARED_TARGET_SUBDIR;
 use crate::TEMP_SCRIPT_NAME;
 use crate::TMPDIR;
 use crate::TOML_NAME;
+use crate::VERSION;
 use crate::{log, PACKAGE_NAME};
 
 use cargo_toml::Manifest;
@@ -17,11 +22,19 @@ use proc_macro2::TokenStream;
 use quote::ToTokens;
 use ratatui::crossterm::cursor::{MoveToColumn, Show};
 use ratatui::crossterm::ExecutableCommand;
+use serde::{Deserialize, Serialize};
+use siphasher::sip::SipHasher13;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::error::Error;
+use std::fs;
+use std::hash::Hasher;
 use std::io::{stdout, Write};
+use std::process::Command;
 use std::{
+    ffi::OsString,
     path::{Path, PathBuf},
-    time::Instant,
+    time::{Duration, Instant},
 };
 use strum::Display;
 
@@ -51,6 +64,64 @@ impl ToTokens for Ast {
     }
 }
 
+/// Toolchain and cargo feature selection for a build, from the command line
+/// or from an `[rs-script]` table in the script's toml block.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct BuildOptions {
+    /// Rustup toolchain, e.g. `nightly` or `1.74.1`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub toolchain: Option<String>,
+    /// Features of the generated package to activate.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<String>,
+    /// Don't activate the `default` feature.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub no_default_features: bool,
+}
+
+impl BuildOptions {
+    /// Fill in anything not chosen here from the script's own options. Features accumulate.
+    #[must_use]
+    pub fn with_defaults(self, script_options: Self) -> Self {
+        let mut features = self.features;
+        for feature in script_options.features {
+            if !features.contains(&feature) {
+                features.push(feature);
+            }
+        }
+        Self {
+            toolchain: self.toolchain.or(script_options.toolchain),
+            features,
+            no_default_features: self.no_default_features || script_options.no_default_features,
+        }
+    }
+
+    /// Make sure a script that enables unstable features with `#![feature(...)]`
+    /// will be built with a nightly toolchain, choosing nightly if none was specified.
+    /// # Errors
+    ///
+    /// Will return `Err` if a different toolchain was specified.
+    pub fn for_unstable_features(self) -> Result<Self, BuildRunError> {
+        match self.toolchain {
+            None => {
+                log!(
+                    Verbosity::Normal,
+                    "Script uses #![feature(...)], so building with the nightly toolchain"
+                );
+                Ok(Self {
+                    toolchain: Some("nightly".to_string()),
+                    ..self
+                })
+            }
+            Some(ref toolchain) if toolchain.starts_with("nightly") => Ok(self),
+            Some(toolchain) => Err(BuildRunError::Command(format!(
+                "Script uses #![feature(...)], which needs a nightly toolchain, but toolchain {toolchain} was specified"
+            ))),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct BuildState {
     #[allow(dead_code)]
@@ -64,10 +135,21 @@ pub struct BuildState {
     pub target_dir_path: PathBuf,
     pub target_path: PathBuf,
     pub cargo_toml_path: PathBuf,
+    pub cargo_profile: Option<String>,
+    /// Cargo target directory shared by all generated projects, if `--shared-target` is in force.
+    pub shared_target_dir: Option<PathBuf>,
+    pub build_options: BuildOptions,
+    pub timeout: Option<Duration>,
+    /// Whether `--offline` is in force, so that cargo must not touch the network.
+    pub offline: bool,
+    pub sandbox: Option<Sandbox>,
     pub rs_manifest: Option<Manifest>,
     pub cargo_manifest: Option<Manifest>,
     pub must_gen: bool,
     pub must_build: bool,
+    /// The rustc versions looked up for the fingerprint so far, by toolchain, so that rustc
+    /// is only run once for each.
+    pub rustc_versions: RefCell<BTreeMap<Option<String>, String>>,
 }
 
 impl BuildState {
@@ -79,9 +161,10 @@ impl BuildState {
     ) -> Result<Self, Box<dyn Error>> {
         let is_repl = proc_flags.contains(ProcFlags::REPL);
         let is_expr = options.expression.is_some();
+        let is_loop = proc_flags.contains(ProcFlags::LOOP);
         let is_stdin = proc_flags.contains(ProcFlags::STDIN);
         let is_edit = proc_flags.contains(ProcFlags::EDIT);
-        let is_dynamic = is_expr | is_stdin | is_edit;
+        let is_dynamic = is_expr | is_loop | is_stdin | is_edit;
         let maybe_script = script_state.get_script();
         if maybe_script.is_none() {
             return Err(Box::new(BuildRunError::NoneOption(
@@ -138,14 +221,7 @@ impl BuildState {
             .parent()
             .expect("Problem resolving to parent directory")
             .to_path_buf();
-        let cargo_home = PathBuf::from(match std::env::var("CARGO_HOME") {
-            Ok(string) if string != String::new() => string,
-            _ => {
-                let home_dir = home_dir().ok_or("Can't resolve home directory")?;
-                debug_log!("home_dir={}", home_dir.display());
-                home_dir.join(".cargo").display().to_string()
-            }
-        });
+        let cargo_home = cargo_home()?;
         debug_log!("cargo_home={}", cargo_home.display());
 
         let target_dir_path = if is_repl {
@@ -156,11 +232,16 @@ impl BuildState {
         } else if is_dynamic {
             TMPDIR.join(DYNAMIC_SUBDIR)
         } else {
-            TMPDIR.join(PACKAGE_NAME).join(&source_stem)
+            let target_dir_path = script_target_dir(&source_path);
+            migrate_legacy_dir(&source_path, &target_dir_path)?;
+            target_dir_path
         };
 
         debug_log!("target_dir_path={}", target_dir_path.display());
-        let mut target_path = target_dir_path.join("target").join("debug");
+        let cargo_profile = options.cargo_profile();
+        let mut target_path = target_dir_path
+            .join("target")
+            .join(profile_target_subdir(cargo_profile.as_deref()));
         target_path = if cfg!(windows) {
             target_path.join(source_stem.clone() + ".exe")
         } else {
@@ -170,6 +251,16 @@ impl BuildState {
         let target_path_clone = target_path.clone();
 
         let cargo_toml_path = target_dir_path.join(TOML_NAME).clone();
+        let sandbox = options
+            .sandbox
+            .then(|| {
+                Sandbox::new(
+                    &CONFIG.sandbox,
+                    options.allow_net,
+                    target_dir_path.join(SCRATCH_SUBDIR),
+                )
+            })
+            .transpose()?;
 
         let mut build_state = Self {
             working_dir_path,
@@ -181,16 +272,33 @@ impl BuildState {
             target_dir_path,
             target_path,
             cargo_toml_path,
+            cargo_profile,
+            shared_target_dir: options
+                .shared_target
+                .then(|| TMPDIR.join(SHARED_TARGET_SUBDIR)),
+            build_options: options.build_options(),
+            timeout: options.timeout,
+            offline: options.offline
+                || std::env::var("CARGO_NET_OFFLINE").is_ok_and(|value| value == "true"),
+            sandbox,
             ..Default::default()
         };
 
-        let force = proc_flags.contains(ProcFlags::FORCE);
+        // Checking, linting and testing don't produce an executable to compare the source with,
+        // and updating the toml block needs the dependencies to be resolved
+        let force = proc_flags.intersects(
+            ProcFlags::FORCE
+                | ProcFlags::CHECK
+                | ProcFlags::CLIPPY
+                | ProcFlags::TEST
+                | ProcFlags::UPDATE_TOML,
+        );
         (build_state.must_gen, build_state.must_build) = if force {
             (true, true)
         } else {
             let stale_executable = matches!(script_state, ScriptState::NamedEmpty { .. })
                 || !target_path_clone.exists()
-                || modified_since_compiled(&build_state).is_some();
+                || build_state.fingerprint_changed();
             let gen_requested = proc_flags.contains(ProcFlags::GENERATE);
             let build_requested = proc_flags.contains(ProcFlags::BUILD);
             let must_gen = force || is_repl || (gen_requested && stale_executable);
@@ -202,6 +310,268 @@ impl BuildState {
 
         Ok(build_state)
     }
+
+    /// Name of the binary target in the generated manifest. In a shared target directory it
+    /// is qualified by a hash of the project directory, so that scripts, REPL sessions and
+    /// expressions of the same name don't overwrite each other's executables.
+    #[must_use]
+    pub fn bin_name(&self) -> String {
+        if self.shared_target_dir.is_some() {
+            shared_bin_name(&self.source_stem, &self.target_dir_path)
+        } else {
+            self.source_stem.clone()
+        }
+    }
+
+    /// Where cargo puts the executable in the shared target directory, if one is in use,
+    /// for copying to `target_path`.
+    #[must_use]
+    pub fn shared_target_binary(&self) -> Option<PathBuf> {
+        let shared_target_dir = self.shared_target_dir.as_ref()?;
+        let bin_name = self.bin_name();
+        let binary = if cfg!(windows) {
+            bin_name + ".exe"
+        } else {
+            bin_name
+        };
+        Some(
+            shared_target_dir
+                .join(profile_target_subdir(self.cargo_profile.as_deref()))
+                .join(binary),
+        )
+    }
+
+    /// File recording the fingerprint of the last build of the executable.
+    #[must_use]
+    pub fn fingerprint_path(&self) -> PathBuf {
+        let mut path = OsString::from(self.target_path.as_os_str());
+        path.push(".fingerprint");
+        PathBuf::from(path)
+    }
+
+    /// The fingerprint of the executable that would be built now, given the toolchain and
+    /// feature options requested on the command line and the toolchain that builds it, which
+    /// may instead come from the toml block or be chosen for unstable features. Options from
+    /// the script itself are covered by its content.
+    #[must_use]
+    pub fn fingerprint(&self, requested: &BuildOptions, toolchain: Option<&str>) -> Fingerprint {
+        let source = fs::read_to_string(&self.source_path).unwrap_or_default();
+        let modules = module_files(&self.source_path, &source)
+            .into_iter()
+            .map(|path| {
+                let hash = fs::read(&path).map(|contents| content_hash(&contents));
+                (path, hash.unwrap_or_default())
+            })
+            .collect();
+        Fingerprint {
+            rs_script_version: VERSION.to_string(),
+            rustc_version: self.rustc_version(toolchain),
+            toolchain: toolchain.map(ToString::to_string),
+            profile: self.cargo_profile.clone().unwrap_or_default(),
+            build_options: requested.clone(),
+            source: content_hash(source.as_bytes()),
+            manifest: fs::read(&self.cargo_toml_path)
+                .map(|contents| content_hash(&contents))
+                .unwrap_or_default(),
+            modules,
+        }
+    }
+
+    /// Record the fingerprint of the executable just built, with the toolchain resolved by
+    /// the generation step.
+    /// # Errors
+    ///
+    /// Will return `Err` if the fingerprint can't be serialized or the file can't be written.
+    pub fn save_fingerprint(&self, requested: &BuildOptions) -> Result<(), BuildRunError> {
+        let toolchain = self.build_options.toolchain.as_deref();
+        let fingerprint = toml::to_string(&self.fingerprint(requested, toolchain))?;
+        fs::write(self.fingerprint_path(), fingerprint)?;
+        Ok(())
+    }
+
+    /// Whether anything that goes into the executable has changed since it was last built,
+    /// logging what has changed in verbose mode.
+    fn fingerprint_changed(&self) -> bool {
+        let previous = fs::read_to_string(self.fingerprint_path())
+            .ok()
+            .and_then(|previous| toml::from_str::<Fingerprint>(&previous).ok());
+        let Some(previous) = previous else {
+            log!(Verbosity::Verbose, "No fingerprint of the last build found");
+            return true;
+        };
+        // Unless the script or the options requested have changed, which will show up anyway,
+        // it would be built with the same toolchain as last time
+        let changes = self
+            .fingerprint(&self.build_options, previous.toolchain.as_deref())
+            .changes_from(&previous);
+        for change in &changes {
+            log!(
+                Verbosity::Verbose,
+                "The {change} changed since the last build"
+            );
+        }
+        !changes.is_empty()
+    }
+
+    /// The version reported by rustc for the given toolchain, looked up the first time only.
+    fn rustc_version(&self, toolchain: Option<&str>) -> String {
+        self.rustc_versions
+            .borrow_mut()
+            .entry(toolchain.map(ToString::to_string))
+            .or_insert_with(|| rustc_version(toolchain))
+            .clone()
+    }
+}
+
+/// What went into a build of a script's executable, recorded beside it so that it is only
+/// rebuilt when one of these changes. File contents are represented by their hashes.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Fingerprint {
+    pub rs_script_version: String,
+    /// The version of rustc of the toolchain that built the executable.
+    pub rustc_version: String,
+    /// The toolchain that built the executable, or `None` for the default one.
+    pub toolchain: Option<String>,
+    pub profile: String,
+    pub build_options: BuildOptions,
+    pub source: String,
+    /// The generated Cargo.toml, with the dependencies as resolved.
+    pub manifest: String,
+    /// The module files declared by the script.
+    pub modules: BTreeMap<PathBuf, String>,
+}
+
+impl Fingerprint {
+    /// Descriptions of what differs from a previous fingerprint.
+    #[must_use]
+    pub fn changes_from(&self, previous: &Fingerprint) -> Vec<&'static str> {
+        [
+            (
+                self.rs_script_version != previous.rs_script_version,
+                "rs-script version",
+            ),
+            (
+                self.rustc_version != previous.rustc_version,
+                "rustc version",
+            ),
+            (self.profile != previous.profile, "cargo profile"),
+            (
+                self.build_options != previous.build_options,
+                "toolchain or feature selection",
+            ),
+            (self.source != previous.source, "script"),
+            (self.manifest != previous.manifest, "generated Cargo.toml"),
+            (self.modules != previous.modules, "script's module files"),
+        ]
+        .into_iter()
+        .filter_map(|(changed, what)| changed.then_some(what))
+        .collect()
+    }
+}
+
+/// A hash of file contents that is stable across platforms and Rust versions.
+#[must_use]
+pub fn content_hash(contents: &[u8]) -> String {
+    let mut hasher = SipHasher13::new();
+    hasher.write(contents);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Name of the binary target of a project built in the shared target directory, qualified by
+/// a hash of the project directory.
+#[must_use]
+pub fn shared_bin_name(source_stem: &str, target_dir_path: &Path) -> String {
+    let hash = content_hash(target_dir_path.to_string_lossy().as_bytes());
+    format!("{source_stem}-{}", &hash[..8])
+}
+
+/// The version reported by rustc for the given toolchain, or the default toolchain.
+/// Empty if rustc can't be run, in which case the build will report the problem.
+#[must_use]
+pub fn rustc_version(toolchain: Option<&str>) -> String {
+    let mut command = Command::new("rustc");
+    if let Some(toolchain) = toolchain {
+        command.arg(format!("+{toolchain}"));
+    }
+    command
+        .arg("--version")
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_default()
+}
+
+/// The files of the modules declared by a script with `mod <name>;`, which may be
+/// `<name>.rs` or `<name>/mod.rs` in the script's directory.
+#[must_use]
+pub fn module_files(source_path: &Path, source: &str) -> Vec<PathBuf> {
+    let Some(source_dir) = source_path.parent() else {
+        return vec![];
+    };
+    find_modules_source(source)
+        .into_iter()
+        .flat_map(|module| {
+            [
+                source_dir.join(format!("{module}{RS_SUFFIX}")),
+                source_dir.join(&module).join("mod.rs"),
+            ]
+        })
+        .filter(|path| path.exists())
+        .collect()
+}
+
+/// Cargo's home directory: `$CARGO_HOME` if set, otherwise `~/.cargo`.
+/// # Errors
+///
+/// Will return `Err` if `$CARGO_HOME` is not set and the home directory can't be resolved.
+pub fn cargo_home() -> Result<PathBuf, BuildRunError> {
+    match std::env::var("CARGO_HOME") {
+        Ok(string) if !string.is_empty() => Ok(PathBuf::from(string)),
+        _ => {
+            let home_dir = home_dir().ok_or_else(|| {
+                BuildRunError::NoneOption("Can't resolve home directory".to_string())
+            })?;
+            debug_log!("home_dir={}", home_dir.display());
+            Ok(home_dir.join(".cargo"))
+        }
+    }
+}
+
+/// Directory of the generated Cargo project for a named script, keyed on the script's
+/// canonical path so that scripts of the same name in different directories don't collide.
+#[must_use]
+pub fn script_target_dir(source_path: &Path) -> PathBuf {
+    TMPDIR.join(PACKAGE_NAME).join(script_dir_name(source_path))
+}
+
+/// Name of the generated project directory of a script: its file stem followed by a
+/// short hash of its canonical path, e.g. `fizz_buzz-1a2b3c4d`.
+#[must_use]
+pub fn script_dir_name(source_path: &Path) -> String {
+    let path = canonical_script_path(source_path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let hash = content_hash(path.to_string_lossy().as_bytes());
+    format!("{stem}-{}", &hash[..8])
+}
+
+/// The canonical path of a script, or if it no longer exists, its absolute path.
+#[must_use]
+pub fn canonical_script_path(source_path: &Path) -> PathBuf {
+    source_path.canonicalize().unwrap_or_else(|_| {
+        std::env::current_dir()
+            .map(|dir| dir.join(source_path))
+            .unwrap_or_else(|_| source_path.to_path_buf())
+    })
+}
+
+/// Name of the subdirectory of `target` where cargo puts the artifacts for a profile.
+/// The built-in `dev` and `test` profiles share `debug`, and `bench` shares `release`.
+pub fn profile_target_subdir(profile: Option<&str>) -> &str {
+    match profile {
+        None | Some("dev" | "test") => "debug",
+        Some("bench") => "release",
+        Some(profile) => profile,
+    }
 }
 
 #[derive(Debug)]
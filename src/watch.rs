@@ -0,0 +1,183 @@
+use crate::builder::{gen_build_run, received_signal, reraise_received_signal, wait_for_script};
+use crate::cmd_args::{Cli, ProcFlags};
+use crate::code_utils::read_file_contents;
+use crate::colors::{nu_resolve_style, MessageLevel};
+use crate::debug_log;
+use crate::errors::BuildRunError;
+use crate::events::{self, OutputEvents};
+use crate::log;
+use crate::logging::Verbosity;
+use crate::sandbox;
+use crate::shared::{clear_screen, module_files, BuildState};
+
+use ratatui::crossterm::cursor::MoveTo;
+use ratatui::crossterm::terminal::{Clear, ClearType};
+use ratatui::crossterm::ExecutableCommand;
+use std::error::Error;
+use std::fs;
+use std::io::stdout;
+use std::path::PathBuf;
+use std::process::{Child, Command};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often to look for changes to the watched files.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How long the watched files must stay unchanged before a rerun, so that a burst of
+/// saves from an editor only triggers one.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Watch a script and any module files it references, and rerun the generate, build
+/// and run pipeline each time they change, until interrupted with Ctrl-C.
+/// # Errors
+///
+/// Will return `Err` if the script can't be spawned or waited on. Errors from the pipeline
+/// itself are displayed and watching continues.
+pub fn watch(
+    options: &mut Cli,
+    proc_flags: &ProcFlags,
+    build_state: &mut BuildState,
+) -> Result<(), Box<dyn Error>> {
+    // Run the script here rather than in gen_build_run, so that it can be killed on a change
+    let run_requested = proc_flags.contains(ProcFlags::RUN);
+    let mut pipeline_flags = proc_flags.clone();
+    pipeline_flags.remove(ProcFlags::RUN);
+
+    loop {
+        clear_terminal()?;
+        let start = Instant::now();
+        let child = match gen_build_run(options, &pipeline_flags, build_state, None, &start) {
+            Ok(()) if run_requested => Some(spawn_script(&options.args, build_state)?),
+            Ok(()) => None,
+            Err(err) => {
+                log!(
+                    Verbosity::Quiet,
+                    "{}",
+                    nu_resolve_style(MessageLevel::Error).paint(err.to_string())
+                );
+                None
+            }
+        };
+
+        let watched_files = watched_files(build_state);
+        debug_log!("watched_files={watched_files:#?}");
+        let dur = start.elapsed();
+        log!(
+            Verbosity::Quiet,
+            "{}",
+            nu_resolve_style(MessageLevel::Emphasis).paint(format!(
+                "[{}.{:03}s] Watching {} for changes. Press Ctrl-C to quit.",
+                dur.as_secs(),
+                dur.subsec_millis(),
+                build_state.source_name
+            ))
+        );
+
+        // Wait for a change, noting if the script finishes or times out first, and
+        // stopping it if it's still running when the change comes. A signal such as
+        // Ctrl-C stops it too, then ends the watch.
+        let modified = modification_times(&watched_files);
+        let mut last_polled = Instant::now();
+        let mut changed = || {
+            if last_polled.elapsed() < POLL_INTERVAL {
+                return false;
+            }
+            last_polled = Instant::now();
+            modification_times(&watched_files) != modified
+        };
+        if let Some(mut running) = child {
+            let stop = || received_signal().is_some() || changed();
+            match wait_for_script(&mut running, build_state.timeout, stop) {
+                Ok(Some(exit_status)) => log!(
+                    Verbosity::Normal,
+                    "{}",
+                    nu_resolve_style(MessageLevel::Ghost).paint(format!(
+                        "{} exited with {exit_status}",
+                        build_state.source_stem
+                    ))
+                ),
+                Ok(None) => {
+                    debug_log!("Stopped {} after a change", build_state.source_stem);
+                }
+                Err(err @ BuildRunError::Timeout(_)) => log!(
+                    Verbosity::Quiet,
+                    "{}",
+                    nu_resolve_style(MessageLevel::Error).paint(err.to_string())
+                ),
+                Err(err) => return Err(err.into()),
+            }
+            reraise_received_signal();
+        }
+        while !changed() {
+            thread::sleep(POLL_INTERVAL);
+        }
+        debounce(&watched_files);
+
+        // Start afresh from the changed source, including its toml block
+        build_state.must_gen = true;
+        build_state.must_build = true;
+        build_state.rs_manifest = None;
+        build_state.cargo_manifest = None;
+        build_state.build_options = options.build_options();
+    }
+}
+
+/// The script and any existing module files that it declares with `mod <name>;`.
+fn watched_files(build_state: &BuildState) -> Vec<PathBuf> {
+    let mut files = vec![build_state.source_path.clone()];
+    if let Ok(source) = read_file_contents(&build_state.source_path) {
+        files.extend(module_files(&build_state.source_path, &source));
+    }
+    files
+}
+
+/// The modification times of the files, with `None` for any that can't be read,
+/// e.g. because an editor is in the middle of replacing them.
+fn modification_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|file| {
+            fs::metadata(file)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+        .collect()
+}
+
+/// Wait until the files have stopped changing.
+fn debounce(files: &[PathBuf]) {
+    let mut modified = modification_times(files);
+    loop {
+        thread::sleep(DEBOUNCE);
+        let latest = modification_times(files);
+        if latest == modified {
+            break;
+        }
+        modified = latest;
+    }
+}
+
+/// Start the built script without waiting for it to finish.
+fn spawn_script(args: &[String], build_state: &BuildState) -> Result<Child, Box<dyn Error>> {
+    let mut run_command = Command::new(format!("{}", build_state.target_path.display()));
+    run_command.args(args);
+    debug_log!("Run command is {run_command:?}");
+    OutputEvents::pipe(&mut run_command);
+    let mut child = sandbox::spawn(&mut run_command, build_state.sandbox.as_ref())?;
+    // Relayed until the script closes its stdout
+    let _ = OutputEvents::start(&mut child);
+    Ok(child)
+}
+
+/// Clear the terminal and put the cursor back at the top left, unless stdout is reserved for
+/// JSON events.
+fn clear_terminal() -> Result<(), Box<dyn Error>> {
+    if events::json_enabled() {
+        return Ok(());
+    }
+    let mut out = stdout();
+    out.execute(Clear(ClearType::All))?;
+    out.execute(MoveTo(0, 0))?;
+    clear_screen();
+    Ok(())
+}
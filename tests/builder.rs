@@ -4,12 +4,14 @@ mod tests {
     use cargo_toml::Manifest;
     use rs_script::builder::{build, generate, run};
     use rs_script::cmd_args::Cli;
+    use rs_script::shared::script_target_dir;
     use rs_script::{code_utils, escape_path_for_windows, execute, TMPDIR};
     use rs_script::{BuildState, ProcFlags};
     // use sequential_test::sequential;
     use std::env::current_dir;
     use std::fs::{self, OpenOptions};
     use std::io::Write;
+    use std::path::Path;
 
     // Helper function to create a sample Cli structure
     fn create_sample_cli(script: Option<String>) -> Cli {
@@ -30,7 +32,8 @@ mod tests {
         let current_dir = current_dir().expect("Could not get current dir");
         let working_dir_path = current_dir.clone();
         let cargo_home = home::cargo_home().expect("Could not get Cargo home");
-        let target_dir_path = TMPDIR.join("rs-script").join(source_stem);
+        let source_path = current_dir.join("tests/assets").join(source_name);
+        let target_dir_path = script_target_dir(&source_path);
         fs::create_dir_all(target_dir_path.clone()).expect("Failed to create script directory");
         let target_path = target_dir_path
             .clone()
@@ -38,7 +41,6 @@ mod tests {
             .join(source_stem);
         let cargo_toml_path = target_dir_path.clone().join("Cargo.toml");
         let source_dir_path = current_dir.clone().join("tests/assets");
-        let source_path = current_dir.clone().join("tests/assets").join(source_name);
         BuildState {
             working_dir_path,
             source_stem: source_stem.into(),
@@ -53,6 +55,7 @@ mod tests {
             cargo_manifest: None,
             must_gen: true,
             must_build: true,
+            ..Default::default()
         }
     }
 
@@ -129,7 +132,7 @@ mod tests {
         let current_dir = current_dir().expect("Could not get current dir");
         let source_path = current_dir.join("tests/assets").join(source_name);
         let cargo_home = home::cargo_home().expect("Could not get Cargo home");
-        let target_dir_path = TMPDIR.join("rs-script").join(source_stem);
+        let target_dir_path = script_target_dir(&source_path);
         fs::create_dir_all(target_dir_path.clone()).expect("Failed to create script directory");
         let cargo_toml_path = target_dir_path.clone().join("Cargo.toml");
         let cargo_toml = format!(
@@ -197,6 +200,7 @@ name = "bitflags_t"
             cargo_manifest: None,
             must_gen: true,
             must_build: true,
+            ..Default::default()
         };
         dbg!(&build_state);
         let proc_flags = ProcFlags::empty();
@@ -211,10 +215,8 @@ name = "bitflags_t"
         let source_stem: &str = source_name
             .strip_suffix(rs_script::RS_SUFFIX)
             .expect("Problem stripping Rust suffix");
-        let target_dir_path = TMPDIR
-            .join("rs-script")
-            .join(source_stem)
-            .join("target/debug");
+        let target_dir_path =
+            script_target_dir(Path::new("tests/assets/fib_fac_dashu_t.rs")).join("target/debug");
         let target_path = if cfg!(windows) {
             target_dir_path.join(source_stem.to_string() + ".exe")
         } else {
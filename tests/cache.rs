@@ -0,0 +1,165 @@
+#[cfg(test)]
+mod tests {
+    use rs_script::cache::{
+        find_shared_artifacts, format_age, format_size, gc_victims, CacheEntry, CacheKind,
+        GcCriteria, SOURCE_PATH_FILE,
+    };
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::{Duration, SystemTime};
+
+    const DAY: Duration = Duration::from_secs(86_400);
+
+    fn entry(name: &str, age_days: u32, dir_size: u64, source_path: Option<&str>) -> CacheEntry {
+        CacheEntry {
+            name: name.to_string(),
+            kind: CacheKind::Script,
+            dir: PathBuf::from("/tmp/rs-script").join(name),
+            source_path: source_path.map(PathBuf::from),
+            binaries: vec![],
+            dir_size,
+            modified: SystemTime::UNIX_EPOCH + DAY * (100 - age_days),
+            shared_artifacts: vec![],
+        }
+    }
+
+    fn names(victims: &[&CacheEntry]) -> Vec<String> {
+        victims.iter().map(|entry| entry.name.clone()).collect()
+    }
+
+    #[test]
+    fn test_gc_victims() {
+        let now = SystemTime::UNIX_EPOCH + DAY * 100;
+        let entries = vec![
+            entry("old", 40, 300, None),
+            entry("recent", 1, 200, None),
+            entry("orphan", 2, 100, Some("/no/such/dir/orphan.rs")),
+            entry("middle", 10, 400, Some(env!("CARGO_MANIFEST_DIR"))),
+        ];
+
+        let older_than = GcCriteria {
+            older_than: Some(DAY * 30),
+            ..Default::default()
+        };
+        assert_eq!(names(&gc_victims(&entries, &older_than, now)), ["old"]);
+
+        let orphans = GcCriteria {
+            orphans: true,
+            ..Default::default()
+        };
+        assert_eq!(names(&gc_victims(&entries, &orphans, now)), ["orphan"]);
+
+        // The least recently used go first until the rest fit in the budget
+        let max_size = GcCriteria {
+            max_size: Some(350),
+            ..Default::default()
+        };
+        assert_eq!(
+            names(&gc_victims(&entries, &max_size, now)),
+            ["old", "middle"]
+        );
+
+        let combined = GcCriteria {
+            orphans: true,
+            max_size: Some(600),
+            ..Default::default()
+        };
+        assert_eq!(
+            names(&gc_victims(&entries, &combined, now)),
+            ["orphan", "old"]
+        );
+    }
+
+    #[test]
+    fn test_gc_victims_shared_target() {
+        let now = SystemTime::UNIX_EPOCH + DAY * 100;
+        let mut old = entry("old", 40, 100, None);
+        old.shared_artifacts = vec![(PathBuf::from("/tmp/rs_target/debug/old-1234abcd"), 300)];
+        let shared_target = CacheEntry {
+            kind: CacheKind::SharedTarget,
+            ..entry("rs_target", 0, 500, None)
+        };
+        let entries = vec![old, entry("recent", 1, 100, None), shared_target];
+
+        // Pruning a project frees its files in the shared target directory too
+        let max_size = GcCriteria {
+            max_size: Some(400),
+            ..Default::default()
+        };
+        assert_eq!(names(&gc_victims(&entries, &max_size, now)), ["old"]);
+
+        // Which is pruned like a project if that is not enough
+        let max_size = GcCriteria {
+            max_size: Some(150),
+            ..Default::default()
+        };
+        assert_eq!(
+            names(&gc_victims(&entries, &max_size, now)),
+            ["old", "recent", "rs_target"]
+        );
+    }
+
+    #[test]
+    fn test_find_shared_artifacts() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut entries = [entry("hello", 1, 0, Some("/no/such/dir/hello.rs"))];
+        let bin_name = entries[0].shared_bin_name();
+        let crate_name = bin_name.replace('-', "_");
+        let debug_dir = dir.path().join("debug");
+        let incremental_dir = debug_dir
+            .join("incremental")
+            .join(format!("{crate_name}-x1"));
+        fs::create_dir_all(debug_dir.join("deps")).unwrap();
+        fs::create_dir_all(&incremental_dir).unwrap();
+        fs::write(debug_dir.join(&bin_name), [0u8; 1000]).unwrap();
+        fs::write(debug_dir.join(format!("{bin_name}.d")), [0u8; 10]).unwrap();
+        fs::write(
+            debug_dir.join("deps").join(format!("{crate_name}-a1")),
+            [0u8; 1000],
+        )
+        .unwrap();
+        fs::write(incremental_dir.join("query-cache.bin"), [0u8; 100]).unwrap();
+        let fingerprint_dir = debug_dir.join(".fingerprint").join("hello-a1");
+        fs::create_dir_all(&fingerprint_dir).unwrap();
+        fs::write(fingerprint_dir.join("bin-hello"), [0u8; 16]).unwrap();
+        // Dependencies and other scripts' executables are not the script's
+        fs::write(debug_dir.join("deps").join("libregex-b2.rlib"), [0u8; 50]).unwrap();
+        fs::write(debug_dir.join("hello-00000000"), [0u8; 50]).unwrap();
+
+        find_shared_artifacts(dir.path(), &mut entries).unwrap();
+        assert_eq!(entries[0].shared_artifacts.len(), 5);
+        assert_eq!(entries[0].shared_size(), 2126);
+    }
+
+    #[test]
+    fn test_cache_entry_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("hello");
+        let binary_dir = project.join("target").join("release");
+        fs::create_dir_all(&binary_dir).unwrap();
+        fs::write(binary_dir.join("hello"), [0u8; 1000]).unwrap();
+        fs::write(project.join("hello.rs"), "fn main() {}").unwrap();
+        fs::write(project.join(SOURCE_PATH_FILE), "/no/such/dir/hello.rs\n").unwrap();
+
+        let entry = CacheEntry::read(&project, CacheKind::Script).unwrap();
+        assert_eq!(entry.name, "hello");
+        assert_eq!(entry.binary_size(), Some(1000));
+        assert!(entry.last_build().is_some());
+        assert_eq!(
+            entry.source_path,
+            Some(PathBuf::from("/no/such/dir/hello.rs"))
+        );
+        assert!(entry.is_orphan());
+        assert!(entry.dir_size >= 1012);
+    }
+
+    #[test]
+    fn test_format_size_and_age() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(3 << 30), "3.0 GiB");
+        let now = SystemTime::UNIX_EPOCH + DAY * 10;
+        assert_eq!(format_age(now, now - Duration::from_secs(90)), "1m ago");
+        assert_eq!(format_age(now, now - DAY * 3), "3d ago");
+    }
+}
@@ -1,5 +1,6 @@
 use clap::Parser;
-use rs_script::{get_proc_flags, Cli, ProcFlags};
+use rs_script::cmd_args::expand_toolchain_arg;
+use rs_script::{get_proc_flags, validate_args, Cli, Commands, ProcFlags};
 
 #[test]
 fn test_get_args_script() {
@@ -55,3 +56,346 @@ fn test_conflicts_with_all() {
     // println!("result={result:#?}");
     assert!(result.is_err()); // or check for specific error
 }
+
+#[test]
+fn test_run_command() {
+    let args = vec!["rs_script", "run", "demo_script.rs", "--", "arg1", "arg2"];
+    let mut cli = Cli::parse_from(args);
+    cli.apply_command().expect("Error applying subcommand");
+    assert!(Some("demo_script.rs") == cli.script.as_deref());
+    assert!(vec!["arg1", "arg2"] == cli.args);
+    let proc_flags = get_proc_flags(&cli).expect("Couldn't access ProcFlags");
+    assert!(proc_flags.contains(ProcFlags::RUN));
+}
+
+#[test]
+fn test_build_command() {
+    let args = vec!["rs_script", "build", "-v", "demo_script.rs"];
+    let mut cli = Cli::parse_from(args);
+    cli.apply_command().expect("Error applying subcommand");
+    assert!(Some("demo_script.rs") == cli.script.as_deref());
+    let proc_flags = get_proc_flags(&cli).expect("Couldn't access ProcFlags");
+    assert!(proc_flags.contains(ProcFlags::BUILD | ProcFlags::NORUN | ProcFlags::VERBOSE));
+    assert!(!proc_flags.contains(ProcFlags::RUN));
+}
+
+#[test]
+fn test_expr_and_repl_commands() {
+    let mut cli = Cli::parse_from(vec!["rs_script", "expr", "'2 + 5'"]);
+    cli.apply_command().expect("Error applying subcommand");
+    assert!(Some("'2 + 5'") == cli.expression.as_deref());
+
+    let mut cli = Cli::parse_from(vec!["rs_script", "repl"]);
+    cli.apply_command().expect("Error applying subcommand");
+    assert!(cli.repl);
+    assert!(cli.script.is_none());
+}
+
+#[test]
+fn test_clean_command_requires_script_or_all() {
+    assert!(Cli::try_parse_from(vec!["rs_script", "clean"]).is_err());
+    assert!(Cli::try_parse_from(vec!["rs_script", "clean", "--all"]).is_ok());
+    assert!(Cli::try_parse_from(vec!["rs_script", "clean", "demo_script.rs"]).is_ok());
+}
+
+#[test]
+fn test_command_conflicts_with_options() {
+    let mut cli = Cli::parse_from(vec!["rs_script", "-l", "run", "demo_script.rs"]);
+    assert!(cli.apply_command().is_err());
+}
+
+#[test]
+fn test_release_and_profile_options() {
+    let cli = Cli::parse_from(vec!["rs_script", "--release", "demo_script.rs"]);
+    assert_eq!(cli.cargo_profile().as_deref(), Some("release"));
+
+    let cli = Cli::parse_from(vec![
+        "rs_script",
+        "build",
+        "demo_script.rs",
+        "--profile",
+        "fast",
+    ]);
+    assert_eq!(cli.cargo_profile().as_deref(), Some("fast"));
+
+    let cli = Cli::parse_from(vec!["rs_script", "demo_script.rs"]);
+    assert!(cli.cargo_profile().is_none());
+
+    assert!(Cli::try_parse_from(vec![
+        "rs_script",
+        "--release",
+        "--profile",
+        "fast",
+        "demo_script.rs"
+    ])
+    .is_err());
+}
+
+#[test]
+fn test_toolchain_and_feature_options() {
+    let args = expand_toolchain_arg(
+        ["rs_script", "+nightly", "-F", "a,b", "demo_script.rs"].map(String::from),
+    );
+    let cli = Cli::parse_from(args);
+    let build_options = cli.build_options();
+    assert_eq!(build_options.toolchain.as_deref(), Some("nightly"));
+    assert_eq!(build_options.features, vec!["a", "b"]);
+    assert!(!build_options.no_default_features);
+
+    // Only a first argument is taken as a toolchain
+    let args = expand_toolchain_arg(["rs_script", "demo_script.rs", "--", "+x"].map(String::from));
+    assert_eq!(args, vec!["rs_script", "demo_script.rs", "--", "+x"]);
+
+    let cli = Cli::parse_from(vec!["rs_script", "--no-default-features", "demo_script.rs"]);
+    assert!(cli.build_options().no_default_features);
+}
+
+#[test]
+fn test_check_and_clippy_commands() {
+    let mut cli = Cli::parse_from(vec!["rs_script", "check", "demo_script.rs"]);
+    cli.apply_command().expect("Error applying subcommand");
+    assert!(cli.check);
+    let proc_flags = get_proc_flags(&cli).expect("Couldn't access ProcFlags");
+    assert!(proc_flags.contains(ProcFlags::CHECK | ProcFlags::NORUN));
+    assert!(!proc_flags.contains(ProcFlags::RUN));
+
+    let mut cli = Cli::parse_from(vec!["rs_script", "clippy", "demo_script.rs"]);
+    cli.apply_command().expect("Error applying subcommand");
+    let proc_flags = get_proc_flags(&cli).expect("Couldn't access ProcFlags");
+    assert!(proc_flags.contains(ProcFlags::CLIPPY | ProcFlags::NORUN));
+    assert!(!proc_flags.contains(ProcFlags::RUN));
+
+    assert!(
+        Cli::try_parse_from(vec!["rs_script", "--check", "--clippy", "demo_script.rs"]).is_err()
+    );
+}
+
+#[test]
+fn test_test_command() {
+    let mut cli = Cli::parse_from(vec![
+        "rs_script",
+        "test",
+        "demo_script.rs",
+        "--",
+        "my_test",
+        "--nocapture",
+    ]);
+    cli.apply_command().expect("Error applying subcommand");
+    assert!(cli.test);
+    assert!(vec!["my_test", "--nocapture"] == cli.args);
+    let proc_flags = get_proc_flags(&cli).expect("Couldn't access ProcFlags");
+    assert!(proc_flags.contains(ProcFlags::TEST | ProcFlags::NORUN));
+    assert!(!proc_flags.contains(ProcFlags::RUN));
+}
+
+#[test]
+fn test_watch_option() {
+    let mut cli = Cli::parse_from(vec!["rs_script", "--watch", "run", "demo_script.rs"]);
+    cli.apply_command().expect("Error applying subcommand");
+    let proc_flags = get_proc_flags(&cli).expect("Couldn't access ProcFlags");
+    assert!(proc_flags.contains(ProcFlags::WATCH | ProcFlags::RUN));
+
+    assert!(Cli::try_parse_from(vec!["rs_script", "-w", "-e", "'2 + 5'"]).is_err());
+}
+
+#[test]
+fn test_export_command() {
+    let mut cli = Cli::parse_from(vec![
+        "rs_script",
+        "export",
+        "demo_script.rs",
+        "--to",
+        "demo",
+    ]);
+    cli.apply_command().expect("Error applying subcommand");
+    assert!(Some("demo_script.rs") == cli.script.as_deref());
+    let proc_flags = get_proc_flags(&cli).expect("Couldn't access ProcFlags");
+    assert!(!proc_flags.contains(ProcFlags::RUN));
+
+    assert!(Cli::try_parse_from(vec!["rs_script", "export", "demo_script.rs"]).is_err());
+}
+
+#[test]
+fn test_import_command() {
+    let cli = Cli::parse_from(vec![
+        "rs_script",
+        "import",
+        "path/to/crate",
+        "--to",
+        "tool.rs",
+    ]);
+    let Some(Commands::Import { project, to }) = cli.command else {
+        panic!("Expected import command");
+    };
+    assert_eq!(project, std::path::PathBuf::from("path/to/crate"));
+    assert_eq!(to, Some(std::path::PathBuf::from("tool.rs")));
+}
+
+#[test]
+fn test_convert_command() {
+    use rs_script::code_utils::ManifestStyle;
+    let cli = Cli::parse_from(vec![
+        "rs_script",
+        "convert",
+        "demo.rs",
+        "--to",
+        "frontmatter",
+    ]);
+    let Some(Commands::Convert { script, to }) = cli.command else {
+        panic!("Expected convert subcommand");
+    };
+    assert_eq!(script, "demo.rs");
+    assert_eq!(to, ManifestStyle::Frontmatter);
+    assert!(Cli::try_parse_from(vec![
+        "rs_script",
+        "convert",
+        "demo.rs",
+        "--to",
+        "doc-comment"
+    ])
+    .is_ok());
+    assert!(Cli::try_parse_from(vec!["rs_script", "convert", "demo.rs", "--to", "yaml"]).is_err());
+    assert!(Cli::try_parse_from(vec!["rs_script", "convert", "demo.rs"]).is_err());
+}
+
+#[test]
+fn test_completions_command() {
+    let cli = Cli::parse_from(vec!["rs_script", "completions", "zsh"]);
+    let Some(Commands::Completions { shell }) = cli.command else {
+        panic!("Expected completions command");
+    };
+    assert_eq!(shell, "zsh");
+    assert!(Cli::try_parse_from(vec!["rs_script", "completions", "tcsh"]).is_err());
+}
+
+#[test]
+fn test_loop_option() {
+    let cli = Cli::parse_from(vec![
+        "rs_script",
+        "-p",
+        "--begin",
+        "let mut n = 0;",
+        "-L",
+        "line.len()",
+        "--end",
+        "n",
+    ]);
+    assert_eq!(cli.loop_expr.as_deref(), Some("line.len()"));
+    assert!(cli.print);
+    assert_eq!(cli.begin.as_deref(), Some("let mut n = 0;"));
+    assert_eq!(cli.end.as_deref(), Some("n"));
+    let proc_flags = get_proc_flags(&cli).unwrap();
+    assert!(proc_flags.contains(ProcFlags::LOOP | ProcFlags::BUILD | ProcFlags::RUN));
+
+    assert!(validate_args(&cli, &proc_flags).is_ok());
+
+    // Printing and BEGIN/END code only apply to --loop
+    let cli = Cli::parse_from(vec!["rs_script", "-p", "demo.rs"]);
+    assert!(validate_args(&cli, &get_proc_flags(&cli).unwrap()).is_err());
+    let cli = Cli::parse_from(vec!["rs_script", "--end", "n", "-e", "1"]);
+    assert!(validate_args(&cli, &get_proc_flags(&cli).unwrap()).is_err());
+    assert!(Cli::try_parse_from(vec!["rs_script", "-L", "line", "-e", "1"]).is_err());
+}
+
+#[test]
+fn test_update_toml_option() {
+    let cli = Cli::parse_from(vec!["rs_script", "--update-toml", "demo.rs"]);
+    let proc_flags = get_proc_flags(&cli).unwrap();
+    assert!(proc_flags.contains(ProcFlags::UPDATE_TOML));
+    assert!(validate_args(&cli, &proc_flags).is_ok());
+
+    // There is no script to update
+    let cli = Cli::parse_from(vec!["rs_script", "--update-toml", "-e", "1"]);
+    assert!(validate_args(&cli, &get_proc_flags(&cli).unwrap()).is_err());
+}
+
+#[test]
+fn test_message_format_option() {
+    use rs_script::logging::MessageFormat;
+
+    let cli = Cli::parse_from(vec!["rs_script", "demo.rs"]);
+    assert_eq!(cli.message_format, MessageFormat::Human);
+    let cli = Cli::parse_from(vec![
+        "rs_script",
+        "build",
+        "demo.rs",
+        "--message-format",
+        "json",
+    ]);
+    assert_eq!(cli.message_format, MessageFormat::Json);
+    assert!(Cli::try_parse_from(vec!["rs_script", "--message-format", "xml", "demo.rs"]).is_err());
+}
+
+#[test]
+fn test_parse_duration() {
+    use rs_script::cmd_args::parse_duration;
+    use std::time::Duration;
+
+    assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+    assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+    assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+    assert_eq!(parse_duration("1.5m"), Ok(Duration::from_secs(90)));
+    assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+    assert!(parse_duration("5x").is_err());
+    assert!(parse_duration("s").is_err());
+    assert!(parse_duration("99999999999999999999d").is_err());
+
+    assert_eq!(parse_duration("30d"), Ok(Duration::from_secs(30 * 86_400)));
+
+    let cli = Cli::parse_from(vec!["rs_script", "run", "demo.rs", "--timeout", "10s"]);
+    assert_eq!(cli.timeout, Some(Duration::from_secs(10)));
+}
+
+#[test]
+fn test_sandbox_options() {
+    let cli = Cli::parse_from(vec![
+        "rs_script",
+        "run",
+        "demo.rs",
+        "--sandbox",
+        "--allow-net",
+    ]);
+    assert!(cli.sandbox);
+    assert!(cli.allow_net);
+    // --allow-net only applies to the sandbox
+    assert!(Cli::try_parse_from(vec!["rs_script", "--allow-net", "demo.rs"]).is_err());
+}
+
+#[test]
+fn test_cache_command() {
+    use rs_script::cmd_args::{parse_size, CacheCommand};
+
+    assert_eq!(parse_size("512"), Ok(512));
+    assert_eq!(parse_size("500M"), Ok(500 << 20));
+    assert_eq!(parse_size("1.5GiB"), Ok(3 << 29));
+    assert_eq!(parse_size("2gb"), Ok(2 << 30));
+    assert!(parse_size("2X").is_err());
+
+    let cli = Cli::parse_from(vec![
+        "rs_script",
+        "cache",
+        "gc",
+        "--orphans",
+        "--max-size",
+        "1G",
+    ]);
+    let Some(Commands::Cache {
+        command:
+            CacheCommand::Gc {
+                older_than,
+                max_size,
+                orphans,
+                dry_run,
+            },
+    }) = cli.command
+    else {
+        panic!("Expected cache gc subcommand");
+    };
+    assert_eq!(older_than, None);
+    assert_eq!(max_size, Some(1 << 30));
+    assert!(orphans);
+    assert!(!dry_run);
+    // gc needs at least one criterion
+    assert!(Cli::try_parse_from(vec!["rs_script", "cache", "gc"]).is_err());
+    assert!(Cli::try_parse_from(vec!["rs_script", "cache", "show", "demo.rs"]).is_ok());
+}
@@ -1,12 +1,19 @@
 #[cfg(test)]
 mod tests {
+    use rs_script::code_utils::extract_build_options;
     use rs_script::code_utils::find_modules_source;
     use rs_script::code_utils::find_use_renames_source;
+    use rs_script::code_utils::hoist_items;
     use rs_script::code_utils::infer_deps_from_ast;
     use rs_script::code_utils::infer_deps_from_source;
+    use rs_script::code_utils::is_last_stmt_unit;
+    use rs_script::code_utils::loop_snippet;
     use rs_script::code_utils::path_to_str;
     use rs_script::code_utils::read_file_contents;
+    use rs_script::code_utils::strip_toml_block;
+    use rs_script::code_utils::uses_unstable_features;
     use rs_script::code_utils::wrap_snippet;
+    use rs_script::extract_ast;
     use rs_script::extract_manifest;
 
     use rs_script::Ast;
@@ -48,6 +55,38 @@ mod tests {
         assert_eq!(deps, vec!["bar", "foo"]);
     }
 
+    #[test]
+    fn test_find_usages() {
+        use rs_script::code_utils::{find_usages_ast, find_usages_source};
+
+        let source_code = r#"
+            use rand::{thread_rng, Rng as _};
+            use serde::Serialize;
+
+            #[derive(Debug, serde::Deserialize, Serialize)]
+            struct Point { x: i32 }
+
+            #[tokio::main]
+            async fn main() {
+                tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            }
+            "#;
+        let ast = Ast::File(syn::parse_file(source_code).unwrap());
+        for usages in [find_usages_ast(&ast), find_usages_source(source_code)] {
+            assert!(usages.attributes.contains("tokio::main"));
+            assert!(!usages.attributes.contains("derive"));
+            for derive in ["Debug", "Deserialize", "Serialize"] {
+                assert!(usages.derives.contains(derive));
+            }
+            assert!(usages.paths.contains("tokio::time::sleep"));
+            assert!(usages.paths.contains("serde::Deserialize"));
+        }
+        // Use trees are flattened into full paths
+        let usages = find_usages_ast(&ast);
+        assert!(usages.paths.contains("rand::thread_rng"));
+        assert!(usages.paths.contains("rand::Rng"));
+    }
+
     #[test]
     fn test_infer_deps_from_source() {
         let source_code = r#"
@@ -60,6 +99,60 @@ mod tests {
         assert_eq!(deps, vec!["bar", "foo"]);
     }
 
+    #[test]
+    fn test_infer_deps_from_qualified_paths() {
+        let source_code = r#"
+            use {anyhow, itertools::Itertools as _};
+            use std::fs;
+            use std::io::{self, Write};
+            use local::helper;
+
+            mod local {
+                pub fn helper() {}
+            }
+
+            #[rustfmt::skip]
+            #[allow(clippy::new_without_default)]
+            struct Wrapper<T> { inner: T }
+
+            impl<T: Default> Wrapper<T> {
+                fn new() -> Self {
+                    Self { inner: T::default() }
+                }
+            }
+
+            fn main() -> anyhow::Result<()> {
+                let re = regex::Regex::new(r"\d+")?;
+                let value: serde_json::Value = serde_json::json!({ "n": u8::MAX });
+                println!("{:?}", chrono::Utc::now());
+                let _ = fs::read_to_string("x.txt");
+                io::stdout().flush()?;
+                let _ = String::new();
+                let _ = Wrapper::<u8>::new();
+                let _ = "1 2".split(' ').map(|s| s.parse::<i32>()).collect::<Vec<_>>();
+                helper();
+                Ok(())
+            }
+            "#;
+        let expected = vec!["anyhow", "chrono", "itertools", "regex", "serde_json"];
+
+        let ast = Ast::File(syn::parse_file(source_code).unwrap());
+        assert_eq!(infer_deps_from_ast(&ast), expected);
+        assert_eq!(infer_deps_from_source(source_code), expected);
+    }
+
+    #[test]
+    fn test_infer_deps_from_macro_args() {
+        // Only the AST version needs to look inside the arguments of macros
+        let ast = Ast::Expr(
+            syn::parse_str::<syn::Expr>(
+                r#"assert_eq!(semver::Version::parse("1.0.0").is_ok(), true)"#,
+            )
+            .unwrap(),
+        );
+        assert_eq!(infer_deps_from_ast(&ast), vec!["semver"]);
+    }
+
     #[test]
     fn test_extract_manifest() {
         let source_code = r#"
@@ -118,4 +211,318 @@ mod tests {
         let modules = find_modules_source(source_code);
         assert_eq!(modules, vec!["foo", "bar"]);
     }
+
+    #[test]
+    fn test_extract_build_options() {
+        let source_code = r#"/*[toml]
+[dependencies]
+serde = "1.0"
+
+[rs-script]
+toolchain = "nightly"
+features = ["simd"]
+no-default-features = true
+*/
+fn main() {}
+"#;
+
+        let build_options = extract_build_options(source_code).unwrap();
+        assert_eq!(build_options.toolchain.as_deref(), Some("nightly"));
+        assert_eq!(build_options.features, vec!["simd"]);
+        assert!(build_options.no_default_features);
+
+        let build_options = extract_build_options("fn main() {}").unwrap();
+        assert_eq!(build_options, Default::default());
+    }
+
+    #[test]
+    fn test_uses_unstable_features() {
+        let source_code = "#![feature(never_type)]\nfn main() {}\n";
+        let ast = Ast::File(syn::parse_file(source_code).unwrap());
+        assert!(uses_unstable_features(Some(&ast), source_code));
+        assert!(uses_unstable_features(None, source_code));
+
+        let source_code = "#[allow(dead_code)]\nfn main() {}\n";
+        let ast = Ast::File(syn::parse_file(source_code).unwrap());
+        assert!(!uses_unstable_features(Some(&ast), source_code));
+        assert!(!uses_unstable_features(None, source_code));
+    }
+
+    #[test]
+    fn test_hoist_items() {
+        let source_code = r#"{
+            macro_rules! three { () => { 3 } }
+            fn double(x: u32) -> u32 { x * 2 }
+            #[test]
+            fn doubles() { assert_eq!(double(2), 4); }
+            println!("{}", double(three!()));
+        }"#;
+        let mut ast = Ast::Expr(syn::parse_str::<syn::Expr>(source_code).unwrap());
+
+        let items = hoist_items(&mut ast);
+        let names: Vec<String> = items
+            .iter()
+            .filter_map(|item| match item {
+                syn::Item::Fn(item_fn) => Some(item_fn.sig.ident.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(names, vec!["double", "doubles"]);
+
+        let Ast::Expr(syn::Expr::Block(expr_block)) = ast else {
+            panic!("Expected a block expression");
+        };
+        // The macro and the statement stay behind
+        assert_eq!(expr_block.block.stmts.len(), 2);
+    }
+
+    #[test]
+    fn test_strip_toml_block() {
+        let source_code = r#"/*[toml]
+[dependencies]
+serde = "1.0"
+*/
+
+fn main() {}
+"#;
+        assert_eq!(strip_toml_block(source_code), "fn main() {}\n");
+        assert_eq!(strip_toml_block("fn main() {}\n"), "fn main() {}\n");
+    }
+
+    #[test]
+    fn test_is_last_stmt_unit() {
+        let is_unit = |source: &str| is_last_stmt_unit(&syn::parse_str(source).unwrap());
+        assert!(is_unit("if x > 1 { y += 1; }"));
+        assert!(is_unit("if x > 1 { y += 1; } else { y -= 1; }"));
+        assert!(!is_unit("if x > 1 { 1 } else { 2 }"));
+        assert!(is_unit(r#"println!("{x}")"#));
+        assert!(is_unit(r#"{ let y = x * 2; println!("{y}") }"#));
+        assert!(!is_unit("{ let y = x * 2; y }"));
+    }
+
+    #[test]
+    fn test_loop_snippet() {
+        let snippet = loop_snippet("line.to_uppercase()", true, None, None);
+        assert!(
+            snippet.contains("for (line_no, line) in std::io::stdin().lock().lines().enumerate()")
+        );
+        assert!(snippet.contains("println!(\"{}\", {\nline.to_uppercase()\n});"));
+
+        // Nothing to print from a unit expression, or without print
+        let snippet = loop_snippet(r#"println!("{line_no}: {line}")"#, true, None, None);
+        assert!(!snippet.contains("println!(\"{}\""));
+        let snippet = loop_snippet("line.len()", false, None, None);
+        assert!(snippet.contains("{\nline.len()\n};"));
+
+        let snippet = loop_snippet(
+            "total += line.len()",
+            false,
+            Some("let mut total = 0;"),
+            Some("total"),
+        );
+        assert!(snippet.starts_with("let mut total = 0;\nfor "));
+        assert!(snippet.ends_with("}\ntotal"));
+        assert!(extract_ast(&snippet).is_ok());
+    }
+
+    #[test]
+    fn test_write_if_changed() {
+        use rs_script::code_utils::write_if_changed;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.rs");
+        assert!(write_if_changed(&path, "fn main() {}\n").unwrap());
+        assert!(!write_if_changed(&path, "fn main() {}\n").unwrap());
+        assert!(write_if_changed(&path, "fn main() { }\n").unwrap());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "fn main() { }\n");
+    }
+
+    #[test]
+    fn test_update_toml_block() {
+        use cargo_toml::{Dependency, DependencyDetail};
+        use rs_script::code_utils::update_toml_block;
+        use std::collections::BTreeMap;
+
+        let mut deps = BTreeMap::new();
+        deps.insert(
+            "regex".to_string(),
+            Dependency::Simple("1.10.5".to_string()),
+        );
+        deps.insert(
+            "serde".to_string(),
+            Dependency::Simple("1.0.204".to_string()),
+        );
+        deps.insert(
+            "hook".to_string(),
+            Dependency::Detailed(Box::new(DependencyDetail {
+                package: Some("signal-hook".to_string()),
+                version: Some("0.3.17".to_string()),
+                ..Default::default()
+            })),
+        );
+
+        // Existing entries, features and patches are kept, and so is everything else
+        let source = r#"/*[toml]
+[dependencies]
+# Derive support
+serde = { version = "1.0", features = ["derive"] }
+
+[patch.crates-io]
+regex = { git = "https://github.com/rust-lang/regex" }
+*/
+use regex::Regex;
+fn main() {   println!("untouched"); }
+"#;
+        let (updated, added) = update_toml_block(source, &deps).unwrap();
+        assert_eq!(added, vec!["hook".to_string(), "regex".to_string()]);
+        assert!(updated.contains(
+            "# Derive support\nserde = { version = \"1.0\", features = [\"derive\"] }\n"
+        ));
+        assert!(updated.contains("regex = \"1.10.5\"\n"));
+        assert!(updated.contains(r#"hook = { package = "signal-hook", version = "0.3.17" }"#));
+        assert!(updated.contains("[patch.crates-io]\nregex = { git"));
+        let rest = "*/\nuse regex::Regex;\nfn main() {   println!(\"untouched\"); }\n";
+        assert!(updated.ends_with(rest));
+        assert!(updated.starts_with("/*[toml]\n"));
+
+        // Nothing changes once all the dependencies are listed
+        let (unchanged, added) = update_toml_block(&updated, &deps).unwrap();
+        assert!(added.is_empty());
+        assert_eq!(unchanged, updated);
+
+        // Scripts without a block get one after any shebang
+        let source = "#!/usr/bin/env rs_script\n#![allow(unused)]\nfn main() {}\n";
+        let (updated, added) = update_toml_block(source, &deps).unwrap();
+        assert_eq!(added.len(), 3);
+        assert!(updated.starts_with("#!/usr/bin/env rs_script\n/*[toml]\n[dependencies]\n"));
+        assert!(updated.ends_with("*/\n\n#![allow(unused)]\nfn main() {}\n"));
+
+        // Front matter keeps its own fences
+        let source = "---\n[dependencies]\nregex = \"1\"\n---\nfn main() {}\n";
+        let (updated, _) = update_toml_block(source, &deps).unwrap();
+        assert!(updated.starts_with("---\n[dependencies]\nregex = \"1\"\n"));
+        assert!(updated.ends_with("\n---\nfn main() {}\n"));
+
+        assert!(update_toml_block("/*[toml]\n[dependencies\n*/", &deps).is_err());
+    }
+
+    const FRONTMATTER_SCRIPT: &str = r#"#!/usr/bin/env rs_script
+---cargo
+[dependencies]
+regex = "1.10"
+---
+
+use regex::Regex;
+fn main() {}
+"#;
+
+    const DOC_COMMENT_SCRIPT: &str = r#"//! Find some words.
+//!
+//! ```cargo
+//! [dependencies]
+//! regex = "1.10"
+//! ```
+
+use regex::Regex;
+fn main() {}
+"#;
+
+    #[test]
+    fn test_find_manifest_block() {
+        use rs_script::code_utils::{find_manifest_block, ManifestStyle};
+
+        let block = find_manifest_block(FRONTMATTER_SCRIPT).unwrap();
+        assert_eq!(block.style, ManifestStyle::Frontmatter);
+        assert_eq!(block.toml, "[dependencies]\nregex = \"1.10\"\n");
+        assert_eq!(
+            &FRONTMATTER_SCRIPT[block.range],
+            "---cargo\n[dependencies]\nregex = \"1.10\"\n---"
+        );
+
+        let block = find_manifest_block(DOC_COMMENT_SCRIPT).unwrap();
+        assert_eq!(block.style, ManifestStyle::DocComment);
+        assert_eq!(block.toml, "[dependencies]\nregex = \"1.10\"\n");
+        assert!(DOC_COMMENT_SCRIPT[block.range].starts_with("//! ```cargo\n"));
+
+        let block = find_manifest_block("/*[toml]\n[dependencies]\n*/\nfn main() {}").unwrap();
+        assert_eq!(block.style, ManifestStyle::Toml);
+        assert_eq!(block.range, 0..26);
+
+        // Front matter must come first, and fences must match
+        assert!(find_manifest_block("fn main() {}\n---\n[dependencies]\n---\n").is_none());
+        assert!(find_manifest_block("----\n[dependencies]\n---\n").is_none());
+        assert!(find_manifest_block("---json\n{}\n---\n").is_none());
+        assert_eq!(
+            find_manifest_block("\n---\n[dependencies]\n---\n")
+                .unwrap()
+                .style,
+            ManifestStyle::Frontmatter
+        );
+    }
+
+    #[test]
+    fn test_extract_manifest_styles() {
+        use rs_script::code_utils::comment_out_frontmatter;
+
+        for source in [FRONTMATTER_SCRIPT, DOC_COMMENT_SCRIPT] {
+            let manifest = extract_manifest(source, Instant::now()).unwrap();
+            assert!(manifest.dependencies.contains_key("regex"));
+        }
+
+        // The front matter becomes a comment on the same lines, and the rest is untouched
+        let commented = comment_out_frontmatter(FRONTMATTER_SCRIPT);
+        assert_eq!(
+            commented,
+            FRONTMATTER_SCRIPT
+                .replace("---cargo", "/*[toml]")
+                .replace("\n---\n", "\n*/\n")
+        );
+        assert_eq!(
+            strip_toml_block(&commented),
+            "#!/usr/bin/env rs_script\n\nuse regex::Regex;\nfn main() {}\n"
+        );
+        assert_eq!(
+            comment_out_frontmatter(DOC_COMMENT_SCRIPT),
+            DOC_COMMENT_SCRIPT
+        );
+
+        // Including leading blank lines, so that diagnostics point at the right lines
+        let source = "---\n\n\n[dependencies]\n---\nfn main() {}\n";
+        assert_eq!(
+            comment_out_frontmatter(source),
+            "/*[toml]\n\n\n[dependencies]\n*/\nfn main() {}\n"
+        );
+        assert_eq!(
+            comment_out_frontmatter("---\n---\nfn main() {}\n"),
+            "/*[toml]\n*/\nfn main() {}\n"
+        );
+    }
+
+    #[test]
+    fn test_convert_manifest_style() {
+        use rs_script::code_utils::{convert_manifest_style, ManifestStyle};
+
+        let toml_script = convert_manifest_style(FRONTMATTER_SCRIPT, ManifestStyle::Toml).unwrap();
+        assert_eq!(
+            toml_script,
+            "#!/usr/bin/env rs_script\n/*[toml]\n[dependencies]\nregex = \"1.10\"\n*/\n\nuse regex::Regex;\nfn main() {}\n"
+        );
+        let doc_script = convert_manifest_style(&toml_script, ManifestStyle::DocComment).unwrap();
+        assert!(doc_script
+            .contains("//! ```cargo\n//! [dependencies]\n//! regex = \"1.10\"\n//! ```\n"));
+        assert_eq!(
+            convert_manifest_style(&doc_script, ManifestStyle::Frontmatter).unwrap(),
+            FRONTMATTER_SCRIPT
+        );
+
+        // Front matter has to go to the top, ahead of the other doc comments
+        let frontmatter_script =
+            convert_manifest_style(DOC_COMMENT_SCRIPT, ManifestStyle::Frontmatter).unwrap();
+        assert_eq!(
+            frontmatter_script,
+            "---cargo\n[dependencies]\nregex = \"1.10\"\n---\n\n//! Find some words.\n//!\n\nuse regex::Regex;\nfn main() {}\n"
+        );
+
+        assert!(convert_manifest_style("fn main() {}", ManifestStyle::Toml).is_err());
+    }
 }
@@ -0,0 +1,46 @@
+#[cfg(test)]
+mod tests {
+    use rs_script::completions::{script_candidates, write_registration};
+    use std::ffi::OsStr;
+    use std::fs;
+
+    #[test]
+    fn test_script_candidates() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("hello.rs"), "").unwrap();
+        fs::write(dir.path().join("notes.txt"), "").unwrap();
+        fs::create_dir(dir.path().join("scripts")).unwrap();
+        let cached_stems = vec!["hello".to_string(), "fib".to_string(), "sum".to_string()];
+
+        let candidates = script_candidates(OsStr::new(""), dir.path(), &cached_stems);
+        let values = candidates
+            .iter()
+            .map(|candidate| candidate.get_value().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        assert!(values.contains(&"hello.rs".to_string()));
+        assert!(values.contains(&"scripts/".to_string()));
+        assert!(values.contains(&"fib.rs".to_string()));
+        assert!(!values.contains(&"notes.txt".to_string()));
+        // A cached script that is also in the directory is only offered once
+        assert_eq!(values.iter().filter(|value| *value == "hello.rs").count(), 1);
+
+        let candidates = script_candidates(OsStr::new("f"), dir.path(), &cached_stems);
+        let values = candidates
+            .iter()
+            .map(|candidate| candidate.get_value().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec!["fib.rs".to_string()]);
+    }
+
+    #[test]
+    fn test_write_registration() {
+        for shell in ["bash", "zsh", "fish"] {
+            let mut buf = Vec::new();
+            write_registration(shell, "/usr/local/bin/rs_script", &mut buf).unwrap();
+            let script = String::from_utf8(buf).unwrap();
+            assert!(script.contains("rs_script"), "{shell}: {script}");
+        }
+        assert!(write_registration("tcsh", "rs_script", &mut Vec::new()).is_err());
+        assert!(write_registration("powershell", "rs_script", &mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+#[cfg(test)]
+mod tests {
+    use rs_script::colors::TermTheme;
+    use rs_script::config::{parse_config, Config};
+    use rs_script::logging::Verbosity;
+    use rs_script::Cli;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_parse_config() {
+        let config = parse_config(
+            r##"
+            verbosity = "verbose"
+            timings = true
+            cache-dir = "/var/cache/rs-script"
+            history-size = 100
+            editor = "code --wait"
+            color-theme = "light"
+            profile = "release"
+            deps-cache-ttl = "12h"
+
+            [[inference]]
+            usage = "#[derive(Queryable)]"
+            crate = "diesel"
+            features = ["sqlite"]
+            "##,
+        )
+        .expect("Error parsing config");
+        assert_eq!(config.verbosity, Some(Verbosity::Verbose));
+        assert_eq!(config.timings, Some(true));
+        assert_eq!(config.multimain, None);
+        assert_eq!(
+            config.cache_dir,
+            Some(PathBuf::from("/var/cache/rs-script"))
+        );
+        assert_eq!(config.history_size, Some(100));
+        assert_eq!(config.editor.as_deref(), Some("code --wait"));
+        assert_eq!(config.color_theme, Some(TermTheme::Light));
+        assert_eq!(
+            config.deps_cache_ttl,
+            Some(std::time::Duration::from_secs(12 * 3600))
+        );
+        assert_eq!(config.profile.as_deref(), Some("release"));
+        assert_eq!(config.inference.len(), 1);
+        assert_eq!(config.inference[0].crate_name, "diesel");
+        assert_eq!(config.inference[0].features, vec!["sqlite".to_string()]);
+    }
+
+    #[test]
+    fn test_parse_config_invalid() {
+        assert!(parse_config(r#"verbosity = "chatty""#).is_err());
+        assert!(parse_config(r#"deps-cache-ttl = "a week""#).is_err());
+        assert!(parse_config(r#"deps-cache-ttl = "99999999999999999999d""#).is_err());
+        assert!(parse_config("[[inference]]\nusage = \"#[derive(\"\ncrate = \"x\"").is_err());
+    }
+
+    #[test]
+    fn test_merge_config() {
+        let user = Config {
+            timings: Some(true),
+            profile: Some("release".to_string()),
+            ..Default::default()
+        };
+        let project = Config {
+            profile: Some("dev".to_string()),
+            multimain: Some(true),
+            ..Default::default()
+        };
+        let merged = user.merge(project);
+        assert_eq!(merged.timings, Some(true));
+        assert_eq!(merged.multimain, Some(true));
+        assert_eq!(merged.profile.as_deref(), Some("dev"));
+
+        let user = parse_config("[sandbox]\ncpu-secs = 10\nprocesses = 32").unwrap();
+        let project = parse_config("[sandbox]\ncpu-secs = 20").unwrap();
+        let merged = user.merge(project);
+        assert_eq!(merged.sandbox.cpu_secs, Some(20));
+        assert_eq!(merged.sandbox.processes, Some(32));
+    }
+
+    #[test]
+    fn test_project_local_config() {
+        let path = PathBuf::from(".rs-script.toml");
+        let user = parse_config("[sandbox]\ncpu-secs = 10").unwrap();
+        let project = parse_config(
+            r##"
+            timings = true
+            profile = "release"
+            cache-dir = "/elsewhere"
+            editor = "vi"
+
+            [[inference]]
+            usage = "use"
+            crate = "has-build-script"
+
+            [sandbox]
+            allow-net = true
+            cpu-secs = 600
+            "##,
+        )
+        .unwrap()
+        .project_local(&path);
+        assert_eq!(project.timings, Some(true));
+        assert_eq!(project.profile.as_deref(), Some("release"));
+        assert_eq!(project.cache_dir, None);
+        assert_eq!(project.editor, None);
+        assert!(project.inference.is_empty());
+        let merged = user.merge(project);
+        assert_eq!(merged.sandbox.cpu_secs, Some(10));
+        assert_eq!(merged.sandbox.allow_net, None);
+    }
+
+    #[test]
+    fn test_apply_to_cli() {
+        let config = Config {
+            verbosity: Some(Verbosity::Quiet),
+            timings: Some(true),
+            shared_target: Some(true),
+            ..Default::default()
+        };
+
+        let mut args = Cli::default();
+        config.apply_to_cli(&mut args);
+        assert!(args.quiet);
+        assert!(args.timings);
+        assert!(args.shared_target);
+
+        // Command-line flags take precedence over the configuration
+        let mut args = Cli {
+            verbose: true,
+            ..Default::default()
+        };
+        config.apply_to_cli(&mut args);
+        assert!(args.verbose);
+        assert!(!args.quiet);
+
+        // So can flags that the configuration turns on
+        let mut args = Cli {
+            no_timings: true,
+            no_shared_target: true,
+            ..Default::default()
+        };
+        config.apply_to_cli(&mut args);
+        assert!(!args.timings);
+        assert!(!args.shared_target);
+    }
+
+    #[test]
+    fn test_apply_profile_to_cli() {
+        let config = Config {
+            profile: Some("fast".to_string()),
+            ..Default::default()
+        };
+
+        let mut args = Cli::default();
+        config.apply_to_cli(&mut args);
+        assert_eq!(args.cargo_profile().as_deref(), Some("fast"));
+
+        // --release takes precedence over the configured profile
+        let mut args = Cli {
+            release: true,
+            ..Default::default()
+        };
+        config.apply_to_cli(&mut args);
+        assert_eq!(args.cargo_profile().as_deref(), Some("release"));
+    }
+}
@@ -0,0 +1,122 @@
+#[cfg(test)]
+mod tests {
+    use rs_script::deps_cache::{cache_key, DepsCache};
+    use rs_script::manifest::MockCommandRunner;
+    use std::process::{ExitStatus, Output};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    const DAY: Duration = Duration::from_secs(86_400);
+
+    fn exit_status(success: bool) -> ExitStatus {
+        #[cfg(unix)]
+        use std::os::unix::process::ExitStatusExt;
+        #[cfg(windows)]
+        use std::os::windows::process::ExitStatusExt;
+        ExitStatus::from_raw(if success { 0 } else { 1 << 8 })
+    }
+
+    /// A command runner whose cargo search prints the given output, or fails if there is none.
+    fn search_runner(stdout: Option<&'static str>) -> MockCommandRunner {
+        let mut runner = MockCommandRunner::new();
+        runner.expect_run_command().returning(move |_, _| {
+            Ok(Output {
+                status: exit_status(stdout.is_some()),
+                stdout: stdout.unwrap_or_default().as_bytes().to_vec(),
+                stderr: vec![],
+            })
+        });
+        runner
+    }
+
+    #[test]
+    fn test_lookup_respects_ttl_and_pins() {
+        let then = UNIX_EPOCH + DAY * 100;
+        let mut deps_cache = DepsCache::default();
+        deps_cache.insert("signal_hook", "signal-hook", "0.3.17", then);
+        deps_cache.insert("regex", "regex", "1.10.5", then);
+        deps_cache
+            .pin(&search_runner(None), "regex", None, then)
+            .unwrap();
+
+        let cached = deps_cache
+            .lookup("signal_hook", DAY, then + DAY / 2)
+            .unwrap();
+        assert_eq!(cached.name, "signal-hook");
+        assert_eq!(cached.version, "0.3.17");
+        // Either spelling finds the crate
+        assert!(deps_cache.lookup("signal-hook", DAY, then).is_some());
+
+        // Expired entries are looked up again, but pinned ones never expire
+        assert!(deps_cache
+            .lookup("signal_hook", DAY, then + DAY * 2)
+            .is_none());
+        let pinned = deps_cache.lookup("regex", DAY, then + DAY * 365).unwrap();
+        assert!(pinned.pinned);
+        assert_eq!(pinned.version, "1.10.5");
+
+        // A search doesn't override a pin
+        deps_cache.insert("regex", "regex", "1.11.0", then + DAY);
+        assert_eq!(deps_cache.crates["regex"].version, "1.10.5");
+    }
+
+    #[test]
+    fn test_pin_and_forget() {
+        let now = SystemTime::now();
+        let mut deps_cache = DepsCache::default();
+        let offline = search_runner(None);
+        assert!(deps_cache.pin(&offline, "serde", None, now).is_err());
+        assert!(deps_cache
+            .pin(&offline, "serde", Some("not a version"), now)
+            .is_err());
+        // A crate that isn't cached must be found to be pinned
+        assert!(deps_cache.pin(&offline, "serde", Some("1.0"), now).is_err());
+        let found = search_runner(Some("serde = \"1.0.203\""));
+        assert_eq!(
+            deps_cache
+                .pin(&found, "serde", Some("1.0"), now)
+                .unwrap()
+                .version,
+            "1.0"
+        );
+
+        // Under its registered name, with its latest version if none is given
+        let found = search_runner(Some("tokio-util = \"0.7.11\""));
+        let pinned = deps_cache.pin(&found, "tokio_util", None, now).unwrap();
+        assert_eq!(pinned.name, "tokio-util");
+        assert_eq!(pinned.version, "0.7.11");
+        let pinned = deps_cache
+            .pin(&offline, "tokio_util", Some("0.7"), now)
+            .unwrap();
+        assert_eq!(pinned.name, "tokio-util");
+        deps_cache.insert("serde_json", "serde_json", "1.0.120", now);
+
+        let forgotten = deps_cache.forget(&["serde-json".to_string(), "nope".to_string()]);
+        assert_eq!(forgotten, vec!["serde-json".to_string()]);
+        assert_eq!(deps_cache.crates.len(), 2);
+        assert_eq!(cache_key("serde-json"), "serde_json");
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache").join("rs_deps_cache.toml");
+        assert_eq!(DepsCache::load(&path).unwrap(), DepsCache::default());
+
+        let mut deps_cache = DepsCache::default();
+        deps_cache.insert("signal_hook", "signal-hook", "0.3.17", SystemTime::now());
+        deps_cache.insert("regex", "regex", "1.10.5", SystemTime::now());
+        deps_cache
+            .pin(
+                &search_runner(None),
+                "regex",
+                Some("1.10"),
+                SystemTime::now(),
+            )
+            .unwrap();
+        deps_cache.save(&path).unwrap();
+        assert_eq!(DepsCache::load(&path).unwrap(), deps_cache);
+
+        std::fs::write(&path, "crates = 1").unwrap();
+        assert!(DepsCache::load(&path).is_err());
+    }
+}
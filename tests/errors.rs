@@ -9,6 +9,7 @@ use toml::de::Error as TomlDeError;
 use toml::ser::Error as TomlSerError;
 
 use rs_script::errors::BuildRunError;
+use rs_script::sandbox::Violation;
 
 #[test]
 fn test_io_error() {
@@ -108,3 +109,37 @@ fn test_cancelled() {
         _ => panic!("Expected BuildRunError::Cancelled variant"),
     }
 }
+
+#[test]
+fn test_script_failure_exit_codes() {
+    assert_eq!(BuildRunError::ScriptExit(3).exit_code(), Some(3));
+    assert_eq!(BuildRunError::ScriptSignal(15).exit_code(), Some(143));
+    let timeout = BuildRunError::Timeout(std::time::Duration::from_millis(1500));
+    assert_eq!(timeout.exit_code(), Some(124));
+    assert_eq!(
+        timeout.to_string(),
+        "Script timed out after 1.5s and was killed"
+    );
+    assert_eq!(
+        BuildRunError::ScriptSignal(2).to_string(),
+        "Script terminated by SIGINT"
+    );
+    assert_eq!(BuildRunError::Cancelled.exit_code(), None);
+}
+
+#[test]
+fn test_sandbox_violation_exit_code() {
+    let violation = BuildRunError::SandboxViolation(Violation::FileSize(64 * 1024 * 1024));
+    assert_eq!(
+        violation.exit_code(),
+        Some(128 + Violation::FileSize(0).signal())
+    );
+    assert_eq!(
+        violation.to_string(),
+        "Script exceeded the sandbox file size limit of 64 MiB and was killed"
+    );
+    assert_eq!(
+        BuildRunError::Sandbox("Landlock is not enabled".to_string()).exit_code(),
+        None
+    );
+}
@@ -0,0 +1,64 @@
+#[cfg(test)]
+mod tests {
+    use rs_script::events::{cargo_message, compiler_messages, Event};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_event_json() {
+        let event = Event::Run {
+            target_path: PathBuf::from("/tmp/rs-script/demo/target/debug/demo"),
+            success: false,
+            exit_status: Some(3),
+            signal: None,
+            timed_out: false,
+            elapsed_ms: 12,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"phase":"run","target_path":"/tmp/rs-script/demo/target/debug/demo","success":false,"exit_status":3,"signal":null,"timed_out":false,"elapsed_ms":12}"#
+        );
+
+        let event = Event::CargoSearch {
+            crate_name: "serde_json".to_string(),
+            found: Some("serde_json".to_string()),
+            version: Some("1.0.120".to_string()),
+            elapsed_ms: 800,
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert!(json.starts_with(r#"{"phase":"cargo-search","crate_name":"serde_json""#));
+
+        let event = Event::TestOutput {
+            line: "test tests::it_works ... ok".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"phase":"test-output","line":"test tests::it_works ... ok"}"#
+        );
+
+        let event = Event::RunOutput {
+            line: "1, 2, Fizz".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"phase":"run-output","line":"1, 2, Fizz"}"#
+        );
+    }
+
+    #[test]
+    fn test_compiler_messages() {
+        let cargo_stdout = r#"{"reason":"compiler-artifact","package_id":"demo 0.0.1"}
+{"reason":"compiler-message","package_id":"demo 0.0.1","message":{"level":"error","message":"mismatched types","rendered":"error[E0308]: mismatched types\n"}}
+{"reason":"build-finished","success":false}
+not json
+"#;
+        let messages = compiler_messages(cargo_stdout);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0]["level"], "error");
+        assert_eq!(messages[0]["message"], "mismatched types");
+
+        // Test harness output is told apart from cargo's messages
+        assert!(cargo_message(r#"{"reason":"build-finished","success":true}"#).is_some());
+        assert!(cargo_message("test tests::it_works ... ok").is_none());
+        assert!(cargo_message(r#"{ "type": "test", "event": "ok" }"#).is_none());
+    }
+}
@@ -0,0 +1,103 @@
+#[cfg(test)]
+mod tests {
+    use cargo_toml::Dependency;
+    use rs_script::knowledge::{
+        applicable_rules, apply_features, required_crates, rules, InferenceRule, Usage, Usages,
+    };
+    use std::collections::BTreeMap;
+
+    fn usages(attributes: &[&str], derives: &[&str], paths: &[&str]) -> Usages {
+        let set = |items: &[&str]| items.iter().map(ToString::to_string).collect();
+        Usages {
+            attributes: set(attributes),
+            derives: set(derives),
+            paths: set(paths),
+        }
+    }
+
+    #[test]
+    fn test_parse_usage() {
+        assert_eq!(
+            "#[tokio::main]".parse::<Usage>().unwrap(),
+            Usage::Attribute("tokio::main".to_string())
+        );
+        assert_eq!(
+            "#[derive(serde::Serialize)]".parse::<Usage>().unwrap(),
+            Usage::Derive("Serialize".to_string())
+        );
+        let path = "rand::thread_rng".parse::<Usage>().unwrap();
+        assert_eq!(path, Usage::Path("rand::thread_rng".to_string()));
+        assert_eq!(path.to_string(), "rand::thread_rng");
+        assert_eq!(
+            Usage::Derive("Parser".to_string()).to_string(),
+            "#[derive(Parser)]"
+        );
+        assert!("#[derive(Serialize]".parse::<Usage>().is_err());
+        assert!("rand:: thread_rng".parse::<Usage>().is_err());
+    }
+
+    #[test]
+    fn test_usages_contains() {
+        let usages = usages(&["tokio::main"], &["Serialize"], &["tokio::time::sleep"]);
+        assert!(usages.contains(&"#[tokio::main]".parse().unwrap()));
+        assert!(usages.contains(&"#[derive(Serialize)]".parse().unwrap()));
+        assert!(usages.contains(&"tokio::time".parse().unwrap()));
+        assert!(usages.contains(&"tokio::time::sleep".parse().unwrap()));
+        // Paths match by whole segments
+        assert!(!usages.contains(&"tokio::ti".parse().unwrap()));
+        assert!(!usages.contains(&"#[tokio::test]".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_applicable_rules() {
+        let usages = usages(
+            &["tokio::main"],
+            &["Serialize", "Parser"],
+            &["tokio::fs::read"],
+        );
+        let is_dependency = |crate_name: &str| crate_name == "serde";
+        let applicable = applicable_rules(&rules(), &usages, is_dependency);
+        let crates: Vec<&str> = applicable
+            .iter()
+            .map(|rule| rule.crate_name.as_str())
+            .collect();
+        // tokio is named by the attribute, serde is a dependency, but a Parser may be pest's
+        assert_eq!(crates, vec!["tokio", "tokio", "serde"]);
+        assert_eq!(required_crates(&applicable, is_dependency), vec!["tokio"]);
+
+        let companion_rule = InferenceRule {
+            usage: "#[serde_as]".parse().unwrap(),
+            crate_name: "serde_with".to_string(),
+            features: vec![],
+            companions: vec!["serde".to_string(), "serde-json".to_string()],
+        };
+        assert_eq!(
+            required_crates(&[companion_rule], is_dependency),
+            vec!["serde_with", "serde_json"]
+        );
+    }
+
+    #[test]
+    fn test_apply_features() {
+        let usages = usages(&["tokio::main"], &["Serialize", "Deserialize"], &[]);
+        let applicable = applicable_rules(&rules(), &usages, |_| true);
+        let mut dependencies = BTreeMap::new();
+        dependencies.insert(
+            "tokio".to_string(),
+            Dependency::Simple("1.38.0".to_string()),
+        );
+        let serde: Dependency = toml::from_str(
+            r#"version = "1.0"
+features = ["rc"]"#,
+        )
+        .unwrap();
+        dependencies.insert("serde".to_string(), serde);
+
+        apply_features(&applicable, &mut dependencies);
+        let tokio = dependencies["tokio"].detail().unwrap();
+        assert_eq!(tokio.version.as_deref(), Some("1.38.0"));
+        assert_eq!(tokio.features, vec!["macros", "rt-multi-thread"]);
+        // Features are added once, after any already there
+        assert_eq!(dependencies["serde"].req_features(), ["rc", "derive"]);
+    }
+}
@@ -3,10 +3,10 @@ mod tests {
     use cargo_toml::{Edition, Manifest};
     use mockall::predicate::*;
     use rs_script::manifest::{
-        capture_dep, cargo_search, default_manifest_from_build_state, merge_manifest,
-        MockCommandRunner,
+        capture_candidates, capture_dep, cargo_search, default_manifest_from_build_state,
+        inferred_dependency, merge_manifest, MockCommandRunner, SEARCH_LIMIT,
     };
-    use rs_script::BuildState;
+    use rs_script::{BuildRunError, BuildState};
     use std::process::Output;
 
     fn init_logger() {
@@ -40,7 +40,7 @@ mod tests {
             "search".to_string(),
             "serde".to_string(),
             "--limit".to_string(),
-            "1".to_string(),
+            SEARCH_LIMIT.to_string(),
         ];
 
         mock_runner
@@ -55,6 +55,74 @@ mod tests {
         assert_eq!(version, "1.0.203");
     }
 
+    #[test]
+    fn test_cargo_search_near_miss() {
+        let output = Output {
+            status: successful_exit_status(),
+            stdout: br#"tokio-util = "0.7.11"    # Additional utilities for working with Tokio.
+tokio-stream = "0.1.15"  # Utilities to work with `Stream` and `tokio`.
+... and 4321 crates more (use --limit N to see more)
+"#
+            .to_vec(),
+            stderr: Vec::new(),
+        };
+
+        let mut mock_runner = MockCommandRunner::new();
+        mock_runner
+            .expect_run_command()
+            .returning(move |_, _| Ok(output.clone()));
+
+        // None of the crates found has the name used in the script, so they are candidates
+        let err = cargo_search(&mock_runner, "tokyo_utils").unwrap_err();
+        let Some(BuildRunError::UnresolvedCrate(crate_name, candidates)) =
+            err.downcast_ref::<BuildRunError>()
+        else {
+            panic!("Expected UnresolvedCrate, got {err:?}");
+        };
+        assert_eq!(crate_name, "tokyo_utils");
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[1].name, "tokio-stream");
+        assert!(err.to_string().contains(
+            r#"tokio-util = "0.7.11"    # Additional utilities for working with Tokio."#
+        ));
+
+        // The underscored name used in the script matches the hyphenated crate
+        let (name, version) = cargo_search(&mock_runner, "tokio_stream").unwrap();
+        assert_eq!(name, "tokio-stream");
+        assert_eq!(version, "0.1.15");
+    }
+
+    #[test]
+    fn test_capture_candidates() {
+        let candidates = capture_candidates(
+            "serde = \"1.0.203\"    # A generic serialization/deserialization framework\nserde_json = \"1.0.120\"\nnot a crate\n",
+        );
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[0].version, "1.0.203");
+        assert_eq!(
+            candidates[0].description,
+            "A generic serialization/deserialization framework"
+        );
+        assert_eq!(candidates[1].name, "serde_json");
+        assert!(candidates[1].description.is_empty());
+    }
+
+    #[test]
+    fn test_inferred_dependency() {
+        use cargo_toml::Dependency;
+
+        let (name, dep) = inferred_dependency("signal_hook", "signal-hook", "0.3.17");
+        assert_eq!(name, "signal-hook");
+        assert_eq!(dep, Dependency::Simple("0.3.17".to_string()));
+
+        // A crate chosen from the candidates is renamed to the name used in the script
+        let (name, dep) = inferred_dependency("tokyo_utils", "tokio-util", "0.7.11");
+        assert_eq!(name, "tokyo_utils");
+        let detail = dep.detail().unwrap();
+        assert_eq!(detail.package.as_deref(), Some("tokio-util"));
+        assert_eq!(detail.version.as_deref(), Some("0.7.11"));
+    }
+
     #[test]
     fn test_capture_dep_valid() {
         let line = r#"serde = "1.0.104""#;
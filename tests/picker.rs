@@ -0,0 +1,72 @@
+#[cfg(test)]
+mod tests {
+    use ratatui::crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+    use rs_script::picker::{picker_action, CrateCandidate, PickerAction};
+
+    fn key(code: KeyCode) -> Event {
+        Event::Key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    #[test]
+    fn test_picker_action() {
+        assert_eq!(
+            picker_action(&key(KeyCode::Down), 0, 3),
+            PickerAction::Select(1)
+        );
+        assert_eq!(
+            picker_action(&key(KeyCode::Char('j')), 2, 3),
+            PickerAction::Select(2)
+        );
+        assert_eq!(
+            picker_action(&key(KeyCode::Up), 0, 3),
+            PickerAction::Select(0)
+        );
+        assert_eq!(
+            picker_action(&key(KeyCode::End), 0, 3),
+            PickerAction::Select(2)
+        );
+        assert_eq!(
+            picker_action(&key(KeyCode::Enter), 1, 3),
+            PickerAction::Choose(1)
+        );
+        assert_eq!(
+            picker_action(&key(KeyCode::Esc), 1, 3),
+            PickerAction::Cancel
+        );
+        assert_eq!(
+            picker_action(
+                &Event::Key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)),
+                1,
+                3
+            ),
+            PickerAction::Cancel
+        );
+        assert_eq!(
+            picker_action(&key(KeyCode::Char('x')), 1, 3),
+            PickerAction::Ignore
+        );
+
+        // Key releases, as reported on Windows, don't count
+        let mut release = KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE);
+        release.kind = KeyEventKind::Release;
+        assert_eq!(
+            picker_action(&Event::Key(release), 1, 3),
+            PickerAction::Ignore
+        );
+    }
+
+    #[test]
+    fn test_crate_candidate_display() {
+        let mut candidate = CrateCandidate {
+            name: "tokio-util".to_string(),
+            version: "0.7.11".to_string(),
+            description: "Additional utilities for working with Tokio.".to_string(),
+        };
+        assert_eq!(
+            candidate.to_string(),
+            r#"tokio-util = "0.7.11"    # Additional utilities for working with Tokio."#
+        );
+        candidate.description.clear();
+        assert_eq!(candidate.to_string(), r#"tokio-util = "0.7.11""#);
+    }
+}
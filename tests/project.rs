@@ -0,0 +1,96 @@
+#[cfg(test)]
+mod tests {
+    use rs_script::extract_manifest;
+    use rs_script::manifest::default_manifest;
+    use rs_script::project::{export_manifest, import_script};
+    use std::fs;
+    use std::time::Instant;
+
+    #[test]
+    fn test_export_manifest() {
+        let generated_manifest = default_manifest("demo", "/tmp/rs-script/demo/demo.rs").unwrap();
+        let script_source = r#"/*[toml]
+[package]
+name = "my-demo"
+version = "1.2.3"
+authors = ["A. Developer"]
+*/
+fn main() {}
+"#;
+
+        let manifest = export_manifest(&generated_manifest, script_source).unwrap();
+        assert!(manifest.bin.is_empty());
+        assert!(manifest.workspace.is_none());
+
+        let package = manifest.package.expect("Missing package");
+        assert_eq!(package.name, "my-demo");
+        assert_eq!(package.version(), "1.2.3");
+        assert_eq!(package.authors(), ["A. Developer"]);
+        // The edition of the generated manifest is kept when the toml block has none
+        assert_eq!(package.edition(), cargo_toml::Edition::E2021);
+    }
+
+    #[test]
+    fn test_export_manifest_without_toml_block() {
+        let generated_manifest = default_manifest("demo", "/tmp/rs-script/demo/demo.rs").unwrap();
+
+        let manifest = export_manifest(&generated_manifest, "fn main() {}").unwrap();
+        assert!(manifest.bin.is_empty());
+        let package = manifest.package.expect("Missing package");
+        assert_eq!(package.name, "demo");
+        assert_eq!(package.version(), "0.0.1");
+    }
+
+    #[test]
+    fn test_import_script() {
+        let project_dir = tempfile::tempdir().unwrap();
+        let project_path = project_dir.path();
+        fs::create_dir_all(project_path.join("src/util")).unwrap();
+        fs::write(
+            project_path.join("Cargo.toml"),
+            r#"[package]
+name = "my-tool"
+version = "0.3.0"
+edition = "2021"
+license = "MIT"
+
+[dependencies]
+regex = { version = "1", default-features = false }
+
+[features]
+loud = []
+"#,
+        )
+        .unwrap();
+        fs::write(
+            project_path.join("src/main.rs"),
+            "mod helpers;\npub mod util;\n\nfn main() {\n    helpers::greet();\n}\n",
+        )
+        .unwrap();
+        fs::write(
+            project_path.join("src/helpers.rs"),
+            "pub fn greet() {\n    println!(\"hello\");\n}\n",
+        )
+        .unwrap();
+        fs::write(project_path.join("src/util/mod.rs"), "pub mod inner;\n").unwrap();
+        fs::write(
+            project_path.join("src/util/inner.rs"),
+            "pub fn shout() {}\n",
+        )
+        .unwrap();
+
+        let (package_name, script) = import_script(project_path).unwrap();
+        assert_eq!(package_name, "my-tool");
+        assert!(script.contains("mod helpers {\npub fn greet() {"));
+        assert!(script.contains("pub mod util {\npub mod inner {\npub fn shout() {}\n}\n}"));
+        assert!(!script.contains("license"));
+
+        // The toml block round-trips
+        let manifest = extract_manifest(&script, Instant::now()).unwrap();
+        assert_eq!(manifest.package.unwrap().name, "my-tool");
+        let regex = &manifest.dependencies["regex"];
+        assert_eq!(regex.req(), "1");
+        assert!(!regex.detail().unwrap().default_features);
+        assert!(manifest.features.contains_key("loud"));
+    }
+}
@@ -0,0 +1,103 @@
+#[cfg(test)]
+mod tests {
+    use rs_script::registry::{index_path, parse_index_cache, resolve_local};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    const REGISTRY: &str = "index.crates.io-1949cf8c6b5b557f";
+
+    /// Write an index cache file for the crate in cargo's format: a header followed by
+    /// null-terminated version and JSON line pairs.
+    fn write_index(cargo_home: &Path, name: &str, versions: &[(&str, bool)]) {
+        let mut contents = b"\x03\x02\0\0\0etag\0".to_vec();
+        for (version, yanked) in versions {
+            contents.extend_from_slice(version.as_bytes());
+            contents.push(0);
+            let line =
+                format!(r#"{{"name":"{name}","vers":"{version}","deps":[],"yanked":{yanked}}}"#);
+            contents.extend_from_slice(line.as_bytes());
+            contents.push(0);
+        }
+        let path = cargo_home
+            .join("registry/index")
+            .join(REGISTRY)
+            .join(".cache")
+            .join(index_path(name).unwrap());
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn write_crate(cargo_home: &Path, name: &str, version: &str) {
+        let dir = cargo_home.join("registry/cache").join(REGISTRY);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(format!("{name}-{version}.crate")), "").unwrap();
+    }
+
+    #[test]
+    fn test_index_path() {
+        assert_eq!(index_path("a"), Some(PathBuf::from("1/a")));
+        assert_eq!(index_path("ab"), Some(PathBuf::from("2/ab")));
+        assert_eq!(index_path("abc"), Some(PathBuf::from("3/a/abc")));
+        assert_eq!(
+            index_path("Serde_json"),
+            Some(PathBuf::from("se/rd/serde_json"))
+        );
+        // Valid Rust identifiers that can't be crates.io names
+        assert_eq!(index_path("café"), None);
+        assert_eq!(index_path("über"), None);
+        assert_eq!(index_path(""), None);
+    }
+
+    #[test]
+    fn test_parse_index_cache() {
+        let contents =
+            b"\x03\x02\0\0\0etag\x001.0.0\0{\"name\":\"x\",\"vers\":\"1.0.0\",\"yanked\":true}\0";
+        let entries = parse_index_cache(contents);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].vers, "1.0.0");
+        assert!(entries[0].yanked);
+        assert!(parse_index_cache(b"garbage").is_empty());
+    }
+
+    #[test]
+    fn test_resolve_local() {
+        let cargo_home = tempfile::tempdir().unwrap();
+        let cargo_home = cargo_home.path();
+
+        // The newest download wins unless it has been yanked, and pre-releases are ignored
+        write_index(
+            cargo_home,
+            "regex",
+            &[
+                ("1.9.0", false),
+                ("1.10.0", false),
+                ("1.11.0", true),
+                ("1.12.0", false),
+            ],
+        );
+        for version in ["1.9.0", "1.10.0", "1.11.0", "2.0.0-alpha.1"] {
+            write_crate(cargo_home, "regex", version);
+        }
+        let found = Some(("regex".to_string(), "1.10.0".to_string()));
+        assert_eq!(resolve_local(cargo_home, "regex", true), found);
+        assert_eq!(resolve_local(cargo_home, "regex", false), found);
+
+        // A version only listed in the index can't be built offline
+        write_index(cargo_home, "itoa", &[("1.0.11", false)]);
+        assert_eq!(resolve_local(cargo_home, "itoa", true), None);
+        assert_eq!(
+            resolve_local(cargo_home, "itoa", false),
+            Some(("itoa".to_string(), "1.0.11".to_string()))
+        );
+
+        // Scripts refer to hyphenated crates with underscores
+        write_crate(cargo_home, "signal-hook", "0.3.17");
+        assert_eq!(
+            resolve_local(cargo_home, "signal_hook", true),
+            Some(("signal-hook".to_string(), "0.3.17".to_string()))
+        );
+
+        assert_eq!(resolve_local(cargo_home, "no_such_crate", false), None);
+        assert_eq!(resolve_local(cargo_home, "café", false), None);
+    }
+}
@@ -0,0 +1,79 @@
+#[cfg(test)]
+mod tests {
+    use rs_script::config::{parse_config, SandboxConfig};
+    use rs_script::sandbox::{Sandbox, Violation, DEFAULT_CPU_SECS, DEFAULT_PROCESSES};
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    const MIB: u64 = 1024 * 1024;
+
+    #[test]
+    fn test_sandbox_limits() {
+        let scratch_dir = PathBuf::from("/tmp/rs-script/demo/scratch");
+        let sandbox = Sandbox::new(&SandboxConfig::default(), false, scratch_dir.clone())
+            .expect("Error creating sandbox");
+        assert_eq!(sandbox.cpu_time, Duration::from_secs(DEFAULT_CPU_SECS));
+        assert_eq!(sandbox.processes, DEFAULT_PROCESSES);
+        assert!(!sandbox.allow_net);
+        assert_eq!(sandbox.scratch_dir, scratch_dir);
+
+        let config = parse_config(
+            r#"
+            [sandbox]
+            cpu-secs = 5
+            memory-mb = 256
+            allow-net = true
+            "#,
+        )
+        .expect("Error parsing config");
+        let sandbox =
+            Sandbox::new(&config.sandbox, false, scratch_dir).expect("Error creating sandbox");
+        assert_eq!(sandbox.cpu_time, Duration::from_secs(5));
+        assert_eq!(sandbox.memory, 256 * MIB);
+        assert!(sandbox.allow_net);
+    }
+
+    #[test]
+    fn test_sandbox_limit_too_large() {
+        let config = SandboxConfig {
+            memory_mb: Some(u64::MAX),
+            ..Default::default()
+        };
+        assert!(Sandbox::new(&config, false, PathBuf::from("scratch")).is_err());
+    }
+
+    #[test]
+    fn test_sandbox_violation() {
+        let config = SandboxConfig {
+            cpu_secs: Some(2),
+            ..Default::default()
+        };
+        let sandbox =
+            Sandbox::new(&config, false, PathBuf::from("scratch")).expect("Error creating sandbox");
+        let cpu_time = Violation::CpuTime(Duration::from_secs(2));
+        assert_eq!(
+            sandbox.violation(cpu_time.signal(), b""),
+            Some(cpu_time.clone())
+        );
+        assert_eq!(
+            cpu_time.to_string(),
+            "Script exceeded the sandbox CPU time limit of 2s and was killed"
+        );
+        // SIGTERM is not a sandbox violation
+        assert_eq!(sandbox.violation(15, b""), None);
+
+        // Nor is an abort, unless the script reported an allocation failure
+        let memory = Violation::Memory(sandbox.memory);
+        assert_eq!(
+            sandbox.violation(memory.signal(), b"panicked at main.rs"),
+            None
+        );
+        assert_eq!(
+            sandbox.violation(
+                memory.signal(),
+                b"memory allocation of 2147483648 bytes failed\n"
+            ),
+            Some(memory)
+        );
+    }
+}
@@ -7,7 +7,8 @@ use std::time::Instant;
 
 use rs_script::cmd_args::{Cli, ProcFlags};
 use rs_script::shared::{
-    debug_timings, display_timings, escape_path_for_windows, Ast, BuildState, ScriptState,
+    debug_timings, display_timings, escape_path_for_windows, Ast, BuildOptions, BuildState,
+    ScriptState,
 };
 
 #[test]
@@ -240,3 +241,167 @@ fn test_escape_path_for_windows() {
         assert_eq!(escaped_path, path);
     }
 }
+
+#[test]
+fn test_build_options_with_defaults() {
+    let cli_options = BuildOptions {
+        features: vec!["a".to_string()],
+        ..Default::default()
+    };
+    let script_options = BuildOptions {
+        toolchain: Some("1.74.1".to_string()),
+        features: vec!["a".to_string(), "b".to_string()],
+        no_default_features: true,
+    };
+    let build_options = cli_options.with_defaults(script_options);
+    assert_eq!(build_options.toolchain.as_deref(), Some("1.74.1"));
+    assert_eq!(build_options.features, vec!["a", "b"]);
+    assert!(build_options.no_default_features);
+}
+
+#[test]
+fn test_build_options_for_unstable_features() {
+    let build_options = BuildOptions::default().for_unstable_features().unwrap();
+    assert_eq!(build_options.toolchain.as_deref(), Some("nightly"));
+
+    let build_options = BuildOptions {
+        toolchain: Some("stable".to_string()),
+        ..Default::default()
+    };
+    assert!(build_options.for_unstable_features().is_err());
+}
+
+#[test]
+fn test_fingerprint() {
+    use rs_script::shared::{content_hash, module_files};
+    use std::fs;
+
+    let dir = tempfile::tempdir().unwrap();
+    let source_path = dir.path().join("fp.rs");
+    let source = "mod helper;\nmod missing;\nfn main() { helper::hi(); }\n";
+    fs::write(&source_path, source).unwrap();
+    fs::write(dir.path().join("helper.rs"), "pub fn hi() {}\n").unwrap();
+    assert_eq!(
+        module_files(&source_path, source),
+        vec![dir.path().join("helper.rs")]
+    );
+
+    let build_state = BuildState {
+        source_path: source_path.clone(),
+        cargo_toml_path: dir.path().join("Cargo.toml"),
+        target_path: dir.path().join("fp"),
+        ..Default::default()
+    };
+    let fingerprint = build_state.fingerprint(&BuildOptions::default(), None);
+    assert_eq!(fingerprint.source, content_hash(source.as_bytes()));
+    assert_eq!(fingerprint.modules.len(), 1);
+    assert!(fingerprint.changes_from(&fingerprint).is_empty());
+
+    // Touching a file doesn't change the fingerprint, but changing a module does
+    fs::write(&source_path, source).unwrap();
+    assert_eq!(
+        build_state.fingerprint(&BuildOptions::default(), None),
+        fingerprint
+    );
+    fs::write(dir.path().join("helper.rs"), "pub fn hi() { }\n").unwrap();
+    let requested = BuildOptions {
+        features: vec!["fast".to_string()],
+        ..Default::default()
+    };
+    assert_eq!(
+        build_state
+            .fingerprint(&requested, None)
+            .changes_from(&fingerprint),
+        vec!["toolchain or feature selection", "script's module files"]
+    );
+
+    // The toolchain that builds the script is recorded, whichever way it was chosen
+    let fingerprint = build_state.fingerprint(&BuildOptions::default(), Some("nightly"));
+    assert_eq!(fingerprint.toolchain.as_deref(), Some("nightly"));
+
+    // rustc is only asked for its version once per toolchain
+    build_state
+        .rustc_versions
+        .borrow_mut()
+        .insert(None, "rustc 0.0.0".to_string());
+    assert_eq!(
+        build_state
+            .fingerprint(&BuildOptions::default(), None)
+            .rustc_version,
+        "rustc 0.0.0"
+    );
+    assert_eq!(
+        build_state
+            .fingerprint(&BuildOptions::default(), Some("nightly"))
+            .rustc_version,
+        fingerprint.rustc_version
+    );
+}
+
+#[test]
+fn test_content_hash_is_stable() {
+    use rs_script::shared::content_hash;
+
+    assert_eq!(content_hash(b"fn main() {}"), content_hash(b"fn main() {}"));
+    assert_ne!(
+        content_hash(b"fn main() {}"),
+        content_hash(b"fn main() { }")
+    );
+    assert_eq!(content_hash(b"").len(), 16);
+}
+
+#[test]
+fn test_script_dir_name() {
+    use rs_script::shared::{script_dir_name, script_target_dir};
+    use std::fs;
+
+    let dir = tempfile::tempdir().unwrap();
+    for subdir in ["a", "b"] {
+        fs::create_dir_all(dir.path().join(subdir)).unwrap();
+        fs::write(dir.path().join(subdir).join("main_demo.rs"), "fn main() {}").unwrap();
+    }
+    let a = dir.path().join("a/main_demo.rs");
+    let b = dir.path().join("b/main_demo.rs");
+    let a_name = script_dir_name(&a);
+    assert!(a_name.starts_with("main_demo-"));
+    assert_eq!(a_name.len(), "main_demo-".len() + 8);
+    assert_ne!(a_name, script_dir_name(&b));
+    // The same script reached by another path has the same directory
+    assert_eq!(
+        script_dir_name(&dir.path().join("b/../a/main_demo.rs")),
+        a_name
+    );
+    assert!(script_target_dir(&a).ends_with(&a_name));
+}
+
+#[test]
+fn test_shared_target_bin_name() {
+    use std::path::PathBuf;
+
+    let build_state = |target_dir: &str, shared: bool| BuildState {
+        source_stem: "main_demo".to_string(),
+        target_dir_path: PathBuf::from(target_dir),
+        target_path: PathBuf::from(target_dir).join("target/debug/main_demo"),
+        cargo_profile: Some("release".to_string()),
+        shared_target_dir: shared.then(|| PathBuf::from("/tmp/rs_target")),
+        ..Default::default()
+    };
+
+    // Without a shared target directory the binary is named after the script
+    let own = build_state("/tmp/rs-script/main_demo-1a2b3c4d", false);
+    assert_eq!(own.bin_name(), "main_demo");
+    assert_eq!(own.shared_target_binary(), None);
+
+    // In a shared one, scripts of the same name must not overwrite each other's executables
+    let a = build_state("/tmp/rs-script/main_demo-1a2b3c4d", true);
+    let b = build_state("/tmp/rs-script/main_demo-5e6f7a8b", true);
+    assert!(a.bin_name().starts_with("main_demo-"));
+    assert_ne!(a.bin_name(), b.bin_name());
+    let binary = a.shared_target_binary().unwrap();
+    assert!(binary.starts_with("/tmp/rs_target/release"));
+    assert!(binary
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .starts_with(&a.bin_name()));
+}